@@ -34,7 +34,7 @@ fn main() {
         .run(server.for_each(|c| {
             let handle = handle.clone();
 
-            println!("New connection from: {}", c.peer_addr());
+            println!("New connection from: {:?}", c.peer_addr());
 
             handle.clone().spawn(
                 c.for_each(move |s| {
@@ -91,6 +91,8 @@ fn main() {
         .clang_arg("-DNULL=0")
         .header("src/picotls/include/picotls.h")
         .header("src/picoquic/picoquic/picoquic.h")
+        .header("src/picoquic/picoquic/picoquic_internal.h")
+        .header("src/picoquic/picoquic/tls_api.h")
         .header("src/picoquic/picoquic/util.h")
         .generate()
         .expect("Unable to generate picoquic bindings");
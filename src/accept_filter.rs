@@ -0,0 +1,48 @@
+use std::net::SocketAddr;
+
+/// The information about an incoming `Connection`, that is given to the `AcceptFilter`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct IncomingInfo {
+    /// The server name (SNI) requested by the client.
+    pub server_name: Option<String>,
+    /// The ALPN protocols offered by the client.
+    /// Protocols that are not valid UTF-8 are given as empty strings.
+    pub alpn: Vec<String>,
+    /// The address of the client.
+    pub peer_addr: SocketAddr,
+    /// Did the client prove its address with the token of a stateless retry?
+    pub address_validated: bool,
+}
+
+/// The decision of the `AcceptFilter` about an incoming `Connection`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum AcceptDecision {
+    /// The handshake is continued.
+    Accept,
+    /// The handshake is aborted with the given TLS alert (e.g. `40` for `handshake_failure`),
+    /// that is sent to the client as `CRYPTO_ERROR`.
+    Reject(u8),
+    /// The client is asked to validate its address with a stateless retry, before the handshake
+    /// is continued. When the client comes back with a valid token, the filter is called again
+    /// with `address_validated` set, then `Retry` continues the handshake like `Accept`.
+    Retry,
+}
+
+/// The `AcceptFilter` trait is used by a server to decide, if an incoming `Connection` should
+/// be accepted.
+/// The filter is called, when the `ClientHello` of the client is processed. At this point the
+/// `Connection` was not returned by the `Context` yet, so a rejected `Connection` never reaches
+/// the application.
+pub trait AcceptFilter {
+    /// Will be called with the information about each incoming `Connection`.
+    fn filter(&mut self, info: &IncomingInfo) -> AcceptDecision;
+}
+
+impl<F> AcceptFilter for F
+where
+    F: FnMut(&IncomingInfo) -> AcceptDecision,
+{
+    fn filter(&mut self, info: &IncomingInfo) -> AcceptDecision {
+        self(info)
+    }
+}
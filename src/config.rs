@@ -1,4 +1,7 @@
-use super::VerifyCertificate;
+use super::{
+    AcceptFilter, CipherSuite, EarlyDataPolicy, EventSink, GenerateConnectionId, KeyExchangeGroup,
+    Keylog, PeerAddressChange, SelectAlpn, VerifyCertificate,
+};
 use picoquic_sys::picoquic::PICOQUIC_RESET_SECRET_SIZE;
 
 use std::path::PathBuf;
@@ -20,6 +23,30 @@ pub enum FileFormat {
     DER,
 }
 
+/// What happens, if a connection buffers more received data than allowed by
+/// `Config::set_max_recv_buffer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// The flow control of all `Stream`s is paused, until the application read enough data. The
+    /// peer can still send the data up to the current flow control limits and the initial
+    /// limits of new `Stream`s.
+    Backpressure,
+    /// The connection is closed with the transport error `FLOW_CONTROL_ERROR` and the `Stream`s
+    /// return `ErrorKind::RecvBufferOverflow`.
+    Close,
+}
+
+/// What happens to new incoming connections that exceed the rate set by
+/// `Config::set_new_connection_rate_limit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitPolicy {
+    /// The `Initial` packets of the connections are dropped.
+    Drop,
+    /// The clients are asked with a stateless retry to validate their address. Clients that
+    /// return with a valid token are accepted, clients with spoofed addresses never return.
+    Retry,
+}
+
 /// Configuration used by `Context` to setup Picoquic.
 pub struct Config {
     /// The path to the certificate chain(PEM format).
@@ -48,6 +75,107 @@ pub struct Config {
     pub client_authentication: bool,
     /// The handler that should verify the peer certificate in the TLS handshake.
     pub verify_certificate_handler: Option<Box<VerifyCertificate>>,
+    /// The ALPN protocol that is offered by a client or selected by default by a server.
+    pub alpn: Option<String>,
+    /// The handler that should select the ALPN protocol on the server.
+    /// If the handler is set, `alpn` is ignored on the server.
+    pub alpn_select_handler: Option<Box<SelectAlpn>>,
+    /// The length of the connection ids that are generated for local connections.
+    /// Valid lengths are between `1` and `20` bytes.
+    pub local_connection_id_length: Option<u8>,
+    /// The handler that should generate the local connection ids.
+    pub connection_id_generator: Option<Box<GenerateConnectionId>>,
+    /// Advertises the `disable_migration` transport parameter to the peer.
+    /// Default: false
+    pub disable_migration: bool,
+    /// Advertises the `grease_quic_bit` transport parameter to the peer and varies the QUIC bit,
+    /// if the peer advertises it as well.
+    /// Default: false
+    pub grease_quic_bit: bool,
+    /// The key that is used by the server to encrypt and decrypt the session tickets.
+    pub ticket_encryption_key: Option<Vec<u8>>,
+    /// The path to the file that stores the session tickets received by a client.
+    pub ticket_store_filename: Option<PathBuf>,
+    /// The initial round trip time that is assumed for outgoing connections, until the first
+    /// round trip time is measured. If not set, the default of picoquic is used.
+    pub initial_rtt: Option<Duration>,
+    /// The number of connection ids of the peer that are stored per `Connection`
+    /// (`active_connection_id_limit` transport parameter). Needs to be at least `2`.
+    pub active_connection_id_limit: Option<u32>,
+    /// The handler that consumes the `QuicEvent`s of all `Connection`s.
+    pub event_sink: Option<Box<EventSink>>,
+    /// Enables the heartbeats that are sent with `Connection::send_heartbeat`.
+    /// Default: false
+    pub heartbeat: bool,
+    /// The handler that is called, when the address of the peer of a `Connection` changed.
+    pub peer_address_change_handler: Option<Box<PeerAddressChange>>,
+    /// The handler that consumes the TLS secrets in the NSS key log format.
+    pub keylog_handler: Option<Box<Keylog>>,
+    /// The path to the file that stores the TLS secrets in the NSS key log format.
+    /// If `keylog_handler` is set, the file is not used.
+    pub keylog_filename: Option<PathBuf>,
+    /// The filter that decides, if an incoming `Connection` is accepted.
+    pub accept_filter: Option<Box<AcceptFilter>>,
+    /// The policy for the 0-RTT data of resumed sessions. If not set, picoquic accepts all
+    /// 0-RTT data.
+    pub early_data_policy: Option<EarlyDataPolicy>,
+    /// Enables the counting of the sent and received frames per frame type.
+    /// Default: false
+    pub frame_stats: bool,
+    /// The maximum time an acknowledgement is delayed (`max_ack_delay` transport parameter).
+    /// Needs to be less than `2^14` milliseconds. If not set, the default of picoquic is used.
+    pub max_ack_delay: Option<Duration>,
+    /// The minimum ack delay that is advertised to the peer (`min_ack_delay` transport
+    /// parameter). Advertising it enables the ack frequency extension.
+    pub min_ack_delay: Option<Duration>,
+    /// The exponent that scales the ack delay in the sent acknowledgements
+    /// (`ack_delay_exponent` transport parameter). Must not be greater than `20`.
+    /// If not set, the default of picoquic is used.
+    pub ack_delay_exponent: Option<u8>,
+    /// The maximum number of ack ranges in a sent acknowledgement.
+    /// If not set, the default of picoquic is used.
+    pub max_ack_ranges: Option<u32>,
+    /// The time after which the handshake of an outgoing `Connection` is aborted.
+    pub handshake_timeout: Option<Duration>,
+    /// The padding policy of the packets that are not `Initial` packets, given as the minimum
+    /// size and the multiple the packets are padded to. If not set, the default of picoquic is
+    /// used.
+    pub padding_policy: Option<(u32, u32)>,
+    /// The maximum size of the packets that are sent in the handshake.
+    /// If not set, the default of picoquic is used.
+    pub max_handshake_packet_size: Option<u32>,
+    /// The sizes of the kernel send and receive buffers of the UDP sockets in bytes.
+    /// If not set, the default of the operating system is used.
+    pub socket_buffer_sizes: Option<(usize, usize)>,
+    /// Sets the don't fragment bit on all packets sent by the UDP sockets.
+    /// Default: false
+    pub dont_fragment: bool,
+    /// Reports the ECN codepoints of the received packets per `Connection`.
+    /// Default: false
+    pub ecn_marks: bool,
+    /// The name of the network interface the UDP sockets are bound to.
+    /// If not set, the interface is selected by the routing table.
+    pub bind_device: Option<String>,
+    /// The expected number of `Connection`s and the expected number of `Stream`s per
+    /// `Connection`, that are open at the same time.
+    pub capacity_hints: Option<(usize, usize)>,
+    /// The custom transport parameters that are send to the peers, as id and value.
+    pub custom_transport_parameters: Vec<(u64, Vec<u8>)>,
+    /// The maximum number of received bytes per connection, that were not read yet by the
+    /// application, and what happens if a connection exceeds this limit.
+    pub max_recv_buffer: Option<(usize, OverflowPolicy)>,
+    /// The TLS cipher suites that may be negotiated. If not set, all cipher suites supported by
+    /// picotls are used.
+    pub cipher_suites: Option<Vec<CipherSuite>>,
+    /// The groups that may be used for the key exchange in the TLS handshake. If not set, all
+    /// groups supported by picotls are used.
+    pub key_exchange_groups: Option<Vec<KeyExchangeGroup>>,
+    /// The maximum number of new incoming connections per second and what happens to the
+    /// connections that exceed this rate.
+    pub new_connection_rate_limit: Option<(u32, RateLimitPolicy)>,
+    /// The maximum number of session tickets that are kept in the ticket store.
+    /// If not set, the number of tickets is not limited.
+    pub ticket_store_capacity: Option<usize>,
 }
 
 impl Config {
@@ -57,7 +185,10 @@ impl Config {
     }
 
     /// Will create a new instance by cloning another `Config`.
-    /// The `verify_certificate_handler` will be set to `None` as it does not support to be cloned.
+    /// The `verify_certificate_handler`, `alpn_select_handler`, `connection_id_generator`,
+    /// `event_sink`, `peer_address_change_handler`, `keylog_handler` and `accept_filter` will be
+    /// set to `None` as they do not support to be cloned. An `early_data_policy` with a callback
+    /// is set to `None` for the same reason.
     pub fn clone_from(other: &Config) -> Config {
         Config {
             certificate_chain_filename: other.certificate_chain_filename.clone(),
@@ -71,6 +202,46 @@ impl Config {
             keep_alive_sender: other.keep_alive_sender,
             client_authentication: other.client_authentication,
             verify_certificate_handler: None,
+            alpn: other.alpn.clone(),
+            alpn_select_handler: None,
+            local_connection_id_length: other.local_connection_id_length,
+            connection_id_generator: None,
+            disable_migration: other.disable_migration,
+            grease_quic_bit: other.grease_quic_bit,
+            ticket_encryption_key: other.ticket_encryption_key.clone(),
+            ticket_store_filename: other.ticket_store_filename.clone(),
+            initial_rtt: other.initial_rtt,
+            active_connection_id_limit: other.active_connection_id_limit,
+            event_sink: None,
+            heartbeat: other.heartbeat,
+            peer_address_change_handler: None,
+            keylog_handler: None,
+            keylog_filename: other.keylog_filename.clone(),
+            accept_filter: None,
+            early_data_policy: match other.early_data_policy {
+                Some(EarlyDataPolicy::Accept) => Some(EarlyDataPolicy::Accept),
+                Some(EarlyDataPolicy::Reject) => Some(EarlyDataPolicy::Reject),
+                Some(EarlyDataPolicy::Callback(_)) | None => None,
+            },
+            frame_stats: other.frame_stats,
+            max_ack_delay: other.max_ack_delay,
+            min_ack_delay: other.min_ack_delay,
+            ack_delay_exponent: other.ack_delay_exponent,
+            max_ack_ranges: other.max_ack_ranges,
+            handshake_timeout: other.handshake_timeout,
+            padding_policy: other.padding_policy,
+            max_handshake_packet_size: other.max_handshake_packet_size,
+            socket_buffer_sizes: other.socket_buffer_sizes,
+            dont_fragment: other.dont_fragment,
+            ecn_marks: other.ecn_marks,
+            bind_device: other.bind_device.clone(),
+            capacity_hints: other.capacity_hints,
+            custom_transport_parameters: other.custom_transport_parameters.clone(),
+            max_recv_buffer: other.max_recv_buffer,
+            cipher_suites: other.cipher_suites.clone(),
+            key_exchange_groups: other.key_exchange_groups.clone(),
+            new_connection_rate_limit: other.new_connection_rate_limit,
+            ticket_store_capacity: other.ticket_store_capacity,
         }
     }
 
@@ -89,6 +260,24 @@ impl Config {
         self.keep_alive_interval = Some(dur);
     }
 
+    /// Sets the time after which the handshake of an outgoing `Connection` is aborted.
+    /// If the handshake did not finish in time (e.g. because the server is not reachable), the
+    /// `Connection` is closed and creating it fails with `ErrorKind::HandshakeTimeout`. This
+    /// is independent of the idle timeout, which can be much longer.
+    /// The timeout is checked each time the `Connection` wakes up, e.g. to retransmit packets.
+    /// So, the `Connection` may be aborted slightly after the timeout elapsed.
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = Some(timeout);
+    }
+
+    /// Enables the application level heartbeats.
+    /// A heartbeat that is sent with `Connection::send_heartbeat`, is reported as
+    /// `Event::Heartbeat` to the peer. The heartbeats are sent on a reserved unidirectional
+    /// stream, so the peer needs to enable the heartbeats as well.
+    pub fn enable_heartbeat(&mut self) {
+        self.heartbeat = true;
+    }
+
     /// Sets the sender for the keep alive messages.
     /// The default value is `Role::Client`. This value should be the same on the server and the
     /// client, otherwise both send continuously useless messages.
@@ -96,16 +285,359 @@ impl Config {
         self.keep_alive_sender = role;
     }
 
-    /// Enables TLS client authentication on the server.
+    /// Enables TLS client authentication on the server (mutual TLS).
+    /// The server requests a certificate from each client and the handshake fails, if the
+    /// client certificate can not be verified with the root certificates or with the verify
+    /// certificate handler.
+    /// A client presents the certificate chain and the private key that are set with
+    /// `set_certificate_chain(_filename)` and `set_private_key(_filename)`.
+    /// The certificates of a client are available at the server with
+    /// `Connection::peer_certificates`.
     pub fn enable_client_authentication(&mut self) {
         self.client_authentication = true;
     }
 
+    /// Sets the ALPN protocol.
+    /// A client will offer this protocol to the server and a server will select this protocol,
+    /// if the client offers it.
+    pub fn set_alpn<T: Into<String>>(&mut self, alpn: T) {
+        self.alpn = Some(alpn.into());
+    }
+
+    /// Sets the handler that should select the ALPN protocol on the server.
+    /// The handler is called with the protocols offered by the client.
+    pub fn set_alpn_select_handler<H: SelectAlpn + 'static>(&mut self, handler: H) {
+        self.alpn_select_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the length of the connection ids that are generated for local connections.
+    /// Load balancers that route by connection id, normally require a fixed length.
+    /// The length must be between `1` and `20` bytes, otherwise creating the `Context` fails.
+    pub fn set_local_connection_id_length(&mut self, len: u8) {
+        self.local_connection_id_length = Some(len);
+    }
+
+    /// Sets the handler that should generate the local connection ids.
+    /// This can be used to encode routing information for a load balancer in the connection ids.
+    pub fn set_connection_id_generator<H: GenerateConnectionId + 'static>(&mut self, handler: H) {
+        self.connection_id_generator = Some(Box::new(handler));
+    }
+
+    /// Disables active connection migration.
+    /// The peers of a server with this option will be informed, that they are not allowed to
+    /// migrate the connection to a new path. This is required for servers behind load balancers,
+    /// which route packets by the address of the peer.
+    pub fn disable_migration(&mut self) {
+        self.disable_migration = true;
+    }
+
+    /// Enables greasing of the QUIC bit.
+    /// The `grease_quic_bit` transport parameter tells the peer, that it is allowed to vary the
+    /// fixed QUIC bit in the packet headers. If the peer advertises the parameter as well, the
+    /// QUIC bit of the packets sent to it is greased.
+    pub fn enable_grease_quic_bit(&mut self) {
+        self.grease_quic_bit = true;
+    }
+
+    /// Sets the key that is used by the server to encrypt and decrypt the session tickets.
+    /// Session tickets enable clients to resume a session (PSK) and to send 0-RTT data.
+    ///
+    /// If no key is set, picoquic chooses a random key and tickets issued by this `Context` can
+    /// not be validated by another `Context`. So, all servers that should accept the same tickets
+    /// (e.g. servers behind a load balancer or a restarted server) need to use the same key.
+    /// As the tickets are stateless, the server does not need to cache any session.
+    ///
+    /// A compromised key breaks the forward secrecy of all sessions that were resumed with
+    /// tickets encrypted by this key. The key should therefore be rotated regularly, by creating
+    /// a new `Context` with a new key. Tickets issued with the old key are not accepted anymore,
+    /// the clients fall back to a full handshake.
+    pub fn set_ticket_encryption_key(&mut self, key: Vec<u8>) {
+        self.ticket_encryption_key = Some(key);
+    }
+
+    /// Sets the file that stores the session tickets received by a client.
+    /// The tickets are loaded from the file when the `Context` is created and stored to it, when
+    /// the `Context` is dropped. This makes it possible to resume sessions after a restart of the
+    /// client.
+    pub fn set_ticket_store_filename<P: Into<PathBuf>>(&mut self, path: P) {
+        self.ticket_store_filename = Some(path.into())
+    }
+
+    /// Sets the maximum number of session tickets that are kept in the ticket store.
+    /// A client stores a ticket for each server it connected to, so a client that connects to
+    /// many servers would grow the store without bounds. If the store holds more tickets, the
+    /// oldest tickets are dropped and the sessions of these tickets can not be resumed anymore.
+    /// The capacity must not be `0`, otherwise creating the `Context` fails.
+    pub fn set_ticket_store_capacity(&mut self, tickets: usize) {
+        self.ticket_store_capacity = Some(tickets);
+    }
+
+    /// Sets the initial round trip time for outgoing connections.
+    /// The retransmission timeout of the first packets is derived from this value. For peers
+    /// that are known to be far away (e.g. over satellite links), the default guess of picoquic
+    /// leads to spurious retransmissions.
+    /// The value only affects the behavior until the first round trip time is measured.
+    pub fn set_initial_rtt(&mut self, rtt: Duration) {
+        self.initial_rtt = Some(rtt);
+    }
+
+    /// Sets the number of connection ids that are accepted from the peer per `Connection`.
+    /// The limit is advertised with the `active_connection_id_limit` transport parameter.
+    /// A higher limit makes connection migration and connection id rotation smoother, as more
+    /// unused connection ids are available. A lower limit saves memory.
+    /// The limit must be at least `2`, otherwise creating the `Context` fails.
+    pub fn set_active_connection_id_limit(&mut self, limit: u32) {
+        self.active_connection_id_limit = Some(limit);
+    }
+
+    /// Enables the counting of the sent and received frames per frame type of each `Connection`,
+    /// see `Connection::frame_stats`. Each sent and received packet needs to be parsed for
+    /// counting, so this is disabled by default.
+    pub fn enable_frame_stats(&mut self) {
+        self.frame_stats = true;
+    }
+
+    /// Sets the maximum time, an acknowledgement is delayed. This is advertised to the peer with
+    /// the `max_ack_delay` transport parameter, see `Connection::peer_max_ack_delay`.
+    /// A higher delay reduces the number of acknowledgements for bulk transfers, while a lower
+    /// delay makes the loss detection of interactive traffic faster.
+    /// The delay must be less than `2^14` milliseconds, otherwise creating the `Context` fails.
+    pub fn set_max_ack_delay(&mut self, delay: Duration) {
+        self.max_ack_delay = Some(delay);
+    }
+
+    /// Enables the ack frequency extension, by advertising the given `min_ack_delay` to the
+    /// peer. A peer that supports the extension can then ask us to acknowledge less often (up to
+    /// the ack delay it requests, but at least `min_ack_delay`), which reduces the overhead of the
+    /// acknowledgements on high-rate flows.
+    /// The delay must not be greater than the `max_ack_delay`, otherwise creating the `Context`
+    /// fails.
+    pub fn enable_ack_frequency(&mut self, min_ack_delay: Duration) {
+        self.min_ack_delay = Some(min_ack_delay);
+    }
+
+    /// Sets the exponent that scales the ack delay in the sent acknowledgements. This is
+    /// advertised to the peer with the `ack_delay_exponent` transport parameter, so the peer
+    /// can decode the ack delay. A peer that ignores the parameter miscalculates the RTT, if the
+    /// exponent differs from the default of the specification (`3`).
+    /// The exponent must not be greater than `20`, otherwise creating the `Context` fails.
+    pub fn set_ack_delay_exponent(&mut self, exponent: u8) {
+        self.ack_delay_exponent = Some(exponent);
+    }
+
+    /// Sets the maximum number of ack ranges in a sent acknowledgement. Fewer ranges produce
+    /// smaller acknowledgements for constrained peers, but the peer learns less about the lost
+    /// packets on lossy paths.
+    /// The number must be at least `1`, otherwise creating the `Context` fails.
+    pub fn set_max_ack_ranges(&mut self, ranges: u32) {
+        self.max_ack_ranges = Some(ranges);
+    }
+
+    /// Sets the padding policy of the packets that are not `Initial` packets. Packets smaller
+    /// than `min_size` are padded to `min_size` and the size of all remaining packets is
+    /// rounded up to a multiple of `multiple` (`0` disables the rounding).
+    /// `Initial` packets are always padded to 1200 bytes, as required by the specification.
+    /// Less padding saves bytes on bandwidth-constrained links with small packets, more padding
+    /// hides the size of the packets from observers.
+    /// Both values must not be greater than the maximum packet size, otherwise creating the
+    /// `Context` fails.
+    pub fn set_padding_policy(&mut self, min_size: u32, multiple: u32) {
+        self.padding_policy = Some((min_size, multiple));
+    }
+
+    /// Sets the maximum size of the packets that are sent in the handshake, before the path MTU
+    /// discovery found the supported size of the path. Some paths drop large packets early in
+    /// a connection, which stalls the handshake of servers with large certificate chains.
+    /// Smaller packets require more handshake flights, see `Connection::handshake_flight_count`.
+    /// The size must be at least 1200 bytes and not greater than the maximum packet size,
+    /// otherwise creating the `Context` fails.
+    pub fn set_max_handshake_packet_size(&mut self, size: u32) {
+        self.max_handshake_packet_size = Some(size);
+    }
+
+    /// Sets the sizes of the kernel send and receive buffers of the UDP sockets that are created
+    /// by the `Context`. Undersized buffers are a common cause of packet loss on fast links, as
+    /// the kernel drops the packets that do not fit into the buffers.
+    /// The operating system may limit the sizes (on Linux by `net.core.wmem_max` and
+    /// `net.core.rmem_max`), a warning is logged if the requested size was not applied.
+    /// Sockets of the test transport do not have kernel buffers, so the sizes are ignored.
+    pub fn set_socket_buffer_sizes(&mut self, send: usize, recv: usize) {
+        self.socket_buffer_sizes = Some((send, recv));
+    }
+
+    /// Sets the don't fragment bit on all packets that are sent by the UDP sockets of the
+    /// `Context`. The path MTU discovery of picoquic probes larger packets, which need to be
+    /// dropped by routers on paths with a smaller MTU, instead of being fragmented. Otherwise,
+    /// picoquic can not detect the MTU of the path.
+    /// A probe that is silently dropped (a black hole) is declared as lost and picoquic falls
+    /// back to a smaller MTU, see `Event::PathMtuReduced` and `Connection::path_mtu`.
+    /// The option is only supported on Linux, other platforms log a warning. Sockets of the test
+    /// transport do not fragment packets, so the option is ignored.
+    pub fn enable_dont_fragment(&mut self) {
+        self.dont_fragment = true;
+    }
+
+    /// Requests the ECN codepoint of each received packet from the kernel and counts the
+    /// codepoints per `Connection`, see `Connection::received_ecn_counts`. Packets that were
+    /// marked as congestion experienced (CE) by a router, tell the application about congestion
+    /// before packets are lost.
+    /// The option is only supported on Linux, other platforms log a warning. The test transport
+    /// marks the packets as configured by its `LinkModel`.
+    pub fn enable_ecn_marks(&mut self) {
+        self.ecn_marks = true;
+    }
+
+    /// Binds the UDP sockets of the `Context` to the network interface with the given name
+    /// (`SO_BINDTODEVICE`). All packets are sent over this interface, regardless of the routing
+    /// table, and only packets that arrive on this interface are received. In contrast to the
+    /// listen address, this pins the interface that is used to send the packets.
+    /// The option is only supported on Linux and requires the `CAP_NET_RAW` capability (on
+    /// kernels before 5.7), otherwise creating the `Context` fails. Sockets of the test
+    /// transport are not bound to an interface, so the option is ignored.
+    pub fn set_bind_device<T: Into<String>>(&mut self, name: T) {
+        self.bind_device = Some(name.into());
+    }
+
+    /// Hints the number of `Connection`s and the number of `Stream`s per `Connection`, that are
+    /// expected to be open at the same time. The maps that store the `Stream`s of `connections`
+    /// `Connection`s are allocated upfront with the capacity for `streams` `Stream`s. A
+    /// `Connection` takes its map from the `Context` and returns it, when it is dropped. This
+    /// keeps the allocations, which grow the map, off the hot path of latency-sensitive servers.
+    /// If more `Connection`s are open at the same time, their maps are allocated as usual.
+    /// See `Context::capacity_stats` for the high-water marks, to tune the hints.
+    ///
+    /// Only the maps are allocated upfront. The state that is allocated by picoquic itself (the
+    /// connection and stream objects of the c library) and the remaining per `Connection` and
+    /// per `Stream` state are still allocated on demand.
+    pub fn set_capacity_hints(&mut self, connections: usize, streams: usize) {
+        self.capacity_hints = Some((connections, streams));
+    }
+
+    /// Adds a custom transport parameter that is send to the peers in the handshake. This can be
+    /// used to negotiate an application specific parameter without an additional round trip.
+    /// The peer reads the parameter with `Connection::peer_custom_transport_parameters`.
+    ///
+    /// To prevent collisions, the ids that are defined by QUIC (all ids below `0x40`), the ids
+    /// of the extensions that are supported by picoquic (e.g. `grease_quic_bit`) and the ids
+    /// reserved for greasing (`31 * N + 27`) are rejected. An id may only be added once.
+    /// Otherwise, creating the `Context` fails. Applications should use an id that is
+    /// registered as provisional in the IANA registry, to not collide with other extensions.
+    pub fn add_custom_transport_parameter(&mut self, id: u64, value: Vec<u8>) {
+        self.custom_transport_parameters.push((id, value));
+    }
+
+    /// Sets the maximum number of received bytes per connection, that were not read yet by the
+    /// application. This protects against peers that open many `Stream`s and send more data
+    /// than the application reads.
+    /// The flow control window that picoquic grants to the peer is limited to `bytes`. Data
+    /// that was already delivered to the `Stream`s is still buffered, until it is read. The
+    /// `policy` decides what happens to a connection that buffers more than `bytes`.
+    /// The buffer is checked, when the connection is polled.
+    pub fn set_max_recv_buffer(&mut self, bytes: usize, policy: OverflowPolicy) {
+        self.max_recv_buffer = Some((bytes, policy));
+    }
+
+    /// Restricts the TLS cipher suites that may be negotiated to the given cipher suites, e.g. to
+    /// comply with a crypto policy. The order of the cipher suites is kept from picotls.
+    /// If none of the given cipher suites is supported by picotls, creating the `Context` fails.
+    /// A handshake with a peer that does not support any of the cipher suites fails with
+    /// `ErrorKind::NoCommonTLSParameters`.
+    pub fn set_cipher_suites(&mut self, cipher_suites: &[CipherSuite]) {
+        self.cipher_suites = Some(cipher_suites.to_vec());
+    }
+
+    /// Restricts the groups that may be used for the key exchange in the TLS handshake to the
+    /// given groups.
+    /// If none of the given groups is supported by picotls, creating the `Context` fails.
+    /// A handshake with a peer that does not support any of the groups fails with
+    /// `ErrorKind::NoCommonTLSParameters`.
+    pub fn set_key_exchange_groups(&mut self, groups: &[KeyExchangeGroup]) {
+        self.key_exchange_groups = Some(groups.to_vec());
+    }
+
+    /// Limits the rate of new incoming connections to `per_sec` connections per second, to
+    /// protect the server against floods of (spoofed) `Initial` packets, that would exhaust the
+    /// CPU with the cryptography of the handshakes.
+    /// The limit is checked before picoquic allocates the state of a connection. Bursts of up to
+    /// `per_sec` connections are allowed, the connections that exceed the rate are handled as
+    /// given by `policy`. Packets of already established connections are never limited.
+    /// `per_sec` must be at least `1`, otherwise creating the `Context` fails.
+    pub fn set_new_connection_rate_limit(&mut self, per_sec: u32, policy: RateLimitPolicy) {
+        self.new_connection_rate_limit = Some((per_sec, policy));
+    }
+
+    /// Sets the handler that consumes the `QuicEvent`s of all `Connection`s.
+    /// The events are reported in-process, while picoquic processes the packets. This can be
+    /// used for real-time monitoring, without writing and parsing log files.
+    pub fn set_event_sink<H: EventSink + 'static>(&mut self, handler: H) {
+        self.event_sink = Some(Box::new(handler));
+    }
+
+    /// Sets the handler that is called, when the address of the peer of a `Connection` changed
+    /// (the peer migrated the `Connection`). The handler can veto the change by returning an
+    /// error, which closes the `Connection`. This can be used to enforce a migration policy, e.g.
+    /// to refuse a migration to a different address family.
+    /// The change is detected, when the `Connection` is polled after receiving a packet from the
+    /// new address. So, a few packets may already be exchanged over the new path.
+    pub fn set_peer_address_change_handler<H: PeerAddressChange + 'static>(&mut self, handler: H) {
+        self.peer_address_change_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the filter that decides, if an incoming `Connection` is accepted.
+    /// The filter is called with the server name, the ALPN protocols and the address of the
+    /// client, when the `ClientHello` is processed. A rejected `Connection` is aborted with the
+    /// given TLS alert and is never returned by the `Context`. This can be used by multi-tenant
+    /// servers or to mitigate denial of service attacks.
+    pub fn set_accept_filter<H: AcceptFilter + 'static>(&mut self, filter: H) {
+        self.accept_filter = Some(Box::new(filter));
+    }
+
+    /// Sets the policy of the server for the 0-RTT data of resumed sessions, see
+    /// `EarlyDataPolicy`. 0-RTT data can be replayed by an attacker, so it should be rejected, if
+    /// the application protocol allows non-idempotent operations in the first flight.
+    /// A `Connection` whose 0-RTT data was rejected, also receives session tickets that do not
+    /// allow 0-RTT data.
+    pub fn set_early_data_policy(&mut self, policy: EarlyDataPolicy) {
+        self.early_data_policy = Some(policy);
+    }
+
+    /// Sets the handler that consumes the TLS secrets of all `Connection`s in the NSS key log
+    /// format (`SSLKEYLOGFILE`). With these secrets, Wireshark can decrypt captured packets.
+    ///
+    /// This is insecure and must only be used for debugging, as anyone with access to the
+    /// secrets can decrypt the traffic.
+    pub fn set_keylog_handler<H: Keylog + 'static>(&mut self, handler: H) {
+        self.keylog_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the file that stores the TLS secrets of all `Connection`s in the NSS key log format
+    /// (`SSLKEYLOGFILE`). Wireshark can use this file to decrypt captured packets.
+    /// The secrets are appended to the file, it is created if it does not exist. If the file can
+    /// not be opened, creating the `Context` fails.
+    ///
+    /// This is insecure and must only be used for debugging, as anyone with access to the file
+    /// can decrypt the traffic. If a handler is set with `set_keylog_handler`, the file is not
+    /// used.
+    pub fn set_keylog_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.keylog_filename = Some(path.into());
+    }
+
     /// Sets the handler that should verify the peer certificate in the TLS handshake.
     pub fn set_verify_certificate_handler<H: VerifyCertificate + 'static>(&mut self, handler: H) {
         self.verify_certificate_handler = Some(Box::new(handler));
     }
 
+    /// Disables the verification of the peer certificate in the TLS handshake.
+    /// All certificates are accepted, even self-signed or expired ones. This makes the
+    /// `Connection`s vulnerable to man-in-the-middle attacks!
+    /// This is only intended for local development and tests and is only available with the
+    /// `insecure` feature.
+    /// This option will overwrite `set_verify_certificate_handler`.
+    #[cfg(feature = "insecure")]
+    pub fn dangerous_skip_verification(&mut self) {
+        self.set_verify_certificate_handler(::verify_certificate::SkipVerification);
+    }
+
     /// Sets the certificate.
     /// This option will overwrite `set_certificate_chain_filename`.
     pub fn set_certificate_chain(&mut self, certs: Vec<Vec<u8>>, format: FileFormat) {
@@ -144,6 +676,42 @@ impl Default for Config {
             keep_alive_sender: Role::Client,
             client_authentication: false,
             verify_certificate_handler: None,
+            alpn: None,
+            alpn_select_handler: None,
+            local_connection_id_length: None,
+            connection_id_generator: None,
+            disable_migration: false,
+            grease_quic_bit: false,
+            ticket_encryption_key: None,
+            ticket_store_filename: None,
+            initial_rtt: None,
+            active_connection_id_limit: None,
+            event_sink: None,
+            heartbeat: false,
+            peer_address_change_handler: None,
+            keylog_handler: None,
+            keylog_filename: None,
+            accept_filter: None,
+            early_data_policy: None,
+            frame_stats: false,
+            max_ack_delay: None,
+            min_ack_delay: None,
+            ack_delay_exponent: None,
+            max_ack_ranges: None,
+            handshake_timeout: None,
+            padding_policy: None,
+            max_handshake_packet_size: None,
+            socket_buffer_sizes: None,
+            dont_fragment: false,
+            ecn_marks: false,
+            bind_device: None,
+            capacity_hints: None,
+            custom_transport_parameters: Vec::new(),
+            max_recv_buffer: None,
+            cipher_suites: None,
+            key_exchange_groups: None,
+            new_connection_rate_limit: None,
+            ticket_store_capacity: None,
         }
     }
 }
@@ -1,27 +1,109 @@
+use config::OverflowPolicy;
 use error::*;
-use ffi::{self, QuicCtx};
+use event_sink::FrameStats;
+use ffi::{self, MicroSeconds, QuicCtx};
+use peer_address_change::PeerAddressChangeHandler;
+use pool::PoolHandle;
 use stream::{self, Stream};
 
 use picoquic_sys::picoquic::{
     self, picoquic_call_back_event_t, picoquic_cnx_t, picoquic_set_callback,
+    PICOQUIC_MAX_PACKET_SIZE,
 };
 
 use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::sync::oneshot;
+use futures::task::AtomicTask;
 use futures::Async::{NotReady, Ready};
 use futures::{Future, Poll, Stream as FStream};
 
-use std::cell::RefCell;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
 use std::mem;
 use std::net::SocketAddr;
+use std::ops::{BitOr, BitOrAssign, Deref};
 use std::os::raw::c_void;
 use std::rc::Rc;
 use std::slice;
-use std::time::Duration;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use openssl::x509::X509;
+
+/// The id of a `Connection`. The id consists of the first 8 bytes of the local connection id,
+/// shorter connection ids are padded with zeros.
+/// The id is formatted as lowercase hex, like connection ids are shown by qlog and Wireshark.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Id(u64);
+
+impl Id {
+    /// Returns the bytes of this id.
+    pub fn as_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = (self.0 >> (56 - i * 8)) as u8;
+        }
+        bytes
+    }
+
+    /// Creates an id from the given connection id bytes. Only the first 8 bytes are used and
+    /// shorter connection ids are padded with zeros.
+    pub fn from_bytes(bytes: &[u8]) -> Id {
+        let mut padded = [0; 8];
+        let len = bytes.len().min(8);
+        padded[..len].copy_from_slice(&bytes[..len]);
+
+        Id(padded.iter().fold(0, |v, b| (v << 8) | u64::from(*b)))
+    }
+}
+
+impl From<u64> for Id {
+    fn from(id: u64) -> Id {
+        Id(id)
+    }
+}
+
+impl From<Id> for u64 {
+    fn from(id: Id) -> u64 {
+        id.0
+    }
+}
+
+impl fmt::LowerHex for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
 
-pub type Id = u64;
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for Id {
+    type Err = Error;
+
+    /// Parses an id from hex, with or without a `0x` prefix.
+    fn from_str(s: &str) -> Result<Id, Error> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+
+        if hex.is_empty() || hex.len() > 16 {
+            return Err(
+                ::failure::err_msg("Connection id must have between 1 and 16 hex digits").into(),
+            );
+        }
+
+        u64::from_str_radix(hex, 16).map(Id).map_err(|_| {
+            ::failure::err_msg(format!("Connection id is not valid hex: {}", s)).into()
+        })
+    }
+}
 
 #[derive(Debug)]
 enum Message {
@@ -30,6 +112,70 @@ enum Message {
     Error(Error),
 }
 
+/// A `Command` is used by the `Connection` to control its `Context`.
+enum Command {
+    PauseStream(stream::Id, oneshot::Sender<Result<(), Error>>),
+    ResumeStream(stream::Id, oneshot::Sender<Result<(), Error>>),
+    SubscribeEvents(UnboundedSender<Event>),
+    SendHeartbeat,
+    ResetAllStreams(u64),
+    SendFromReader(
+        stream::Id,
+        Box<Read + Send>,
+        Option<Instant>,
+        oneshot::Sender<Result<(), Error>>,
+    ),
+    SetStreamDataProvider(stream::Id, stream::DataProvider),
+    SetWeight(u32),
+    SetCongestionAlgorithm(CongestionAlgorithm),
+    SendAckNow,
+    SetLabel(String),
+    SetCongestionEventCallback(CongestionEventCallback),
+    SetRttChangeCallback(Duration, RttChangeCallback),
+    #[cfg(feature = "loss-injection")]
+    MarkPacketLost(u64),
+}
+
+/// An `Event` that is reported by a `Connection` to its subscribers.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    /// The peer is blocked by the connection level flow control (`DATA_BLOCKED`).
+    PeerDataBlocked,
+    /// The peer is blocked by the flow control of the `Stream` with the given id
+    /// (`STREAM_DATA_BLOCKED`).
+    PeerStreamDataBlocked(stream::Id),
+    /// The client received a version negotiation packet from the server, with the given QUIC
+    /// versions that are supported by the server.
+    VersionNegotiation(Vec<u32>),
+    /// The peer opened a new `Stream` with the given id and type.
+    /// This `Event` is reported when the `Stream` is created, which happens when picoquic sees
+    /// the `Stream` for the first time. This is also the case for a `Stream` that carries no
+    /// data, e.g. a `Stream` that is only finished. The `Event` is reported before the data of
+    /// the `Stream` is delivered and before the `Stream` is returned by the `Connection`.
+    NewStream(stream::Id, stream::Type),
+    /// The handshake is confirmed. A client confirms the handshake, when it receives the
+    /// `HANDSHAKE_DONE` frame, a server, when the handshake is finished.
+    /// After this `Event`, the handshake keys are discarded and the `Connection` is fully
+    /// established. If the handshake is already confirmed when subscribing to the `Event`s,
+    /// this `Event` is reported immediately.
+    HandshakeConfirmed,
+    /// The peer sent a heartbeat with `Connection::send_heartbeat`.
+    Heartbeat,
+    /// The path MTU was reduced from the first to the second value, because picoquic detected
+    /// that packets of the larger size are dropped on the path (a black hole).
+    PathMtuReduced(usize, usize),
+}
+
+/// The default value of the `max_udp_payload_size` transport parameter.
+const DEFAULT_MAX_UDP_PAYLOAD_SIZE: usize = 65527;
+/// The default value of the `max_ack_delay` transport parameter in milliseconds.
+const DEFAULT_MAX_ACK_DELAY: u64 = 25;
+/// The default value of the `ack_delay_exponent` transport parameter.
+const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+
+/// The stable id of the next `Connection` that is created.
+static NEXT_STABLE_ID: AtomicUsize = AtomicUsize::new(0);
+
 /// A `Connection` can either be `Incoming` or `Outgoing`.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Type {
@@ -39,43 +185,519 @@ pub enum Type {
     Outgoing,
 }
 
+/// The state of a `Connection`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum State {
+    /// The handshake is not finished yet.
+    Handshaking,
+    /// The handshake is finished and data can be exchanged.
+    Ready,
+    /// The `Connection` is closed by this side. Incoming packets are still answered with a
+    /// `CONNECTION_CLOSE` frame, so the `Connection` needs to be serviced until it is
+    /// `Disconnected`.
+    Closing,
+    /// The peer closed the `Connection`. No packets are sent anymore, the `Connection` only waits
+    /// until it is `Disconnected`.
+    Draining,
+    /// The `Connection` is fully closed.
+    Disconnected,
+}
+
+/// A snapshot of the state of the c connection object.
+/// The snapshot is updated by the `Context` each time it is polled and is read by the
+/// `Connection`, because the `Connection` is not allowed to access the c object directly.
+#[derive(Default)]
+struct Snapshot {
+    packets_sent: u64,
+    packets_lost: u64,
+    negotiated_alpn: Option<String>,
+    is_ready: bool,
+    is_closed: bool,
+    disconnect_reason: Option<DisconnectReason>,
+    close_info: Option<CloseInfo>,
+    peer_addr: Option<SocketAddr>,
+    /// The number of times the address of the peer changed.
+    migration_count: u32,
+    /// The label that was set by `Connection::set_label`.
+    label: Option<String>,
+    /// The number of `RESET_STREAM` frames received from the peer.
+    peer_reset_count: u64,
+    /// The number of `STOP_SENDING` frames received from the peer.
+    peer_stop_sending_count: u64,
+    /// The `Stream`s with received data that was not read yet and the number of these bytes.
+    readable_streams: Vec<(stream::Id, usize)>,
+    /// The `Stream`s that received a `FIN` and if all their data was read.
+    recv_finished_streams: Vec<(stream::Id, bool)>,
+    resumed: bool,
+    /// Did the session ticket of the resumed session allow to send 0-RTT data?
+    early_data_allowed: bool,
+    rtt: Duration,
+    bandwidth_estimate: u64,
+    packets_in_flight: u64,
+    /// Is there application data that was not sent or not acknowledged yet?
+    has_unacked_data: bool,
+    /// The number of bytes that are queued in picoquic on all `Stream`s, but were not sent yet.
+    send_queue_bytes: u64,
+    pacing_rate: u64,
+    send_limitation: Option<SendLimit>,
+    congestion_debug: Option<CongestionDebug>,
+    congestion_algorithm: Option<CongestionAlgorithm>,
+    received_ecn_counts: EcnCounts,
+    path_mtu: usize,
+    /// The last point in time (in microseconds), where the connection made progress.
+    latest_progress_time: u64,
+    amplification_budget: Option<u64>,
+    /// The versions offered by the server in a version negotiation packet.
+    offered_versions: Option<Vec<u32>>,
+    peer_active_connection_id_limit: Option<u32>,
+    peer_max_udp_payload_size: Option<usize>,
+    peer_max_ack_delay: Option<Duration>,
+    peer_ack_delay_exponent: Option<u8>,
+    peer_extensions: Option<PeerExtensions>,
+    peer_custom_transport_parameters: Option<Vec<(u64, Vec<u8>)>>,
+    cipher_suite: Option<CipherSuite>,
+    peer_idle_timeout: Option<Duration>,
+    open_stream_counts: StreamCounts,
+    /// The `next_id` of the next `Stream` that is opened by us, see `generate_stream_id`.
+    next_stream_id: u64,
+    max_stream_id_bidi: stream::Id,
+    max_stream_id_uni: stream::Id,
+    state: Option<State>,
+    local_id_bytes: Vec<u8>,
+    remote_id_bytes: Vec<u8>,
+    /// The certificates presented by the peer in the TLS handshake.
+    peer_certificates: Option<Vec<X509>>,
+    frame_stats: Option<FrameStats>,
+    /// The number of lost `Initial` and `Handshake` packets.
+    handshake_retransmit_count: u32,
+    handshake_flight_count: u32,
+}
+
+impl Snapshot {
+    fn update(&mut self, cnx: ffi::Connection) {
+        self.packets_sent = cnx.packets_sent();
+        self.packets_lost = cnx.packets_lost();
+        self.is_ready = cnx.is_ready();
+        self.state = Some(cnx.connection_state());
+        self.local_id_bytes = cnx.local_id_bytes();
+        self.remote_id_bytes = cnx.remote_id_bytes();
+        self.peer_addr = cnx.peer_addr().or(self.peer_addr);
+        self.rtt = cnx.rtt();
+        self.bandwidth_estimate = cnx.bandwidth_estimate();
+        self.packets_in_flight = cnx.packets_in_flight();
+        self.pacing_rate = cnx.pacing_rate();
+        self.send_limitation = Some(cnx.send_limitation());
+        self.congestion_debug = cnx.congestion_debug();
+        self.congestion_algorithm = cnx.congestion_algorithm();
+        self.received_ecn_counts = cnx.received_ecn_counts();
+        self.path_mtu = cnx.path_mtu();
+        self.frame_stats = cnx.frame_stats();
+        self.handshake_retransmit_count = cnx.handshake_packets_lost();
+        self.handshake_flight_count = cnx.handshake_flight_count();
+        self.latest_progress_time = cnx.latest_progress_time();
+        self.amplification_budget = cnx.amplification_budget();
+        self.max_stream_id_bidi = cnx.max_stream_id(stream::Type::Bidirectional);
+        self.max_stream_id_uni = cnx.max_stream_id(stream::Type::Unidirectional);
+        // Remembered, as the 0-RTT keys may be discarded after the handshake.
+        self.early_data_allowed |= cnx.is_0rtt_available();
+
+        if self.negotiated_alpn.is_none() {
+            self.negotiated_alpn = cnx.negotiated_alpn();
+        }
+
+        if self.is_ready {
+            self.resumed = cnx.is_psk_handshake();
+            self.peer_active_connection_id_limit = Some(cnx.peer_active_connection_id_limit());
+            self.peer_max_udp_payload_size = Some(cnx.peer_max_udp_payload_size());
+            self.peer_max_ack_delay = Some(cnx.peer_max_ack_delay());
+            self.peer_ack_delay_exponent = Some(cnx.peer_ack_delay_exponent());
+            self.peer_extensions = Some(cnx.peer_extensions());
+            self.peer_idle_timeout = Some(cnx.peer_idle_timeout());
+
+            if self.peer_custom_transport_parameters.is_none() {
+                self.peer_custom_transport_parameters =
+                    Some(cnx.peer_custom_transport_parameters());
+            }
+
+            if self.cipher_suite.is_none() {
+                self.cipher_suite = cnx.cipher_suite().map(CipherSuite::from_id);
+            }
+
+            if self.peer_certificates.is_none() {
+                self.peer_certificates = cnx.peer_certificates();
+            }
+        }
+    }
+
+    fn loss_rate(&self) -> f64 {
+        if self.packets_sent == 0 {
+            0.0
+        } else {
+            self.packets_lost as f64 / self.packets_sent as f64
+        }
+    }
+
+    fn is_healthy(&self, policy: &HealthPolicy, now: u64) -> bool {
+        if !self.is_ready || self.is_closed {
+            return false;
+        }
+
+        if let Some(max_loss_rate) = policy.max_loss_rate {
+            if self.loss_rate() > max_loss_rate {
+                return false;
+            }
+        }
+
+        if let Some(max_rtt) = policy.max_rtt {
+            if self.rtt > max_rtt {
+                return false;
+            }
+        }
+
+        if let Some(max_idle_time) = policy.max_idle_time {
+            let idle_time = now.saturating_sub(self.latest_progress_time);
+            if Duration::from_micro_seconds(idle_time) > max_idle_time {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// The number of open `Stream`s of a `Connection` per type and initiator.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct StreamCounts {
+    /// The bidirectional `Stream`s opened by this side.
+    pub bidi_local: usize,
+    /// The bidirectional `Stream`s opened by the peer.
+    pub bidi_remote: usize,
+    /// The unidirectional `Stream`s opened by this side.
+    pub uni_local: usize,
+    /// The unidirectional `Stream`s opened by the peer.
+    pub uni_remote: usize,
+}
+
+impl StreamCounts {
+    /// Counts the `Stream` with the given id.
+    fn add(&mut self, id: stream::Id, is_client: bool) {
+        let local = stream::is_client_initiated(id) == is_client;
+
+        match (stream::Type::from_id(id), local) {
+            (stream::Type::Bidirectional, true) => self.bidi_local += 1,
+            (stream::Type::Bidirectional, false) => self.bidi_remote += 1,
+            (stream::Type::Unidirectional, true) => self.uni_local += 1,
+            (stream::Type::Unidirectional, false) => self.uni_remote += 1,
+        }
+    }
+}
+
+/// The extensions that were advertised by the peer in its transport parameters.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct PeerExtensions {
+    /// The maximum size of a `DATAGRAM` frame the peer accepts, if it supports datagrams.
+    pub max_datagram_frame_size: Option<usize>,
+    /// Does the peer support multipath?
+    pub multipath: bool,
+    /// Does the peer accept greased QUIC bits (`grease_quic_bit`)?
+    pub grease_quic_bit: bool,
+    /// Did the peer disable the active migration (`disable_active_migration`)?
+    pub migration_disabled: bool,
+    /// The `min_ack_delay` of the peer, if it supports the ack frequency extension.
+    pub min_ack_delay: Option<Duration>,
+}
+
+/// A TLS 1.3 cipher suite.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CipherSuite {
+    /// `TLS_AES_128_GCM_SHA256`
+    Aes128GcmSha256,
+    /// `TLS_AES_256_GCM_SHA384`
+    Aes256GcmSha384,
+    /// `TLS_CHACHA20_POLY1305_SHA256`
+    Chacha20Poly1305Sha256,
+    /// A cipher suite with the given id, that is not known by this crate.
+    Unknown(u16),
+}
+
+impl CipherSuite {
+    /// Returns the cipher suite with the given IANA id.
+    pub fn from_id(id: u16) -> CipherSuite {
+        match id {
+            0x1301 => CipherSuite::Aes128GcmSha256,
+            0x1302 => CipherSuite::Aes256GcmSha384,
+            0x1303 => CipherSuite::Chacha20Poly1305Sha256,
+            id => CipherSuite::Unknown(id),
+        }
+    }
+
+    /// Returns the IANA id of this cipher suite.
+    pub fn id(self) -> u16 {
+        match self {
+            CipherSuite::Aes128GcmSha256 => 0x1301,
+            CipherSuite::Aes256GcmSha384 => 0x1302,
+            CipherSuite::Chacha20Poly1305Sha256 => 0x1303,
+            CipherSuite::Unknown(id) => id,
+        }
+    }
+}
+
+/// A group that is used for the (EC)DHE key exchange in the TLS handshake.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum KeyExchangeGroup {
+    Secp256r1,
+    Secp384r1,
+    Secp521r1,
+    X25519,
+    X448,
+    /// A group with the given id, that is not known by this crate.
+    Unknown(u16),
+}
+
+impl KeyExchangeGroup {
+    /// Returns the group with the given IANA id.
+    pub fn from_id(id: u16) -> KeyExchangeGroup {
+        match id {
+            23 => KeyExchangeGroup::Secp256r1,
+            24 => KeyExchangeGroup::Secp384r1,
+            25 => KeyExchangeGroup::Secp521r1,
+            29 => KeyExchangeGroup::X25519,
+            30 => KeyExchangeGroup::X448,
+            id => KeyExchangeGroup::Unknown(id),
+        }
+    }
+
+    /// Returns the IANA id of this group.
+    pub fn id(self) -> u16 {
+        match self {
+            KeyExchangeGroup::Secp256r1 => 23,
+            KeyExchangeGroup::Secp384r1 => 24,
+            KeyExchangeGroup::Secp521r1 => 25,
+            KeyExchangeGroup::X25519 => 29,
+            KeyExchangeGroup::X448 => 30,
+            KeyExchangeGroup::Unknown(id) => id,
+        }
+    }
+}
+
+/// The reason why a `Connection` was disconnected.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DisconnectReason {
+    /// The peer sent a stateless reset, because it lost the state of the connection (e.g. the
+    /// peer restarted).
+    StatelessReset,
+    /// The connection was idle for longer than the idle timeout.
+    IdleTimeout,
+    /// The connection was closed by us.
+    LocalClose,
+    /// The connection was closed by us because of an error, e.g. a protocol violation of the peer
+    /// that was detected by picoquic or an overflow of the receive buffer.
+    LocalError,
+    /// The connection was closed by the peer.
+    RemoteClose,
+    /// The connection was closed before the handshake finished (e.g. the TLS handshake failed or
+    /// the server refused the connection).
+    HandshakeFailed,
+    /// The handshake did not finish before the handshake timeout, see
+    /// `Config::set_handshake_timeout`.
+    Timeout,
+}
+
+/// The cause of a reduction of the congestion window.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CongestionCause {
+    /// Packets were declared as lost.
+    Loss,
+    /// Packets that were marked with ECN-CE (congestion experienced) were received, see
+    /// `Connection::received_ecn_counts`.
+    Ecn,
+}
+
+/// Reported by the callback of `Connection::set_congestion_event_callback`, when the congestion
+/// window was reduced.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct CongestionEvent {
+    /// The new congestion window in bytes.
+    pub cwnd: u64,
+    /// Why the congestion window was reduced.
+    pub cause: CongestionCause,
+}
+
+/// The number of packets a `Connection` received per ECN codepoint, see
+/// `Connection::received_ecn_counts`.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct EcnCounts {
+    /// The packets that were not ECN capable (Not-ECT).
+    pub not_ect: u64,
+    /// The packets that were marked as ECN capable with ECT(0).
+    pub ect0: u64,
+    /// The packets that were marked as ECN capable with ECT(1).
+    pub ect1: u64,
+    /// The packets that were marked as congestion experienced (CE) by a router on the path.
+    pub ce: u64,
+}
+
+/// A congestion control algorithm of picoquic, see `Connection::set_congestion_algorithm`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CongestionAlgorithm {
+    NewReno,
+    Cubic,
+    Bbr,
+}
+
+/// The internal state of the congestion controller of a `Connection`, as reported by picoquic,
+/// see `Connection::congestion_debug`.
+/// The values are internals of picoquic that may change with its versions, so they should only
+/// be used for debugging.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CongestionDebug {
+    NewReno {
+        /// The state of the algorithm in picoquic (slow start, congestion avoidance, recovery).
+        state: u64,
+        /// The slow start threshold in bytes, `0` as long as no congestion occurred.
+        ssthresh: u64,
+    },
+    Cubic {
+        /// The state of the algorithm in picoquic (slow start, congestion avoidance, recovery).
+        state: u64,
+        /// The congestion window in bytes, before the last reduction.
+        w_max: u64,
+    },
+    Bbr {
+        /// The mode of BBR in picoquic (startup, drain, probe bandwidth, probe RTT).
+        mode: u64,
+        /// The estimated bandwidth of the bottleneck in bytes per second.
+        bandwidth: u64,
+    },
+    /// A congestion algorithm of picoquic that is not known by this crate.
+    Other {
+        /// The id of the algorithm in picoquic.
+        algorithm: String,
+        state: u64,
+        param: u64,
+    },
+}
+
+/// The limit that currently prevents a `Connection` from sending faster, see
+/// `Connection::send_limitation`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SendLimit {
+    /// The congestion window is full, so the network limits the `Connection`.
+    CongestionLimited,
+    /// The connection level flow control window of the peer is used up.
+    FlowControlLimited,
+    /// The pacer delays the next packet.
+    PacingLimited,
+    /// None of the limits applies, so the application does not provide data fast enough.
+    AppLimited,
+}
+
+/// The error codes and the reason phrase that were exchanged in the `CONNECTION_CLOSE` frames,
+/// when a `Connection` was closed, see `Connection::close_info`.
+/// An error code of `0` means, that no error was reported.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CloseInfo {
+    /// The transport error code that was sent by this side.
+    pub local_error: u64,
+    /// The transport error code that was sent by the peer.
+    pub remote_error: u64,
+    /// The application error code that was sent by this side.
+    pub local_application_error: u64,
+    /// The application error code that was sent by the peer.
+    pub remote_application_error: u64,
+    /// The type of the frame that triggered the transport error of this side.
+    pub offending_frame_type: Option<u64>,
+    /// The reason phrase that was sent by the peer.
+    pub remote_reason: Option<String>,
+}
+
+/// The callback that is called with the `CongestionEvent`s of a `Connection`.
+type CongestionEventCallback = Box<Fn(CongestionEvent) + Send>;
+
+/// The callback that is called with the new smoothed RTT of a `Connection`.
+type RttChangeCallback = Box<Fn(Duration) + Send>;
+
+/// The thresholds that are checked by `Connection::is_healthy`.
+/// A threshold that is `None`, is not checked.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HealthPolicy {
+    /// The maximum ratio of lost packets to sent packets.
+    pub max_loss_rate: Option<f64>,
+    /// The maximum smoothed round trip time.
+    pub max_rtt: Option<Duration>,
+    /// The maximum time since the `Connection` made progress the last time.
+    pub max_idle_time: Option<Duration>,
+}
+
+/// The events of a `Connection` that are reported by `Connection::poll_events`.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct EventFlags(u8);
+
+impl EventFlags {
+    /// At least one `Stream` received data that was not read yet.
+    pub const READABLE: EventFlags = EventFlags(1);
+    /// The `Connection` is ready to send data.
+    pub const WRITABLE: EventFlags = EventFlags(1 << 1);
+    /// The `Connection` is closed.
+    pub const CLOSED: EventFlags = EventFlags(1 << 2);
+    /// The address of the peer changed since the `Connection` was created.
+    pub const MIGRATED: EventFlags = EventFlags(1 << 3);
+
+    /// Returns `EventFlags` without any event set.
+    pub fn empty() -> EventFlags {
+        EventFlags(0)
+    }
+
+    /// Returns if no event is set.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns if all events of `other` are set.
+    pub fn contains(self, other: EventFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for EventFlags {
+    type Output = EventFlags;
+
+    fn bitor(self, other: EventFlags) -> EventFlags {
+        EventFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for EventFlags {
+    fn bitor_assign(&mut self, other: EventFlags) {
+        self.0 |= other.0;
+    }
+}
+
 struct ConnectionBuilder {
     msg_recv: UnboundedReceiver<Message>,
-    close_send: oneshot::Sender<()>,
-    peer_addr: SocketAddr,
-    local_addr: SocketAddr,
+    close_send: oneshot::Sender<(u16, String)>,
+    cmd_send: UnboundedSender<Command>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
     new_stream_handle: NewStreamHandle,
     ctype: Type,
+    snapshot: Arc<Mutex<Snapshot>>,
+    stable_id: u64,
 }
 
 impl ConnectionBuilder {
-    fn new(
-        msg_recv: UnboundedReceiver<Message>,
-        close_send: oneshot::Sender<()>,
-        peer_addr: SocketAddr,
-        local_addr: SocketAddr,
-        new_stream_handle: NewStreamHandle,
-        ctype: Type,
-    ) -> ConnectionBuilder {
-        ConnectionBuilder {
-            msg_recv,
-            close_send,
-            peer_addr,
-            local_addr,
-            new_stream_handle,
-            ctype,
-        }
-    }
-
     fn build(self, id: Id) -> Connection {
         Connection {
             msg_recv: self.msg_recv,
             close_send: Some(self.close_send),
+            cmd_send: self.cmd_send,
             peer_addr: self.peer_addr,
             local_addr: self.local_addr,
             new_stream_handle: self.new_stream_handle,
             ctype: self.ctype,
             id,
+            snapshot: self.snapshot,
+            stable_id: self.stable_id,
         }
     }
 }
@@ -83,22 +705,27 @@ impl ConnectionBuilder {
 /// Represents a connection to a peer.
 pub struct Connection {
     msg_recv: UnboundedReceiver<Message>,
-    close_send: Option<oneshot::Sender<()>>,
-    peer_addr: SocketAddr,
-    local_addr: SocketAddr,
+    close_send: Option<oneshot::Sender<(u16, String)>>,
+    cmd_send: UnboundedSender<Command>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
     new_stream_handle: NewStreamHandle,
     id: Id,
     ctype: Type,
+    snapshot: Arc<Mutex<Snapshot>>,
+    stable_id: u64,
 }
 
 impl Connection {
     /// Returns the address of the peer, this `Connection` is connected to.
-    pub fn peer_addr(&self) -> SocketAddr {
+    /// If the address was not known when the `Connection` was created, `None` is returned.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
         self.peer_addr
     }
 
     /// Returns the address of the local `Context`, where it is listening on.
-    pub fn local_addr(&self) -> SocketAddr {
+    /// If the address was not known when the `Connection` was created, `None` is returned.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
         self.local_addr
     }
 
@@ -108,10 +735,428 @@ impl Connection {
         self.id
     }
 
+    /// Returns the bytes of the current local connection id.
+    /// In contrast to `id`, that only contains the first 8 bytes of the connection id, this
+    /// returns the full connection id.
+    pub fn local_cid_bytes(&self) -> Vec<u8> {
+        self.snapshot.lock().unwrap().local_id_bytes.clone()
+    }
+
+    /// Returns the bytes of the current remote connection id, that is used to send packets to
+    /// the peer.
+    pub fn remote_cid_bytes(&self) -> Vec<u8> {
+        self.snapshot.lock().unwrap().remote_id_bytes.clone()
+    }
+
     /// Returns the `Type` of this `Connection`.
     pub fn get_type(&self) -> Type {
         self.ctype
     }
+
+    /// Returns if a new `Stream` of the given type can be opened, without exceeding the stream
+    /// limit of the peer. If this returns `false`, a new `Stream` can still be created, but no
+    /// data is sent on it until the peer raises the limit.
+    pub fn can_open_stream(&self, stype: stream::Type) -> bool {
+        let snapshot = self.snapshot.lock().unwrap();
+        let id = ffi::Connection::generate_stream_id(
+            snapshot.next_stream_id,
+            self.ctype == Type::Outgoing,
+            stype,
+        );
+
+        match stype {
+            stream::Type::Bidirectional => id <= snapshot.max_stream_id_bidi,
+            stream::Type::Unidirectional => id <= snapshot.max_stream_id_uni,
+        }
+    }
+
+    /// Returns if the `Stream` with the given id is opened by the peer.
+    /// The initiator of a `Stream` is encoded in the first bit of its id.
+    pub fn is_peer_initiated(&self, id: stream::Id) -> bool {
+        stream::is_client_initiated(id) != (self.ctype == Type::Outgoing)
+    }
+
+    /// Returns an id that is unique for each `Connection` of this process and that never changes.
+    /// In contrast to the connection ids, which can change at any time (e.g. by a migration or a
+    /// rotation of the connection ids), this id can be used to correlate logs and metrics over
+    /// the entire lifetime of this `Connection`.
+    pub fn stable_id(&self) -> u64 {
+        self.stable_id
+    }
+
+    /// Returns the events of this `Connection`, derived from its current state.
+    /// This can be used as an alternative to polling the `Connection` and its `Stream`s.
+    pub fn poll_events(&self) -> EventFlags {
+        let snapshot = self.snapshot.lock().unwrap();
+        let mut events = EventFlags::empty();
+
+        if !snapshot.readable_streams.is_empty() {
+            events |= EventFlags::READABLE;
+        }
+
+        if snapshot.is_closed {
+            events |= EventFlags::CLOSED;
+        } else if snapshot.is_ready {
+            events |= EventFlags::WRITABLE;
+        }
+
+        if self.peer_addr.is_some()
+            && snapshot.peer_addr.is_some()
+            && snapshot.peer_addr != self.peer_addr
+        {
+            events |= EventFlags::MIGRATED;
+        }
+
+        events
+    }
+
+    /// Returns all `Stream`s with received data that was not read yet, together with the number
+    /// of these bytes. This can be used to read only from the `Stream`s that have data available.
+    pub fn poll_readable_streams(&self) -> Vec<(stream::Id, usize)> {
+        self.snapshot.lock().unwrap().readable_streams.clone()
+    }
+
+    /// Returns if the peer finished sending on the given `Stream` with a `FIN`. More data can be
+    /// buffered and not read yet, see `stream_all_received`.
+    /// A `Stream` that is closed on both sides is not tracked anymore and `false` is returned.
+    pub fn stream_recv_finished(&self, id: stream::Id) -> bool {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .recv_finished_streams
+            .iter()
+            .any(|&(s, _)| s == id)
+    }
+
+    /// Returns if the peer finished sending on the given `Stream` with a `FIN` and all data up
+    /// to the `FIN` was read from the `Stream`. This marks the end of a request or a response.
+    /// A `Stream` that is closed on both sides is not tracked anymore and `false` is returned.
+    pub fn stream_all_received(&self, id: stream::Id) -> bool {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .recv_finished_streams
+            .iter()
+            .any(|&(s, all)| s == id && all)
+    }
+
+    /// Returns the ALPN protocol that was negotiated in the TLS handshake.
+    /// If no protocol was negotiated, `None` is returned.
+    pub fn negotiated_alpn(&self) -> Option<String> {
+        self.snapshot.lock().unwrap().negotiated_alpn.clone()
+    }
+
+    /// Returns the ratio of lost packets to sent packets over the lifetime of this `Connection`.
+    /// Before any packet was sent, `0.0` is returned.
+    pub fn loss_rate(&self) -> f64 {
+        self.snapshot.lock().unwrap().loss_rate()
+    }
+
+    /// Returns the smoothed round trip time of this `Connection`.
+    pub fn rtt(&self) -> Duration {
+        self.snapshot.lock().unwrap().rtt
+    }
+
+    /// Returns the estimated bandwidth of this `Connection` in bytes per second.
+    /// The estimate is based on the measured delivery rate of the acknowledged data and can be
+    /// used to adapt the bitrate of the application (e.g. the encoding of a video).
+    /// Only the BBR congestion controller uses and updates the estimate continuously, with the
+    /// other congestion controllers (e.g. New Reno and Cubic) the value is less meaningful.
+    /// Before the first estimate is available, `0` is returned.
+    pub fn bandwidth_estimate(&self) -> u64 {
+        self.snapshot.lock().unwrap().bandwidth_estimate
+    }
+
+    /// Returns the rate in bytes per second, this `Connection` is currently pacing its packets at.
+    /// In contrast to the `bandwidth_estimate`, this is the rate the congestion controller allows
+    /// to send at right now. A media encoder can use it to avoid producing more data than can
+    /// be sent.
+    /// If pacing is not active (e.g. before the congestion controller set a rate), `0` is
+    /// returned.
+    pub fn pacing_rate(&self) -> u64 {
+        self.snapshot.lock().unwrap().pacing_rate
+    }
+
+    /// Returns the limit that currently prevents this `Connection` from sending faster. This
+    /// tells if a slow transfer is limited by the network (`CongestionLimited`), by the peer
+    /// (`FlowControlLimited`), by the pacer (`PacingLimited`) or by the application that does
+    /// not provide data fast enough (`AppLimited`).
+    /// If multiple limits apply, the first one in this order is returned. The limits of the
+    /// default path are checked; the flow control of the single `Stream`s is not checked.
+    /// Before the `Connection` is polled by its `Context` the first time, `AppLimited` is
+    /// returned.
+    pub fn send_limitation(&self) -> SendLimit {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .send_limitation
+            .unwrap_or(SendLimit::AppLimited)
+    }
+
+    /// Returns the internal state of the congestion controller of this `Connection`, e.g. to
+    /// debug the behavior of BBR or Cubic without reading the sources of picoquic.
+    /// The state is updated each time the `Connection` is polled by its `Context`. If the
+    /// congestion algorithm does not report its state or the `Connection` was not polled yet,
+    /// `None` is returned.
+    pub fn congestion_debug(&self) -> Option<CongestionDebug> {
+        self.snapshot.lock().unwrap().congestion_debug.clone()
+    }
+
+    /// Returns the maximum size of the packets that are sent to the peer. The size is discovered
+    /// by picoquic, see `Config::enable_dont_fragment`.
+    pub fn path_mtu(&self) -> usize {
+        self.snapshot.lock().unwrap().path_mtu
+    }
+
+    /// Returns the number of packets of this `Connection` that are in flight on the default path,
+    /// i.e. that were sent, but are neither acknowledged nor declared as lost.
+    /// In contrast to the bytes in flight, this helps to see if a stall is caused by a few large
+    /// or by many small packets.
+    pub fn packets_in_flight(&self) -> u64 {
+        self.snapshot.lock().unwrap().packets_in_flight
+    }
+
+    /// Returns if this `Connection` has data that was not sent yet or was sent, but not
+    /// acknowledged by the peer yet. Closing the `Connection` discards this data, so a graceful
+    /// shutdown should wait until this returns `false`, before closing the `Connection`.
+    ///
+    /// The state is updated each time the `Context` polls the `Connection`, so data that was just
+    /// given to a `Stream` may not be reported yet.
+    pub fn has_unacked_data(&self) -> bool {
+        self.snapshot.lock().unwrap().has_unacked_data
+    }
+
+    /// Returns the number of bytes that are queued on all `Stream`s of this `Connection`, but were
+    /// not sent yet. A growing value indicates that the application produces data faster than
+    /// the network can drain it.
+    /// The data of a reader or a data provider is only counted, after it was given to picoquic.
+    pub fn total_send_queue_bytes(&self) -> u64 {
+        self.snapshot.lock().unwrap().send_queue_bytes
+    }
+
+    /// Returns the number of sent and received frames per frame type.
+    /// The frames are only counted, if `Config::enable_frame_stats` is set. Otherwise, empty
+    /// `FrameStats` are returned.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .frame_stats
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of packets that were retransmitted in the handshake.
+    /// A value greater than `0` indicates a lossy path, while the `Connection` was established.
+    /// Only the lost packets of the `Initial` and `Handshake` packet number spaces are counted,
+    /// the losses of 0-RTT and 1-RTT packets are not included.
+    pub fn handshake_retransmit_count(&self) -> u32 {
+        self.snapshot.lock().unwrap().handshake_retransmit_count
+    }
+
+    /// Returns if this `Connection` is healthy under the given `HealthPolicy`.
+    /// A `Connection` is healthy, if it is ready, not closed and does not exceed any threshold
+    /// of the policy.
+    ///
+    /// now - The current time in microseconds since the UNIX epoch.
+    pub fn is_healthy(&self, policy: &HealthPolicy, now: u64) -> bool {
+        self.snapshot.lock().unwrap().is_healthy(policy, now)
+    }
+
+    /// Returns the number of flights of packets that this `Connection` sent in the handshake.
+    /// A flight ends, when data from the peer is received. Servers with large certificate
+    /// chains need additional flights, if the certificates do not fit into the packets allowed
+    /// by the amplification limit (see `is_amplification_limited`) or by
+    /// `Config::set_max_handshake_packet_size`.
+    pub fn handshake_flight_count(&self) -> u32 {
+        self.snapshot.lock().unwrap().handshake_flight_count
+    }
+
+    /// Returns the number of bytes that the server is allowed to send, before the address of
+    /// the client is validated. This is limited to three times the number of bytes received
+    /// from the client (amplification limit).
+    /// If the limit does not apply (anymore), `None` is returned.
+    pub fn amplification_budget(&self) -> Option<u64> {
+        self.snapshot.lock().unwrap().amplification_budget
+    }
+
+    /// Returns if this `Connection` can not send a full sized packet, because of the
+    /// amplification limit. This can stall the handshake of servers with large certificate
+    /// chains, until the client sends more data.
+    pub fn is_amplification_limited(&self) -> bool {
+        self.amplification_budget()
+            .map(|b| b < u64::from(PICOQUIC_MAX_PACKET_SIZE))
+            .unwrap_or(false)
+    }
+
+    /// Returns the QUIC versions that the server offered in a version negotiation packet.
+    /// If the client did not receive a version negotiation packet, `None` is returned.
+    pub fn offered_versions(&self) -> Option<Vec<u32>> {
+        self.snapshot.lock().unwrap().offered_versions.clone()
+    }
+
+    /// Returns if the TLS handshake of this `Connection` resumed a prior session (PSK), instead
+    /// of doing a full handshake.
+    /// The value is only valid after the handshake finished; before, `false` is returned.
+    pub fn resumed(&self) -> bool {
+        self.snapshot.lock().unwrap().resumed
+    }
+
+    /// Returns if the session ticket that this client `Connection` resumed, allowed to send 0-RTT
+    /// data. A server issues session tickets that do not allow 0-RTT data, if it rejected the
+    /// 0-RTT data of the `Connection` the ticket was issued on, see
+    /// `Config::set_early_data_policy`.
+    pub fn early_data_allowed(&self) -> bool {
+        self.snapshot.lock().unwrap().early_data_allowed
+    }
+
+    /// Returns the certificates that were presented by the peer in the TLS handshake.
+    /// The first certificate is the certificate of the peer, followed by its chain.
+    /// A server only gets certificates from clients, if client authentication is enabled.
+    /// The certificates are only available, if a verify certificate handler is set and after
+    /// the handshake finished; otherwise `None` is returned.
+    pub fn peer_certificates(&self) -> Option<Vec<X509>> {
+        self.snapshot.lock().unwrap().peer_certificates.clone()
+    }
+
+    /// Returns the `State` of this `Connection`.
+    pub fn state(&self) -> State {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .state
+            .unwrap_or(State::Handshaking)
+    }
+
+    /// Returns the number of open `Stream`s per type and initiator.
+    /// A `Stream` is open, until both sides finished it or it was reset.
+    pub fn open_stream_counts(&self) -> StreamCounts {
+        self.snapshot.lock().unwrap().open_stream_counts
+    }
+
+    /// Returns the `max_udp_payload_size` that was advertised by the peer. This is the size of
+    /// the largest UDP payload, the peer is willing to receive. In contrast to the path MTU, this
+    /// value is not discovered, it is the limit stated by the peer.
+    /// Before the handshake finished, the default value of the transport parameter (`65527`) is
+    /// returned.
+    pub fn peer_max_udp_payload_size(&self) -> usize {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_max_udp_payload_size
+            .unwrap_or(DEFAULT_MAX_UDP_PAYLOAD_SIZE)
+    }
+
+    /// Returns the `max_ack_delay` that was advertised by the peer. This is the maximum time, the
+    /// peer delays sending an acknowledgement.
+    /// Before the handshake finished, the default value of the transport parameter (`25ms`) is
+    /// returned.
+    pub fn peer_max_ack_delay(&self) -> Duration {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_max_ack_delay
+            .unwrap_or_else(|| Duration::from_millis(DEFAULT_MAX_ACK_DELAY))
+    }
+
+    /// Returns the `ack_delay_exponent` that was advertised by the peer. The peer scales the ack
+    /// delay of its acknowledgements with this exponent.
+    /// Before the handshake finished, the default value of the transport parameter (`3`) is
+    /// returned.
+    pub fn peer_ack_delay_exponent(&self) -> u8 {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_ack_delay_exponent
+            .unwrap_or(DEFAULT_ACK_DELAY_EXPONENT)
+    }
+
+    /// Returns the extensions that were advertised by the peer in its transport parameters.
+    /// This can be used to enable features of the application conditionally.
+    /// Before the handshake finished, no extensions are returned.
+    pub fn peer_extensions(&self) -> PeerExtensions {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_extensions
+            .unwrap_or_default()
+    }
+
+    /// Returns the `idle_timeout` that was advertised by the peer. The effective idle timeout of
+    /// the `Connection` is the minimum of the idle timeouts of both peers. A value of `0` means
+    /// that the peer has no idle timeout.
+    /// The value is only known after the handshake finished; before, `None` is returned.
+    pub fn peer_idle_timeout(&self) -> Option<Duration> {
+        self.snapshot.lock().unwrap().peer_idle_timeout
+    }
+
+    /// Returns how many times the address of the peer changed, because of a NAT rebinding or
+    /// an explicit migration of the peer. Many migrations may indicate an unstable network of
+    /// the peer.
+    /// The address is checked each time the `Connection` is polled by its `Context`, so changes
+    /// in between are counted as one migration.
+    pub fn migration_count(&self) -> u32 {
+        self.snapshot.lock().unwrap().migration_count
+    }
+
+    /// Returns the number of `Stream`s that were reset by the peer (`RESET_STREAM` frames).
+    /// A peer that opens and immediately resets many `Stream`s may be abusive, so the counter
+    /// can be used to rate-limit or close misbehaving peers.
+    pub fn peer_reset_count(&self) -> u64 {
+        self.snapshot.lock().unwrap().peer_reset_count
+    }
+
+    /// Returns the number of `Stream`s, where the peer asked to stop sending (`STOP_SENDING`
+    /// frames).
+    pub fn peer_stop_sending_count(&self) -> u64 {
+        self.snapshot.lock().unwrap().peer_stop_sending_count
+    }
+
+    /// Returns the custom transport parameters of the peer (see
+    /// `Config::add_custom_transport_parameter`), as id and value. This includes all transport
+    /// parameters that are unknown to picoquic.
+    /// Before the handshake finished, no parameters are returned.
+    pub fn peer_custom_transport_parameters(&self) -> Vec<(u64, Vec<u8>)> {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_custom_transport_parameters
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Returns the reason why this `Connection` was disconnected.
+    /// As long as the `Connection` is not disconnected, `None` is returned.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.snapshot.lock().unwrap().disconnect_reason
+    }
+
+    /// Returns all error codes and the reason phrase that were exchanged, when this `Connection`
+    /// was closed. In contrast to the `Error` that is returned by the `Connection`, which only
+    /// reports one error code, the local and the remote codes of the transport and the
+    /// application are reported separately, together with the frame type that triggered a
+    /// transport error.
+    /// As long as the `Connection` is not closed, `None` is returned.
+    pub fn close_info(&self) -> Option<CloseInfo> {
+        self.snapshot.lock().unwrap().close_info.clone()
+    }
+
+    /// Returns the TLS cipher suite that was negotiated in the handshake.
+    /// This can be used to log or to enforce the cipher suite of a `Connection`.
+    /// Before the handshake finished, `None` is returned.
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.snapshot.lock().unwrap().cipher_suite
+    }
+
+    /// Returns the `active_connection_id_limit` that was advertised by the peer. This is the
+    /// maximum number of connection ids, this side can provide to the peer.
+    /// The value is only known after the handshake finished; before, `None` is returned.
+    pub fn peer_active_connection_id_limit(&self) -> Option<u32> {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .peer_active_connection_id_limit
+    }
 }
 
 impl FStream for Connection {
@@ -119,43 +1164,55 @@ impl FStream for Connection {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match try_ready!(
-            self.msg_recv
-                .poll()
-                .map_err(|_| Error::from(ErrorKind::Unknown))
-        ) {
-            Some(Message::Close) | None => Ok(Ready(None)),
-            Some(Message::NewStream(s)) => Ok(Ready(Some(s))),
-            Some(Message::Error(e)) => Err(e),
-        }
+        poll_new_stream(&mut self.msg_recv)
+    }
+}
+
+/// Polls the given receiver for the next `Stream` that was opened by the peer.
+fn poll_new_stream(msg_recv: &mut UnboundedReceiver<Message>) -> Poll<Option<Stream>, Error> {
+    match try_ready!(msg_recv
+        .poll()
+        .map_err(|_| Error::from(ErrorKind::Unknown)))
+    {
+        Some(Message::Close) | None => Ok(Ready(None)),
+        Some(Message::NewStream(s)) => Ok(Ready(Some(s))),
+        Some(Message::Error(e)) => Err(e),
     }
 }
 
 impl Connection {
     /// Creates a new `Connection` from an incoming connection.
     pub(crate) fn from_incoming(
-        cnx: *mut picoquic_cnx_t,
+        cnx: ffi::SharedConnection,
         stream_id: stream::Id,
         data: *mut u8,
         len: usize,
         event: picoquic_call_back_event_t,
         keep_alive_interval: Option<Duration>,
+        heartbeat: bool,
     ) -> (Connection, Rc<RefCell<Context>>) {
-        let cnx = ffi::Connection::from(cnx);
+        cnx.get().adopt_state();
 
+        let peer_addr = cnx.get().peer_addr();
+        let local_addr = cnx.get().local_addr();
         let (builder, ctx, c_ctx) = Self::create_builder(
-            cnx,
-            cnx.peer_addr(),
-            cnx.local_addr(),
+            cnx.clone(),
+            peer_addr,
+            local_addr,
             false,
             keep_alive_interval,
         );
 
-        let con = builder.build(cnx.local_id());
+        // The received data could already be a heartbeat.
+        if heartbeat {
+            ctx.borrow_mut().enable_heartbeat();
+        }
+
+        let con = builder.build(cnx.get().local_id());
 
         // Now we need to call the callback once manually to process the received data
         unsafe {
-            recv_data_callback(cnx.as_ptr(), stream_id, data, len, event, c_ctx);
+            recv_data_callback(cnx.get().as_ptr(), stream_id, data, len, event, c_ctx);
         }
 
         (con, ctx)
@@ -163,18 +1220,19 @@ impl Connection {
 
     /// Creates a new `Connection` to the given `peer_addr` server.
     pub(crate) fn new(
-        quic: &QuicCtx,
+        quic: &Rc<QuicCtx>,
         peer_addr: SocketAddr,
         local_addr: SocketAddr,
         server_name: String,
+        idle_timeout: Option<Duration>,
         current_time: u64,
-        keep_alive_interval: Option<Duration>,
         created_sender: oneshot::Sender<Result<Connection, Error>>,
     ) -> Result<(Rc<RefCell<Context>>), Error> {
-        let cnx = ffi::Connection::new(quic, peer_addr, current_time, server_name)?;
+        let cnx = ffi::Connection::new(quic, peer_addr, current_time, server_name, idle_timeout)?;
+        let cnx = ffi::SharedConnection::new(quic.clone(), cnx);
 
         let (builder, ctx, _) =
-            Self::create_builder(cnx, peer_addr, local_addr, true, keep_alive_interval);
+            Self::create_builder(cnx, Some(peer_addr), Some(local_addr), true, None);
 
         // set the builder and the sender as waiting for ready state payload
         ctx.borrow_mut()
@@ -184,30 +1242,51 @@ impl Connection {
     }
 
     fn create_builder(
-        cnx: ffi::Connection,
-        peer_addr: SocketAddr,
-        local_addr: SocketAddr,
+        cnx: ffi::SharedConnection,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
         is_client: bool,
         keep_alive_interval: Option<Duration>,
     ) -> (ConnectionBuilder, Rc<RefCell<Context>>, *mut c_void) {
         let (sender, msg_recv) = unbounded();
         let (close_send, close_recv) = oneshot::channel();
 
-        let (ctx, c_ctx, new_stream_handle) =
-            Context::new(cnx, sender, close_recv, is_client, local_addr);
+        let (cmd_send, cmd_recv) = unbounded();
+
+        // Incoming connections are created after the handshake, so the certificates are
+        // already known.
+        let snapshot = Arc::new(Mutex::new(Snapshot {
+            peer_certificates: cnx.get().peer_certificates(),
+            local_id_bytes: cnx.get().local_id_bytes(),
+            remote_id_bytes: cnx.get().remote_id_bytes(),
+            ..Default::default()
+        }));
+
+        let (ctx, c_ctx, new_stream_handle) = Context::new(
+            cnx.clone(),
+            sender,
+            close_recv,
+            cmd_recv,
+            is_client,
+            local_addr,
+            snapshot.clone(),
+        );
 
         if let Some(interval) = keep_alive_interval {
-            cnx.enable_keep_alive(interval);
+            cnx.get().enable_keep_alive(interval);
         }
 
-        let builder = ConnectionBuilder::new(
+        let builder = ConnectionBuilder {
             msg_recv,
             close_send,
+            cmd_send,
             peer_addr,
             local_addr,
             new_stream_handle,
-            cnx.con_type(),
-        );
+            ctype: cnx.get().con_type(),
+            snapshot,
+            stable_id: NEXT_STABLE_ID.fetch_add(1, Ordering::SeqCst) as u64,
+        };
 
         (builder, ctx, c_ctx)
     }
@@ -227,21 +1306,355 @@ impl Connection {
         self.new_stream_handle.clone()
     }
 
-    /// Immediately closes this connection.
-    /// Any buffered data will be discarded.
-    /// This function should only be used, if the application layer negotiated a close of the
-    /// connection.
-    pub fn close_immediately(mut self) {
-        self.close_send.take().map(|s| s.send(()));
+    /// Pauses the delivery of received data to the `Stream` with the given id.
+    /// While a `Stream` is paused, no new flow control credit is granted to the peer, so the peer
+    /// gets blocked after sending the data it was already allowed to send. This data is buffered
+    /// and will be delivered after calling `resume_stream`. In contrast to stop sending, the
+    /// `Stream` is not aborted.
+    ///
+    /// The future fails with `ErrorKind::UnknownStream`, if the `Stream` does not exist.
+    pub fn pause_stream(&self, id: stream::Id) -> PauseStreamFuture {
+        let (send, recv) = oneshot::channel();
+        let _ = self.cmd_send.unbounded_send(Command::PauseStream(id, send));
+        PauseStreamFuture { recv }
+    }
+
+    /// Resumes the delivery of received data to the `Stream` with the given id.
+    /// All data that was buffered while the `Stream` was paused, is delivered and the peer gets
+    /// new flow control credit.
+    ///
+    /// The future fails with `ErrorKind::UnknownStream`, if the `Stream` does not exist.
+    pub fn resume_stream(&self, id: stream::Id) -> PauseStreamFuture {
+        let (send, recv) = oneshot::channel();
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::ResumeStream(id, send));
+        PauseStreamFuture { recv }
+    }
+
+    /// Returns a stream of `Event`s that are reported by this connection.
+    /// Only `Event`s that occur after calling this function are returned.
+    ///
+    /// A blocked peer is detected by checking if the peer used up all the flow control credit
+    /// that we granted, as picoquic does not report the received `DATA_BLOCKED` and
+    /// `STREAM_DATA_BLOCKED` frames. Each blocked state is reported once, until the peer got new
+    /// credit.
+    pub fn events(&self) -> Events {
+        let (send, recv) = unbounded();
+        let _ = self.cmd_send.unbounded_send(Command::SubscribeEvents(send));
+        Events { recv }
+    }
+
+    /// Marks the sent packet with the given packet number as lost, so picoquic's loss handling
+    /// retransmits the data of the packet. If no packet with the given number is in flight,
+    /// nothing happens.
+    /// This function is only intended for testing the loss recovery and requires the
+    /// `loss-injection` feature.
+    #[cfg(feature = "loss-injection")]
+    pub fn mark_packet_lost(&self, packet_number: u64) {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::MarkPacketLost(packet_number));
+    }
+
+    /// Sends all data of the given reader on the `Stream` with the given id, followed by a `FIN`.
+    /// The returned future resolves, when all data was sent to the peer (not necessarily
+    /// acknowledged by the peer).
+    /// The reader is only read as fast as the flow and congestion control allows, the sending is
+    /// continued when the `Connection` can send data again. So, the data is never buffered
+    /// completely in memory.
+    /// The reader is read in the event loop of the `Context`, so it should not block for a long
+    /// time (e.g. a file is okay, but not a socket in blocking mode). A reader that returns
+    /// `io::ErrorKind::WouldBlock` is read again at the next wake up of the `Connection`.
+    ///
+    /// The `Stream` should not be used to send other data until the returned future resolved.
+    /// Dropping the `Stream` does not abort the sending, use `Stream::reset` for that.
+    ///
+    /// # Errors
+    /// The future fails with `ErrorKind::ReaderError` if the reader failed (the `Stream` is reset
+    /// in that case), with `ErrorKind::UnknownStream` if the `Stream` does not exist and
+    /// otherwise with the error of the `Connection` or the `Stream`.
+    pub fn send_stream_from_reader<R: Read + Send + 'static>(
+        &self,
+        id: stream::Id,
+        reader: R,
+    ) -> SendFromReaderFuture {
+        self.send_stream_from_reader_impl(id, reader, None)
+    }
+
+    /// Sends all data of the given reader on the `Stream` with the given id, followed by a `FIN`,
+    /// like `send_stream_from_reader`.
+    /// If not all data could be sent before the given `timeout` elapsed (e.g. because the peer
+    /// does not acknowledge the data or is blocked by the flow control), the `Stream` is reset
+    /// and the future fails with `ErrorKind::Timeout`. In contrast to the idle timeout, the
+    /// timeout also fires, if the `Connection` is still alive.
+    /// The deadline is checked, each time the `Connection` wakes up. So, the future may fail
+    /// slightly after the timeout elapsed.
+    pub fn send_stream_from_reader_with_timeout<R: Read + Send + 'static>(
+        &self,
+        id: stream::Id,
+        reader: R,
+        timeout: Duration,
+    ) -> SendFromReaderFuture {
+        self.send_stream_from_reader_impl(id, reader, Some(Instant::now() + timeout))
+    }
+
+    /// Sets the provider that produces the data of the `Stream` with the given id on demand.
+    /// Instead of pushing all data into the send buffer up front, picoquic calls the provider
+    /// whenever it has room to send data on the `Stream`. The provider writes up to
+    /// `buffer.len()` bytes into the given buffer and returns the number of written bytes and
+    /// if all data was provided. After the provider returned `true`, the `Stream` is finished
+    /// with a `FIN` and the provider is dropped. So, generated or infinite content does not need
+    /// to be buffered in memory.
+    /// A provider that returns `0` bytes without finishing the `Stream` is not called again
+    /// immediately, but the next time the `Context` wakes up, for example for a timer or an
+    /// incoming packet. To get data sent that became available in the meantime, the provider can
+    /// be set again.
+    ///
+    /// The provider is called in the event loop of the `Context`, so it should not block.
+    /// The `Stream` should not be used to send other data, while the provider is set. Dropping
+    /// the `Stream` does not abort the sending, use `Stream::reset` for that.
+    /// If the `Stream` does not exist or can not send data, the provider is dropped.
+    pub fn set_stream_data_provider<F>(&self, id: stream::Id, provider: F)
+    where
+        F: FnMut(&mut [u8]) -> (usize, bool) + Send + 'static,
+    {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::SetStreamDataProvider(id, Box::new(provider)));
+    }
+
+    fn send_stream_from_reader_impl<R: Read + Send + 'static>(
+        &self,
+        id: stream::Id,
+        reader: R,
+        deadline: Option<Instant>,
+    ) -> SendFromReaderFuture {
+        let (send, recv) = oneshot::channel();
+        let _ = self.cmd_send.unbounded_send(Command::SendFromReader(
+            id,
+            Box::new(reader),
+            deadline,
+            send,
+        ));
+        SendFromReaderFuture { recv }
+    }
+
+    /// Resets all open `Stream`s of this `Connection` with the given application error code.
+    /// The sending side of each `Stream` is reset with a `RESET_STREAM` frame and the receiving
+    /// side is aborted with a `STOP_SENDING` frame, as far as the direction of the `Stream`
+    /// allows it. The local `Stream`s return `None` and report `Stream::is_reset`.
+    /// This can be used before `close`, to give peers that track a state per `Stream` a clean
+    /// signal for each `Stream`.
+    pub fn reset_all_streams(&self, error_code: u64) {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::ResetAllStreams(error_code));
+    }
+
+    /// Sends a heartbeat to the peer, that is reported as `Event::Heartbeat` to the peer
+    /// application.
+    /// In contrast to the keep alive (see `Config::enable_keep_alive`), which sends QUIC `PING`
+    /// frames that are never seen by the peer application, the heartbeat can be used to
+    /// implement a liveness check at the application layer.
+    /// The heartbeats are sent on a reserved unidirectional `Stream`, so both peers need to
+    /// enable them with `Config::enable_heartbeat`. Otherwise, nothing is sent.
+    pub fn send_heartbeat(&self) {
+        let _ = self.cmd_send.unbounded_send(Command::SendHeartbeat);
+    }
+
+    /// Sets the weight of this connection in the send scheduler of its `Context` (default `1`).
+    /// The connections of a `Context` are served in rounds and in each round, a connection may
+    /// send as many packets as its weight. So, if all connections have data to send, the
+    /// available bandwidth is shared proportionally to the weights. A weight of `0` is treated
+    /// as `1`.
+    ///
+    /// The weights only apply to the connections of the same `Context`, picoquic does not
+    /// schedule packets across multiple `Context`s.
+    pub fn set_weight(&self, weight: u32) {
+        let _ = self.cmd_send.unbounded_send(Command::SetWeight(weight));
+    }
+
+    /// Sets the congestion control algorithm of this `Connection` (default `NewReno`). The
+    /// algorithm can be switched at any time, e.g. to compare the algorithms on the same path.
+    ///
+    /// Switching the algorithm resets the state of the congestion controller. The congestion
+    /// window starts again at the initial window, so the `Connection` needs to ramp up again.
+    pub fn set_congestion_algorithm(&self, algorithm: CongestionAlgorithm) {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::SetCongestionAlgorithm(algorithm));
+    }
+
+    /// Returns the congestion control algorithm that is used by this `Connection`, see
+    /// `set_congestion_algorithm`.
+    /// If picoquic uses an algorithm that is not a `CongestionAlgorithm`, `None` is returned.
+    pub fn congestion_algorithm(&self) -> Option<CongestionAlgorithm> {
+        self.snapshot.lock().unwrap().congestion_algorithm
+    }
+
+    /// Returns the number of packets this `Connection` received per ECN codepoint.
+    /// The packets are only counted, if `Config::enable_ecn_marks` is set. The counts are
+    /// collected per packet, so a single `Stream` frame can not be mapped to the codepoint of the
+    /// packet that carried it. A growing `ce` count tells the application about congestion on the
+    /// path, before packets are lost.
+    pub fn received_ecn_counts(&self) -> EcnCounts {
+        self.snapshot.lock().unwrap().received_ecn_counts
+    }
+
+    /// Sends an acknowledgement for the received packets with the next packet, instead of
+    /// delaying it up to the `max_ack_delay`. This gives the peer a tight RTT sample for the data
+    /// that was just received, or deterministic ack timing in tests.
+    /// If all received packets were already acknowledged, the last acknowledgement is repeated.
+    pub fn send_ack_now(&self) {
+        let _ = self.cmd_send.unbounded_send(Command::SendAckNow);
+    }
+
+    /// Sets a human-readable label of this `Connection` (e.g. the id of the authenticated
+    /// user), that is included in the log lines of this crate and in the `QuicEvent`s of this
+    /// `Connection`. This helps to follow the logs of a server with many connections, that are
+    /// otherwise only keyed by the connection id.
+    /// A previously set label is replaced. The events that were reported before the label was
+    /// set, are reported without label.
+    pub fn set_label<T: Into<String>>(&self, label: T) {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::SetLabel(label.into()));
+    }
+
+    /// Returns the label of this `Connection`, see `set_label`.
+    pub fn label(&self) -> Option<String> {
+        self.snapshot.lock().unwrap().label.clone()
+    }
+
+    /// Sets the callback that is called, when the congestion window of this `Connection` was
+    /// reduced because of lost packets or ECN-CE marks. This gives the application an early
+    /// signal to adapt (e.g. to lower the quality of a video), before the buffers drain.
+    /// A previously set callback is replaced.
+    ///
+    /// A reduction is only noticed, when the `Connection` is polled by its `Context` (e.g. after
+    /// packets were received or a timer expired), so multiple reductions in between are
+    /// reported as one `CongestionEvent`. ECN-CE marks are only counted, if
+    /// `Config::enable_ecn_marks` is set. Reductions without loss or ECN-CE marks (e.g. after
+    /// the `Connection` was idle) are not reported.
+    /// The callback is called on the thread of the `Context`.
+    pub fn set_congestion_event_callback<F>(&self, callback: F)
+    where
+        F: Fn(CongestionEvent) + Send + 'static,
+    {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::SetCongestionEventCallback(Box::new(callback)));
+    }
+
+    /// Sets the callback that is called with the new smoothed RTT of this `Connection`, when the
+    /// RTT changed by more than `threshold` since the last call of the callback (or since the
+    /// callback was set). This gives real-time applications a timely signal to resize their
+    /// jitter buffers, without polling `rtt`.
+    /// A previously set callback is replaced.
+    ///
+    /// The RTT is checked each time the `Connection` is polled by its `Context`, so slow drifts
+    /// are reported, once they add up to more than `threshold`.
+    /// The callback is called on the thread of the `Context`.
+    pub fn set_rtt_change_callback<F>(&self, threshold: Duration, callback: F)
+    where
+        F: Fn(Duration) + Send + 'static,
+    {
+        let _ = self
+            .cmd_send
+            .unbounded_send(Command::SetRttChangeCallback(threshold, Box::new(callback)));
+    }
+
+    /// Immediately closes this connection.
+    /// Any buffered data will be discarded.
+    /// This function should only be used, if the application layer negotiated a close of the
+    /// connection.
+    pub fn close_immediately(mut self) {
+        self.close_send.take().map(|s| s.send((0, String::new())));
+    }
+
+    /// Immediately closes this connection with the given application error code and reason
+    /// phrase, that are reported to the peer, see `CloseInfo`.
+    /// Any buffered data will be discarded, like with `close_immediately`.
+    pub fn close_with_error(mut self, error_code: u16, reason: &str) {
+        self.close_send
+            .take()
+            .map(|s| s.send((error_code, reason.to_owned())));
+    }
+
+    /// Splits this `Connection` into a `ReadHalf` and a `WriteHalf`, that can be moved to
+    /// different tasks or threads.
+    /// The `ReadHalf` returns the `Stream`s that are opened by the peer, while the `WriteHalf`
+    /// provides all other functions of the `Connection`. Both halves communicate over channels
+    /// with the `Context` of the `Connection`, so they do not need any further synchronization.
+    pub fn split(mut self) -> (ReadHalf, WriteHalf) {
+        let (_, closed_recv) = unbounded();
+        let msg_recv = mem::replace(&mut self.msg_recv, closed_recv);
+
+        (ReadHalf { msg_recv }, WriteHalf { con: self })
+    }
+}
+
+/// The reading half of a `Connection`, that returns the `Stream`s that are opened by the peer.
+/// This half is created by `Connection::split`.
+pub struct ReadHalf {
+    msg_recv: UnboundedReceiver<Message>,
+}
+
+impl FStream for ReadHalf {
+    type Item = Stream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        poll_new_stream(&mut self.msg_recv)
+    }
+}
+
+/// The writing half of a `Connection`, that opens new `Stream`s and controls the `Connection`.
+/// All functions of the `Connection`, besides polling for new `Stream`s, are available through
+/// `Deref`. This half is created by `Connection::split`.
+pub struct WriteHalf {
+    con: Connection,
+}
+
+impl WriteHalf {
+    /// Creates a new bidirectional `Stream`.
+    pub fn new_bidirectional_stream(&mut self) -> NewStreamFuture {
+        self.con.new_bidirectional_stream()
+    }
+
+    /// Creates a new unidirectional `Stream`.
+    pub fn new_unidirectional_stream(&mut self) -> NewStreamFuture {
+        self.con.new_unidirectional_stream()
+    }
+
+    /// Immediately closes the `Connection`, see `Connection::close_immediately`.
+    pub fn close_immediately(self) {
+        self.con.close_immediately()
+    }
+
+    /// Immediately closes the `Connection` with the given application error, see
+    /// `Connection::close_with_error`.
+    pub fn close_with_error(self, error_code: u16, reason: &str) {
+        self.con.close_with_error(error_code, reason)
+    }
+}
+
+impl Deref for WriteHalf {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.con
     }
 }
 
 pub(crate) struct Context {
     send_msg: UnboundedSender<Message>,
-    close_recv: oneshot::Receiver<()>,
+    close_recv: oneshot::Receiver<(u16, String)>,
+    cmd_recv: UnboundedReceiver<Command>,
     recv_create_stream: UnboundedReceiver<(stream::Type, oneshot::Sender<Result<Stream, Error>>)>,
     streams: HashMap<stream::Id, stream::Context>,
-    cnx: ffi::Connection,
+    cnx: ffi::SharedConnection,
     closed: bool,
     /// Is the connection initiated by us?
     is_client: bool,
@@ -253,16 +1666,54 @@ pub(crate) struct Context {
         ConnectionBuilder,
         oneshot::Sender<Result<Connection, Error>>,
     )>,
-    local_addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    event_subscribers: Vec<UnboundedSender<Event>>,
+    /// Is the peer blocked by the connection level flow control?
+    peer_data_blocked: bool,
+    handshake_confirmed: bool,
+    /// Are heartbeats enabled?
+    heartbeat: bool,
+    /// The last known address of the peer.
+    peer_addr: Option<SocketAddr>,
+    /// The point in time, until the handshake needs to be finished.
+    handshake_deadline: Option<Instant>,
+    peer_address_change: Option<PeerAddressChangeHandler>,
+    /// The maximum number of received bytes that were not read yet by the `Stream`s.
+    max_recv_buffer: Option<(usize, OverflowPolicy)>,
+    congestion_event_callback: Option<CongestionEventCallback>,
+    /// The congestion window, the number of lost packets and the number of received ECN-CE
+    /// marks at the last call of `check_congestion`.
+    congestion_state: (u64, u64, u64),
+    /// Is the flow control of the `Stream`s paused, because they buffer more than
+    /// `max_recv_buffer`?
+    recv_buffer_full: bool,
+    /// Is notified, when a `Stream` read received data.
+    read_task: Arc<AtomicTask>,
+    /// The number of bytes that were given to picoquic on all `Stream`s of this connection.
+    /// The bytes that picoquic did not send yet, are the difference to the sent stream bytes.
+    given_stream_bytes: Rc<Cell<u64>>,
+    /// The threshold and the callback of `Connection::set_rtt_change_callback`.
+    rtt_change_callback: Option<(Duration, RttChangeCallback)>,
+    /// The RTT at the last call of the RTT change callback.
+    reported_rtt: Duration,
+    /// The path MTU at the last call of `check_path_mtu`.
+    path_mtu: usize,
+    /// The pool the map of the `streams` is returned to, when this `Context` is dropped.
+    pool: Option<PoolHandle>,
+    /// The application error code and the reason phrase of a local close.
+    local_close_error: Option<(u16, String)>,
 }
 
 impl Context {
     fn new(
-        cnx: ffi::Connection,
+        cnx: ffi::SharedConnection,
         send_msg: UnboundedSender<Message>,
-        close_recv: oneshot::Receiver<()>,
+        close_recv: oneshot::Receiver<(u16, String)>,
+        cmd_recv: UnboundedReceiver<Command>,
         is_client: bool,
-        local_addr: SocketAddr,
+        local_addr: Option<SocketAddr>,
+        snapshot: Arc<Mutex<Snapshot>>,
     ) -> (Rc<RefCell<Context>>, *mut c_void, NewStreamHandle) {
         let (send_create_stream, recv_create_stream) = unbounded();
 
@@ -273,7 +1724,7 @@ impl Context {
         let ctx = Rc::new(RefCell::new(Context {
             send_msg,
             streams: Default::default(),
-            cnx,
+            cnx: cnx.clone(),
             closed: false,
             recv_create_stream,
             is_client,
@@ -281,13 +1732,33 @@ impl Context {
             wait_for_ready_state: None,
             local_addr,
             close_recv,
+            cmd_recv,
+            snapshot,
+            event_subscribers: Vec::new(),
+            peer_data_blocked: false,
+            handshake_confirmed: false,
+            heartbeat: false,
+            peer_addr: cnx.get().peer_addr(),
+            peer_address_change: None,
+            max_recv_buffer: None,
+            recv_buffer_full: false,
+            read_task: Arc::new(AtomicTask::new()),
+            given_stream_bytes: Rc::new(Cell::new(0)),
+            handshake_deadline: None,
+            congestion_event_callback: None,
+            congestion_state: (0, 0, 0),
+            rtt_change_callback: None,
+            reported_rtt: Duration::from_millis(0),
+            path_mtu: 0,
+            pool: None,
+            local_close_error: None,
         }));
 
         // Convert the `Context` to a `*mut c_void` and reset the callback to the
         // `recv_data_callback`
         let c_ctx = unsafe {
             let c_ctx = Rc::into_raw(ctx.clone()) as *mut c_void;
-            picoquic_set_callback(cnx.as_ptr(), Some(recv_data_callback), c_ctx);
+            picoquic_set_callback(cnx.get().as_ptr(), Some(recv_data_callback), c_ctx);
             c_ctx
         };
 
@@ -297,26 +1768,254 @@ impl Context {
         (ctx, c_ctx, new_stream_handle)
     }
 
+    /// Asks the data provider of the given `Stream` for data to send.
+    fn prepare_to_send(&mut self, id: stream::Id, context: *mut c_void, space: usize) {
+        match self.streams.get_mut(&id) {
+            Some(stream) => stream.provide_data(context, space),
+            None => stream::provide_no_data(context),
+        }
+    }
+
     fn recv_data(&mut self, id: stream::Id, data: &[u8], event: picoquic_call_back_event_t) {
-        let new_stream_handle = match self.streams.entry(id) {
-            Occupied(mut entry) => {
-                entry.get_mut().recv_data(data, event);
-                None
+        if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_reset {
+            self.snapshot.lock().unwrap().peer_reset_count += 1;
+        } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stop_sending {
+            self.snapshot.lock().unwrap().peer_stop_sending_count += 1;
+        }
+
+        if self.heartbeat && id == heartbeat_stream_id(!self.is_client) {
+            // Each byte on the heartbeat stream is one heartbeat.
+            data.iter().for_each(|_| self.emit_event(Event::Heartbeat));
+            return;
+        }
+
+        if !self.streams.contains_key(&id) {
+            let (stream, ctx) = Stream::new(
+                id,
+                self.cnx.clone(),
+                self.peer_addr,
+                self.local_addr,
+                self.is_client,
+                self.read_task.clone(),
+                self.given_stream_bytes.clone(),
+            );
+
+            if self.recv_buffer_full {
+                let _ = self.cnx.get().set_stream_flow_control_paused(id, true);
+            }
+
+            self.streams.insert(id, ctx);
+            self.emit_event(Event::NewStream(id, stream.get_type()));
+            let _ = self.send_msg.unbounded_send(Message::NewStream(stream));
+        }
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.recv_data(data, event);
+        }
+    }
+
+    /// Handles a version negotiation packet with the given list of versions.
+    fn handle_version_negotiation(&mut self, data: &[u8]) {
+        let versions = parse_versions(data);
+
+        self.snapshot.lock().unwrap().offered_versions = Some(versions.clone());
+        self.emit_event(Event::VersionNegotiation(versions));
+    }
+
+    /// Enables the sending and receiving of heartbeats.
+    pub fn enable_heartbeat(&mut self) {
+        self.heartbeat = true;
+    }
+
+    /// Sets the maximum number of received bytes that were not read yet by the `Stream`s.
+    pub fn set_max_recv_buffer(&mut self, bytes: usize, policy: OverflowPolicy) {
+        self.max_recv_buffer = Some((bytes, policy));
+    }
+
+    /// Checks if the `Stream`s buffer more received bytes than allowed. If the policy is
+    /// `OverflowPolicy::Close`, the connection is closed with `FLOW_CONTROL_ERROR` in this case.
+    /// Otherwise, the flow control of all `Stream`s is paused, until the `Stream`s read enough
+    /// data.
+    fn check_recv_buffer(&mut self) {
+        let (max, policy) = match self.max_recv_buffer {
+            Some(max_recv_buffer) => max_recv_buffer,
+            None => return,
+        };
+
+        let unread = self
+            .streams
+            .values()
+            .map(|s| s.unread_bytes())
+            .sum::<usize>();
+
+        match policy {
+            OverflowPolicy::Close => {
+                if unread > max {
+                    self.streams.values_mut().for_each(|s| {
+                        s.handle_connection_error(&|| ErrorKind::RecvBufferOverflow.into())
+                    });
+                    let _ = self
+                        .send_msg
+                        .unbounded_send(Message::Error(ErrorKind::RecvBufferOverflow.into()));
+
+                    self.cnx.get().close_with_flow_control_error();
+                    self.close(DisconnectReason::LocalError);
+                }
+            }
+            OverflowPolicy::Backpressure => {
+                // Wake up, when a `Stream` read data, to resume the flow control in time.
+                self.read_task.register();
+
+                let full = unread > max;
+                if full == self.recv_buffer_full {
+                    return;
+                }
+
+                self.recv_buffer_full = full;
+                let cnx = self.cnx.get();
+                self.streams
+                    .iter()
+                    .filter(|(_, s)| !s.is_paused())
+                    .for_each(|(id, _)| {
+                        let _ = cnx.set_stream_flow_control_paused(*id, full);
+                    });
             }
-            Vacant(entry) => {
-                let (stream, mut ctx) = Stream::new(id, self.cnx, self.local_addr, self.is_client);
+        }
+    }
+
+    /// Sets the time after which the handshake is aborted.
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_deadline = Some(Instant::now() + timeout);
+    }
+
+    /// Checks if the handshake did not finish before the handshake deadline.
+    /// In this case, the connection is closed.
+    fn check_handshake_timeout(&mut self) {
+        let deadline = match self.handshake_deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+
+        if self.cnx.get().is_ready() {
+            self.handshake_deadline = None;
+        } else if deadline <= Instant::now() {
+            self.handshake_deadline = None;
 
-                ctx.recv_data(data, event);
-                entry.insert(ctx);
-                Some(stream)
+            if let Some((_, send)) = self.wait_for_ready_state.take() {
+                let _ = send.send(Err(ErrorKind::HandshakeTimeout.into()));
             }
+
+            self.close(DisconnectReason::Timeout);
+        }
+    }
+
+    /// Sets the handler that is called, when the address of the peer changed.
+    pub fn set_peer_address_change_handler(&mut self, handler: PeerAddressChangeHandler) {
+        self.peer_address_change = Some(handler);
+    }
+
+    /// Sets the pool that provides the map of the `Stream`s, that was allocated upfront.
+    pub fn set_pool(&mut self, pool: PoolHandle) {
+        let mut streams = pool.borrow_mut().take_stream_map();
+        streams.extend(self.streams.drain());
+        self.streams = streams;
+        self.pool = Some(pool);
+    }
+
+    /// Checks if the address of the peer changed since the last call of this function.
+    /// If the handler vetoes the change, the connection is closed.
+    fn check_peer_address_change(&mut self) {
+        let peer_addr = match self.cnx.get().peer_addr() {
+            Some(addr) => addr,
+            None => return,
         };
 
-        if let Some(stream) = new_stream_handle {
-            let _ = self.send_msg.unbounded_send(Message::NewStream(stream));
+        let old = match self.peer_addr.replace(peer_addr) {
+            Some(old) if old != peer_addr => old,
+            // Learning the first address of the peer is no migration.
+            _ => return,
+        };
+        self.snapshot.lock().unwrap().migration_count += 1;
+
+        let res = match self.peer_address_change {
+            Some(ref handler) => handler.borrow_mut().on_peer_address_change(
+                self.cnx.get().local_id(),
+                old,
+                peer_addr,
+            ),
+            None => Ok(()),
+        };
+
+        if let Err(err) = res {
+            match self.wait_for_ready_state.take() {
+                Some((_, send)) => {
+                    let _ = send.send(Err(err));
+                }
+                None => {
+                    let _ = self.send_msg.unbounded_send(Message::Error(err));
+                }
+            }
+
+            self.close(DisconnectReason::LocalError);
+        }
+    }
+
+    /// Checks if the congestion window was reduced because of lost packets or ECN-CE marks since
+    /// the last call of this function and calls the congestion event callback.
+    fn check_congestion(&mut self) {
+        let (old_cwnd, old_lost, old_ce) = self.congestion_state;
+        let cwnd = self.cnx.get().congestion_window();
+        let lost = self.cnx.get().packets_lost();
+        let ce = self.cnx.get().received_ecn_counts().ce;
+        self.congestion_state = (cwnd, lost, ce);
+
+        if cwnd >= old_cwnd {
+            return;
+        }
+
+        let cause = if ce > old_ce {
+            CongestionCause::Ecn
+        } else if lost > old_lost {
+            CongestionCause::Loss
+        } else {
+            return;
+        };
+
+        if let Some(ref callback) = self.congestion_event_callback {
+            callback(CongestionEvent { cwnd, cause });
+        }
+    }
+
+    /// Checks if the RTT changed by more than the threshold since the last call of the RTT change
+    /// callback and calls the callback.
+    fn check_rtt_change(&mut self) {
+        if let Some((threshold, ref callback)) = self.rtt_change_callback {
+            let rtt = self.cnx.get().rtt();
+            let change = cmp::max(rtt, self.reported_rtt) - cmp::min(rtt, self.reported_rtt);
+
+            if change > threshold {
+                self.reported_rtt = rtt;
+                callback(rtt);
+            }
+        }
+    }
+
+    /// Sends a heartbeat on the heartbeat stream.
+    fn send_heartbeat(&mut self) {
+        if self.heartbeat {
+            self.cnx
+                .get()
+                .add_to_stream(heartbeat_stream_id(self.is_client), &[HEARTBEAT]);
+            self.given_stream_bytes
+                .set(self.given_stream_bytes.get() + 1);
         }
     }
 
+    /// Enables the keep alive with the given interval.
+    pub fn enable_keep_alive(&self, interval: Duration) {
+        self.cnx.get().enable_keep_alive(interval);
+    }
+
     /// Check for new streams to create and create these requested streams.
     fn check_create_stream_requests(&mut self) {
         loop {
@@ -330,7 +2029,15 @@ impl Context {
                     );
                     self.next_stream_id += 1;
 
-                    let (stream, ctx) = Stream::new(id, self.cnx, self.local_addr, self.is_client);
+                    let (stream, ctx) = Stream::new(
+                        id,
+                        self.cnx.clone(),
+                        self.peer_addr,
+                        self.local_addr,
+                        self.is_client,
+                        self.read_task.clone(),
+                        self.given_stream_bytes.clone(),
+                    );
                     assert!(self.streams.insert(id, ctx).is_none());
 
                     let _ = sender.send(Ok(stream));
@@ -339,9 +2046,176 @@ impl Context {
         }
     }
 
-    fn close(&mut self) {
-        self.cnx.close();
+    /// Check for new commands from the `Connection` and execute them.
+    fn check_commands(&mut self) {
+        loop {
+            match self.cmd_recv.poll() {
+                Ok(Ready(None)) | Ok(NotReady) | Err(_) => break,
+                Ok(Ready(Some(Command::PauseStream(id, done)))) => {
+                    let res = match self.streams.get_mut(&id) {
+                        Some(stream) => {
+                            stream.pause();
+                            self.cnx.get().set_stream_flow_control_paused(id, true)
+                        }
+                        None => Err(ErrorKind::UnknownStream(id).into()),
+                    };
+                    let _ = done.send(res);
+                }
+                Ok(Ready(Some(Command::ResumeStream(id, done)))) => {
+                    let res = match self.streams.get_mut(&id) {
+                        Some(stream) => {
+                            stream.resume();
+                            self.cnx
+                                .get()
+                                .set_stream_flow_control_paused(id, self.recv_buffer_full)
+                        }
+                        None => Err(ErrorKind::UnknownStream(id).into()),
+                    };
+                    let _ = done.send(res);
+                }
+                Ok(Ready(Some(Command::SendHeartbeat))) => self.send_heartbeat(),
+                Ok(Ready(Some(Command::ResetAllStreams(error_code)))) => {
+                    self.streams
+                        .values_mut()
+                        .for_each(|s| s.reset_with_error_code(error_code));
+                }
+                Ok(Ready(Some(Command::SendFromReader(id, reader, deadline, done)))) => {
+                    match self.streams.get_mut(&id) {
+                        Some(stream) => stream.send_from_reader(reader, deadline, done),
+                        None => {
+                            let _ = done.send(Err(ErrorKind::UnknownStream(id).into()));
+                        }
+                    }
+                }
+                Ok(Ready(Some(Command::SetStreamDataProvider(id, provider)))) => {
+                    if let Some(stream) = self.streams.get_mut(&id) {
+                        stream.set_data_provider(provider);
+                    }
+                }
+                Ok(Ready(Some(Command::SetWeight(weight)))) => self.cnx.get().set_weight(weight),
+                Ok(Ready(Some(Command::SetCongestionAlgorithm(algorithm)))) => {
+                    self.cnx.get().set_congestion_algorithm(algorithm)
+                }
+                Ok(Ready(Some(Command::SendAckNow))) => self.cnx.get().send_ack_now(),
+                Ok(Ready(Some(Command::SetLabel(label)))) => {
+                    self.snapshot.lock().unwrap().label = Some(label.clone());
+                    self.cnx.get().set_label(label);
+                }
+                Ok(Ready(Some(Command::SetCongestionEventCallback(callback)))) => {
+                    self.congestion_event_callback = Some(callback);
+                }
+                Ok(Ready(Some(Command::SetRttChangeCallback(threshold, callback)))) => {
+                    self.reported_rtt = self.cnx.get().rtt();
+                    self.rtt_change_callback = Some((threshold, callback));
+                }
+                Ok(Ready(Some(Command::SubscribeEvents(sender)))) => {
+                    if self.handshake_confirmed {
+                        let _ = sender.unbounded_send(Event::HandshakeConfirmed);
+                    }
+
+                    self.event_subscribers.push(sender);
+                }
+                #[cfg(feature = "loss-injection")]
+                Ok(Ready(Some(Command::MarkPacketLost(packet_number)))) => {
+                    self.cnx.get().mark_packet_lost(packet_number);
+                }
+            }
+        }
+    }
+
+    /// Sends the given `Event` to all subscribers.
+    fn emit_event(&mut self, event: Event) {
+        self.event_subscribers
+            .retain(|s| s.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Returns the reason of a close, that was reported by picoquic.
+    /// This needs to be called before the error is handled, as the handshake is still pending as
+    /// long as `wait_for_ready_state` is set.
+    fn close_reason(&self) -> DisconnectReason {
+        if self.closed {
+            // The reason of our own close was already recorded by `close`.
+            DisconnectReason::LocalClose
+        } else if self.cnx.get().is_idle_timeout() {
+            DisconnectReason::IdleTimeout
+        } else if self.wait_for_ready_state.is_some() {
+            DisconnectReason::HandshakeFailed
+        } else if self.cnx.get().has_local_error() {
+            DisconnectReason::LocalError
+        } else {
+            DisconnectReason::RemoteClose
+        }
+    }
+
+    /// Checks if the handshake was confirmed since the last call of this function.
+    fn check_handshake_confirmed(&mut self) {
+        if !self.handshake_confirmed && self.cnx.get().is_handshake_confirmed() {
+            self.handshake_confirmed = true;
+            self.emit_event(Event::HandshakeConfirmed);
+        }
+    }
+
+    /// Checks if the path MTU was reduced since the last call of this function.
+    fn check_path_mtu(&mut self) {
+        let mtu = self.cnx.get().path_mtu();
+        let old = mem::replace(&mut self.path_mtu, mtu);
+
+        if mtu < old {
+            self.emit_event(Event::PathMtuReduced(old, mtu));
+        }
+    }
+
+    /// Checks if the peer is blocked by the connection or a `Stream` flow control.
+    fn check_peer_blocked(&mut self) {
+        if self.event_subscribers.is_empty() || !self.cnx.get().is_ready() {
+            return;
+        }
+
+        let blocked = self.cnx.get().is_peer_data_blocked();
+        if blocked && !self.peer_data_blocked {
+            self.emit_event(Event::PeerDataBlocked);
+        }
+        self.peer_data_blocked = blocked;
+
+        let blocked_streams = self
+            .streams
+            .iter_mut()
+            .filter_map(|(id, s)| {
+                if s.check_peer_blocked() {
+                    Some(*id)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        blocked_streams
+            .into_iter()
+            .for_each(|id| self.emit_event(Event::PeerStreamDataBlocked(id)));
+    }
+
+    fn close(&mut self, reason: DisconnectReason) {
+        debug!(
+            "connection {} closed: {:?}",
+            self.cnx.get().log_name(),
+            reason
+        );
+
+        // A local error is already sent to the peer by picoquic.
+        if !self.cnx.get().has_local_error() {
+            let (error_code, reason) = self.local_close_error.take().unwrap_or_default();
+            self.cnx.get().close(error_code, &reason);
+        }
         self.closed = true;
+
+        {
+            let mut snapshot = self.snapshot.lock().unwrap();
+            snapshot.is_closed = true;
+            snapshot.state = Some(self.cnx.get().connection_state());
+            snapshot.disconnect_reason.get_or_insert(reason);
+            snapshot.close_info = Some(self.cnx.get().close_info());
+        }
+
         self.streams
             .values_mut()
             .for_each(|s| s.handle_connection_close());
@@ -351,7 +2225,7 @@ impl Context {
     fn process_wait_for_ready_state(&mut self) {
         match self.wait_for_ready_state.take() {
             Some((builder, sender)) => {
-                let id = self.cnx.local_id();
+                let id = self.cnx.get().local_id();
                 let con = builder.build(id);
 
                 let _ = sender.send(Ok(con));
@@ -368,20 +2242,62 @@ impl Context {
         self.wait_for_ready_state = Some((builder, sender));
     }
 
+    /// Updates the snapshot that is read by the `Connection`.
+    fn update_snapshot(&self) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+
+        snapshot.update(self.cnx.get());
+        snapshot.readable_streams.clear();
+        snapshot.readable_streams.extend(
+            self.streams
+                .iter()
+                .map(|(id, s)| (*id, s.buffered_bytes()))
+                .filter(|&(_, bytes)| bytes > 0),
+        );
+
+        snapshot.recv_finished_streams.clear();
+        snapshot.recv_finished_streams.extend(
+            self.streams
+                .iter()
+                .filter(|&(_, s)| s.is_fin_received())
+                .map(|(id, s)| (*id, s.is_all_received())),
+        );
+
+        let mut counts = StreamCounts::default();
+        for id in self.streams.keys() {
+            counts.add(*id, self.is_client);
+        }
+        snapshot.open_stream_counts = counts;
+        snapshot.next_stream_id = self.next_stream_id;
+        snapshot.has_unacked_data = self.cnx.get().has_unacked_data()
+            || self.streams.values().any(|s| s.has_pending_send_data());
+        snapshot.send_queue_bytes = self
+            .given_stream_bytes
+            .get()
+            .saturating_sub(self.cnx.get().sent_stream_bytes());
+    }
+
     /// Checks if the connection had an error and handles it.
     fn check_and_handle_error(&mut self) {
-        if let Some(err) = self.cnx.error() {
+        if let Some(err) = self.cnx.get().error() {
             self.streams
                 .values_mut()
-                .for_each(|s| s.handle_connection_error(err()));
+                .for_each(|s| s.handle_connection_error(&*err));
 
             while let Ok(Ready(Some((_, sender)))) = self.recv_create_stream.poll() {
                 let _ = sender.send(Err(err()));
             }
 
+            let offered_versions = self.snapshot.lock().unwrap().offered_versions.clone();
+
             match self.wait_for_ready_state.take() {
                 Some((_, send)) => {
-                    let _ = send.send(Err(err()));
+                    // The connection failed in the handshake, because of an unsupported version.
+                    let err = match offered_versions {
+                        Some(versions) => ErrorKind::VersionNegotiation(versions).into(),
+                        None => err(),
+                    };
+                    let _ = send.send(Err(err));
                 }
                 None => {
                     let _ = self.send_msg.unbounded_send(Message::Error(err()));
@@ -400,24 +2316,96 @@ impl Future for Context {
             return Ok(Ready(()));
         }
 
-        if self.wait_for_ready_state.is_some() && self.cnx.is_ready() {
+        if self.wait_for_ready_state.is_some() && self.cnx.get().is_ready() {
             self.process_wait_for_ready_state();
         }
 
+        self.check_handshake_timeout();
+
+        if self.closed {
+            return Ok(Ready(()));
+        }
+
         self.streams
             .retain(|_, s| s.poll().map(|r| r.is_not_ready()).unwrap_or(false));
 
         self.check_create_stream_requests();
 
+        if let Some(ref pool) = self.pool {
+            pool.borrow().record_streams(self.streams.len());
+        }
+
+        self.check_commands();
+
+        self.check_peer_address_change();
+
+        if self.closed {
+            return Ok(Ready(()));
+        }
+
+        self.update_snapshot();
+
+        self.check_handshake_confirmed();
+
+        self.check_peer_blocked();
+
+        self.check_congestion();
+
+        self.check_rtt_change();
+
+        self.check_path_mtu();
+
+        self.check_recv_buffer();
+
+        if self.closed {
+            return Ok(Ready(()));
+        }
+
         // Check if the connection should be closed
-        if let Ok(Ready(_)) = self.close_recv.poll() {
-            self.close();
+        if let Ok(Ready(error)) = self.close_recv.poll() {
+            self.local_close_error = Some(error);
+            self.close(DisconnectReason::LocalClose);
         }
 
         Ok(NotReady)
     }
 }
 
+impl Drop for Context {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.borrow_mut()
+                .put_stream_map(mem::take(&mut self.streams));
+        }
+    }
+}
+
+/// Parses the list of big endian encoded versions of a version negotiation packet.
+fn parse_versions(data: &[u8]) -> Vec<u32> {
+    data.chunks(4)
+        .filter(|v| v.len() == 4)
+        .map(|v| {
+            (u32::from(v[0]) << 24)
+                | (u32::from(v[1]) << 16)
+                | (u32::from(v[2]) << 8)
+                | u32::from(v[3])
+        })
+        .collect()
+}
+
+/// The byte that is sent on the heartbeat stream for each heartbeat.
+const HEARTBEAT: u8 = 1;
+
+/// Returns the id of the unidirectional stream that is used to send heartbeats.
+/// The streams `2` (client) and `3` (server) are reserved and never used for `Stream`s.
+fn heartbeat_stream_id(is_client: bool) -> stream::Id {
+    if is_client {
+        2
+    } else {
+        3
+    }
+}
+
 fn get_context(ctx: *mut c_void) -> Rc<RefCell<Context>> {
     unsafe { Rc::from_raw(ctx as *mut RefCell<Context>) }
 }
@@ -435,14 +2423,34 @@ unsafe extern "C" fn recv_data_callback(
 
     if event == picoquic::picoquic_call_back_event_t_picoquic_callback_close
         || event == picoquic::picoquic_call_back_event_t_picoquic_callback_application_close
+        || event == picoquic::picoquic_call_back_event_t_picoquic_callback_stateless_reset
     {
+        let reason =
+            if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stateless_reset {
+                DisconnectReason::StatelessReset
+            } else {
+                ctx.borrow().close_reason()
+            };
+
         // when Rc goes out of scope, it will dereference the Context pointer automatically
         ctx.borrow_mut().check_and_handle_error();
-        ctx.borrow_mut().close();
+        ctx.borrow_mut().close(reason);
+    } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_prepare_to_send {
+        // For this event, picoquic passes its context in `bytes` and the available space in
+        // `length`.
+        ctx.borrow_mut()
+            .prepare_to_send(stream_id, bytes as *mut c_void, length);
+
+        // the context must not be dereferenced!
+        mem::forget(ctx);
     } else {
         let data = slice::from_raw_parts(bytes, length as usize);
 
-        ctx.borrow_mut().recv_data(stream_id, data, event);
+        if event == picoquic::picoquic_call_back_event_t_picoquic_callback_version_negotiation {
+            ctx.borrow_mut().handle_version_negotiation(data);
+        } else {
+            ctx.borrow_mut().recv_data(stream_id, data, event);
+        }
 
         // the context must not be dereferenced!
         mem::forget(ctx);
@@ -475,6 +2483,21 @@ impl NewStreamHandle {
     }
 }
 
+/// A stream of `Event`s of a `Connection`.
+/// This stream is created by `Connection::events`.
+pub struct Events {
+    recv: UnboundedReceiver<Event>,
+}
+
+impl FStream for Events {
+    type Item = Event;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.recv.poll().map_err(|_| ErrorKind::Unknown.into())
+    }
+}
+
 /// A future that resolves to a `Stream`.
 /// This future is created by the `NewStreamHandle`.
 pub struct NewStreamFuture {
@@ -495,3 +2518,180 @@ impl Future for NewStreamFuture {
             })
     }
 }
+
+/// A future that resolves, when a `Stream` was paused or resumed.
+/// This future is created by `Connection::pause_stream` and `Connection::resume_stream`.
+pub struct PauseStreamFuture {
+    recv: oneshot::Receiver<Result<(), Error>>,
+}
+
+impl Future for PauseStreamFuture {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.recv
+            .poll()
+            .map_err(|_| ErrorKind::Unknown.into())
+            .and_then(|r| match r {
+                Ready(v) => v.map(Ready),
+                NotReady => Ok(NotReady),
+            })
+    }
+}
+
+/// A future that resolves, when all data of a reader was sent on a `Stream`.
+/// This future is created by `Connection::send_stream_from_reader`.
+pub struct SendFromReaderFuture {
+    recv: oneshot::Receiver<Result<(), Error>>,
+}
+
+impl Future for SendFromReaderFuture {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.recv
+            .poll()
+            .map_err(|_| ErrorKind::Unknown.into())
+            .and_then(|r| match r {
+                Ready(v) => v.map(Ready),
+                NotReady => Ok(NotReady),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cipher_suite_id_round_trips() {
+        for id in &[0x1301, 0x1302, 0x1303, 0x1304] {
+            assert_eq!(*id, CipherSuite::from_id(*id).id());
+        }
+
+        assert_eq!(CipherSuite::Unknown(0x1304), CipherSuite::from_id(0x1304));
+    }
+
+    #[test]
+    fn key_exchange_group_id_round_trips() {
+        for id in &[23, 24, 25, 29, 30, 256] {
+            assert_eq!(*id, KeyExchangeGroup::from_id(*id).id());
+        }
+    }
+
+    #[test]
+    fn id_formats_and_parses_hex() {
+        let id = Id::from(0x0123_4567_89ab_cdef);
+
+        assert_eq!("0123456789abcdef", id.to_string());
+        assert_eq!("123456789abcdef", format!("{:x}", id));
+        assert_eq!(id, "0123456789abcdef".parse().unwrap());
+        assert_eq!(id, "0x123456789abcdef".parse().unwrap());
+        assert!("".parse::<Id>().is_err());
+        assert!("0123456789abcdef0".parse::<Id>().is_err());
+        assert!("xyz".parse::<Id>().is_err());
+    }
+
+    #[test]
+    fn id_bytes_round_trip() {
+        let id = Id::from(0x0123_4567_89ab_cdef);
+
+        assert_eq!(
+            [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+            id.as_bytes()
+        );
+        assert_eq!(id, Id::from_bytes(&id.as_bytes()));
+        assert_eq!(
+            Id::from(0xaabb_0000_0000_0000),
+            Id::from_bytes(&[0xaa, 0xbb])
+        );
+    }
+
+    #[test]
+    fn loss_rate_without_sent_packets_is_zero() {
+        assert_eq!(0.0, Snapshot::default().loss_rate());
+    }
+
+    #[test]
+    fn loss_rate_is_lost_divided_by_sent() {
+        let snapshot = Snapshot {
+            packets_sent: 200,
+            packets_lost: 50,
+            ..Default::default()
+        };
+
+        assert_eq!(0.25, snapshot.loss_rate());
+    }
+
+    #[test]
+    fn connection_that_is_not_ready_is_not_healthy() {
+        assert!(!Snapshot::default().is_healthy(&HealthPolicy::default(), 0));
+    }
+
+    #[test]
+    fn healthy_connection_must_not_exceed_thresholds() {
+        let snapshot = Snapshot {
+            is_ready: true,
+            packets_sent: 100,
+            packets_lost: 10,
+            rtt: Duration::from_millis(50),
+            latest_progress_time: 1_000_000,
+            ..Default::default()
+        };
+
+        let mut policy = HealthPolicy {
+            max_loss_rate: Some(0.2),
+            max_rtt: Some(Duration::from_millis(100)),
+            max_idle_time: Some(Duration::from_secs(1)),
+        };
+        assert!(snapshot.is_healthy(&policy, 1_500_000));
+        assert!(!snapshot.is_healthy(&policy, 2_500_000));
+
+        policy.max_rtt = Some(Duration::from_millis(10));
+        assert!(!snapshot.is_healthy(&policy, 1_500_000));
+    }
+
+    #[test]
+    fn stream_counts_distinguish_type_and_initiator() {
+        let mut counts = StreamCounts::default();
+        // client initiated bidirectional and unidirectional streams
+        counts.add(0, true);
+        counts.add(4, true);
+        counts.add(2, true);
+        // server initiated bidirectional and unidirectional streams
+        counts.add(1, true);
+        counts.add(3, true);
+        counts.add(7, true);
+
+        assert_eq!(
+            StreamCounts {
+                bidi_local: 2,
+                bidi_remote: 1,
+                uni_local: 1,
+                uni_remote: 2,
+            },
+            counts
+        );
+    }
+
+    #[test]
+    fn parse_versions_of_version_negotiation_packet() {
+        assert_eq!(
+            vec![0xff00_000f, 0x0000_0001],
+            parse_versions(&[0xff, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x01])
+        );
+    }
+
+    #[test]
+    fn event_flags_contains_combined_events() {
+        let events = EventFlags::READABLE | EventFlags::WRITABLE;
+
+        assert!(events.contains(EventFlags::READABLE));
+        assert!(events.contains(EventFlags::WRITABLE));
+        assert!(!events.contains(EventFlags::CLOSED));
+        assert!(!events.contains(EventFlags::READABLE | EventFlags::MIGRATED));
+        assert!(EventFlags::empty().is_empty());
+    }
+}
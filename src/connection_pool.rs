@@ -0,0 +1,194 @@
+use connection::{self, Connection};
+use context_inner::{NewConnectionFuture, NewConnectionHandle};
+use error::*;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::Async::{NotReady, Ready};
+use futures::{Future, Poll};
+
+use tokio_core::reactor::{Handle, Timeout};
+
+/// The delay before connecting again, after connecting to the server failed the first time.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// The maximum delay before connecting again. The delay doubles with each failed attempt.
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// A pool of established `Connection`s to one server, for clients that make frequent requests
+/// to the same server and do not want to wait for a handshake per request.
+///
+/// The pool keeps `size` `Connection`s ready or connecting. A ready `Connection` is handed out
+/// by `get` and replaced by a new `Connection`. `Connection`s that died while idling in the pool
+/// (e.g. closed by the server) are dropped and replaced as well.
+/// The `Context` of the `NewConnectionHandle` should enable keep alive (see
+/// `Config::enable_keep_alive`), otherwise the idle `Connection`s run into the idle timeout.
+/// If connecting to the server fails, no new `Connection`s are started for a delay that grows
+/// exponentially with each failed attempt, to not hammer a server that is down.
+pub struct ConnectionPool {
+    con_handle: NewConnectionHandle,
+    server_addr: SocketAddr,
+    server_name: String,
+    size: usize,
+    idle: VecDeque<Connection>,
+    connecting: Vec<NewConnectionFuture>,
+    /// The delay before connecting again, after the next failed attempt.
+    backoff: Duration,
+    /// The point in time, until no new `Connection`s are started, because connecting failed.
+    retry_at: Option<Instant>,
+    /// Wakes up the task, when connecting is allowed again.
+    timer: Timeout,
+}
+
+impl ConnectionPool {
+    /// Creates a new `ConnectionPool` and starts connecting its `Connection`s.
+    ///
+    /// con_handle - The handle of the `Context` that creates the `Connection`s.
+    /// server_addr - The address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// size - The number of `Connection`s that are kept ready, needs to be greater than `0`.
+    /// handle - The handle of the event loop the `ConnectionPool` is polled on.
+    pub fn new<T: Into<String>>(
+        con_handle: NewConnectionHandle,
+        server_addr: SocketAddr,
+        server_name: T,
+        size: usize,
+        handle: &Handle,
+    ) -> Result<ConnectionPool, Error> {
+        if size == 0 {
+            return Err(
+                ::failure::err_msg("The size of a ConnectionPool must be greater than 0!").into(),
+            );
+        }
+
+        let mut pool = ConnectionPool {
+            con_handle,
+            server_addr,
+            server_name: server_name.into(),
+            size,
+            idle: VecDeque::with_capacity(size),
+            connecting: Vec::with_capacity(size),
+            backoff: INITIAL_BACKOFF,
+            retry_at: None,
+            timer: Timeout::new(Duration::from_secs(0), handle).context(ErrorKind::Unknown)?,
+        };
+
+        pool.fill();
+        Ok(pool)
+    }
+
+    /// Returns the number of ready `Connection`s in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Returns the number of `Connection`s of the pool that are still connecting.
+    pub fn connecting_count(&self) -> usize {
+        self.connecting.len()
+    }
+
+    /// Returns a future that resolves into a ready `Connection` of the pool.
+    pub fn get(&mut self) -> GetConnection<'_> {
+        GetConnection { pool: self }
+    }
+
+    /// Returns a `Connection` that is not needed anymore to the pool, so it is handed out by the
+    /// next `get`. The `Connection` takes the slot of a `Connection` that is still connecting,
+    /// which is dropped. If all slots are taken by ready `Connection`s or the `Connection` is not
+    /// ready, it is dropped.
+    pub fn put(&mut self, con: Connection) {
+        if con.state() != connection::State::Ready || self.idle.len() >= self.size {
+            return;
+        }
+
+        if self.idle.len() + self.connecting.len() >= self.size {
+            self.connecting.pop();
+        }
+
+        self.idle.push_front(con);
+    }
+
+    /// Polls for a ready `Connection` of the pool. Dead `Connection`s are dropped and the pool
+    /// is refilled.
+    /// If no `Connection` could be established, the error of the last attempt is returned.
+    pub fn poll_connection(&mut self) -> Poll<Connection, Error> {
+        self.idle.retain(|c| c.state() == connection::State::Ready);
+        self.fill();
+
+        let mut error = None;
+        let mut i = 0;
+
+        while i < self.connecting.len() {
+            match self.connecting[i].poll() {
+                Ok(Ready(con)) => {
+                    self.connecting.swap_remove(i);
+                    self.idle.push_back(con);
+                    self.backoff = INITIAL_BACKOFF;
+                }
+                Ok(NotReady) => i += 1,
+                Err(e) => {
+                    self.connecting.swap_remove(i);
+                    error = Some(e);
+                }
+            }
+        }
+
+        // The attempts that failed together, count as one failed attempt.
+        if error.is_some() {
+            self.retry_at = Some(Instant::now() + self.backoff);
+            self.backoff = cmp::min(self.backoff * 2, MAX_BACKOFF);
+        }
+
+        match self.idle.pop_front() {
+            Some(con) => {
+                self.fill();
+                Ok(Ready(con))
+            }
+            None => match error {
+                Some(e) if self.connecting.is_empty() => Err(e),
+                _ => {
+                    if let Some(retry_at) = self.retry_at {
+                        self.timer.reset(retry_at);
+                        // Poll the timer once, to register the current task to be woken up when
+                        // connecting is allowed again.
+                        let _ = self.timer.poll();
+                    }
+
+                    Ok(NotReady)
+                }
+            },
+        }
+    }
+
+    /// Starts connecting new `Connection`s, until the pool has `size` `Connection`s.
+    /// After a failed attempt, no `Connection`s are started until `retry_at`.
+    fn fill(&mut self) {
+        match self.retry_at {
+            Some(retry_at) if retry_at > Instant::now() => return,
+            _ => self.retry_at = None,
+        }
+
+        while self.idle.len() + self.connecting.len() < self.size {
+            let con = self
+                .con_handle
+                .new_connection(self.server_addr, self.server_name.clone());
+            self.connecting.push(con);
+        }
+    }
+}
+
+/// Resolves into a ready `Connection` of a `ConnectionPool`, see `ConnectionPool::get`.
+pub struct GetConnection<'a> {
+    pool: &'a mut ConnectionPool,
+}
+
+impl<'a> Future for GetConnection<'a> {
+    type Item = Connection;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.pool.poll_connection()
+    }
+}
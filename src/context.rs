@@ -1,21 +1,60 @@
 use config::Config;
 use connection::Connection;
-use context_inner::{ContextInner, NewConnectionFuture, NewConnectionHandle};
+use context_inner::{ContextInner, NewConnectionFuture, NewConnectionHandle, Socket};
 use error::*;
+use ffi::MicroSeconds;
+use packet_info;
+use picoquic_sys::picoquic::PICOQUIC_INITIAL_RTT;
+use pool::CapacityStats;
+use stream;
+#[cfg(feature = "test-transport")]
+use test_transport;
 
+use std::io;
+use std::mem;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use socket2::{Domain, Protocol, SockAddr, Socket as RawSocket, Type};
+
+use tokio_core::net::UdpSocket;
 use tokio_core::reactor::Handle;
 
 use futures::sync::mpsc::UnboundedReceiver;
-use futures::{Poll, Stream};
+use futures::Async::{NotReady, Ready};
+use futures::{Future, Poll, Stream};
+
+use bytes::BytesMut;
 
 /// The `Picoquic` context. It setups and controls the `UdpSocket`. Every incoming `Connection`
 /// can be obtained by polling this context.
 pub struct Context {
     recv_con: UnboundedReceiver<Connection>,
-    local_addr: SocketAddr,
+    local_addrs: Vec<SocketAddr>,
     new_connection_handle: NewConnectionHandle,
+    initial_rtt: Duration,
+    drain: Arc<AtomicBool>,
+    capacity_stats: Arc<Mutex<CapacityStats>>,
+    /// The `Connection`s that were accepted by `accept_request` and wait for their first data.
+    pending_requests: Vec<PendingRequest>,
+}
+
+/// An incoming `Connection` that waits for the data of its first `Stream`.
+struct PendingRequest {
+    con: Connection,
+    stream: Option<stream::Stream>,
+}
+
+/// The first data that was received from an incoming `Connection`, see
+/// `Context::accept_request`.
+pub struct Request {
+    pub connection: Connection,
+    /// The `Stream` that carried the data. The response can be sent on it.
+    pub stream: stream::Stream,
+    /// The first data that was received on `stream`.
+    pub data: BytesMut,
 }
 
 impl Context {
@@ -27,24 +66,143 @@ impl Context {
         handle: &Handle,
         config: Config,
     ) -> Result<Context, Error> {
+        Context::new_with_addresses(&[*listen_address], handle, config)
+    }
+
+    /// Creates a new `Context` that listens on all the given addresses.
+    /// This can be used by a server to listen on IPv4 and IPv6 or on multiple interfaces. The
+    /// replies to a peer are sent from the address, the peer sent its packets to.
+    /// Outgoing connections use the first address with the same IP version as the server.
+    pub fn new_with_addresses(
+        listen_addresses: &[SocketAddr],
+        handle: &Handle,
+        config: Config,
+    ) -> Result<Context, Error> {
+        if listen_addresses.is_empty() {
+            return Err(::failure::err_msg("At least one listen address is required!").into());
+        }
+
+        let sockets = listen_addresses
+            .iter()
+            .map(|addr| {
+                let socket =
+                    bind_udp_socket(addr, &config, handle).context(ErrorKind::NetworkError)?;
+
+                // The replies need to be sent from the address the peer contacted.
+                if addr.ip().is_unspecified() {
+                    packet_info::enable(&socket, addr.is_ipv4())
+                        .context(ErrorKind::NetworkError)?;
+                }
+
+                if config.ecn_marks {
+                    packet_info::enable_ecn(&socket, addr.is_ipv4())
+                        .context(ErrorKind::NetworkError)?;
+                }
+
+                Ok(Socket::Udp(socket))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Context::new_with_sockets(sockets, handle, config)
+    }
+
+    /// Creates a new `Context` that sends and receives its packets over the given in-memory
+    /// `Network`, instead of an `UdpSocket`.
+    ///
+    /// listen_address - The address in the `Network`. If the port is `0`, a free port is chosen.
+    #[cfg(feature = "test-transport")]
+    pub fn new_with_test_transport(
+        listen_address: &SocketAddr,
+        network: &test_transport::Network,
+        handle: &Handle,
+        config: Config,
+    ) -> Result<Context, Error> {
+        Context::new_with_test_transport_addresses(&[*listen_address], network, handle, config)
+    }
+
+    /// Creates a new `Context` that listens on all the given addresses of the in-memory
+    /// `Network`, like `Context::new_with_addresses`.
+    #[cfg(feature = "test-transport")]
+    pub fn new_with_test_transport_addresses(
+        listen_addresses: &[SocketAddr],
+        network: &test_transport::Network,
+        handle: &Handle,
+        config: Config,
+    ) -> Result<Context, Error> {
+        if listen_addresses.is_empty() {
+            return Err(::failure::err_msg("At least one listen address is required!").into());
+        }
+
+        let sockets = listen_addresses
+            .iter()
+            .map(|addr| {
+                network
+                    .bind(addr, handle)
+                    .map(Socket::Memory)
+                    .context(ErrorKind::NetworkError)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if config.socket_buffer_sizes.is_some() {
+            warn!("socket buffer sizes are ignored by the test transport");
+        }
+
+        if config.dont_fragment {
+            warn!("don't fragment is ignored by the test transport");
+        }
+
+        if config.bind_device.is_some() {
+            warn!("the bind device is ignored by the test transport");
+        }
+
+        Context::new_with_sockets(sockets, handle, config)
+    }
+
+    fn new_with_sockets(
+        sockets: Vec<Socket>,
+        handle: &Handle,
+        config: Config,
+    ) -> Result<Context, Error> {
+        let initial_rtt = config
+            .initial_rtt
+            .unwrap_or_else(|| Duration::from_micro_seconds(u64::from(PICOQUIC_INITIAL_RTT)));
+
+        let drain = Arc::new(AtomicBool::new(false));
         let (inner, recv_con, new_connection_handle) =
-            ContextInner::new(listen_address, handle, config)?;
+            ContextInner::new(sockets, handle, config, drain.clone())?;
 
-        let local_addr = inner.local_addr();
+        let local_addrs = inner.local_addrs();
+        let capacity_stats = inner.capacity_stats();
 
         // start the inner future
         handle.spawn(inner);
 
         Ok(Context {
             recv_con,
-            local_addr,
+            local_addrs,
             new_connection_handle,
+            initial_rtt,
+            drain,
+            capacity_stats,
+            pending_requests: Vec::new(),
         })
     }
 
     /// Returns the local address, this `Context` is bound to.
+    /// If the `Context` listens on multiple addresses, the first address is returned.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+        self.local_addrs[0]
+    }
+
+    /// Returns all local addresses, this `Context` is bound to.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns the initial round trip time that is assumed for outgoing connections, until the
+    /// first round trip time is measured.
+    pub fn initial_rtt(&self) -> Duration {
+        self.initial_rtt
     }
 
     /// Connects to the given address and returns a future that resolves into a `Connection`.
@@ -59,10 +217,137 @@ impl Context {
         self.new_connection_handle.new_connection(addr, server_name)
     }
 
+    /// Connects to the given address with the given idle timeout and returns a future that
+    /// resolves into a `Connection`.
+    /// The effective idle timeout of the `Connection` is the minimum of the idle timeouts of both
+    /// peers.
+    ///
+    /// addr - Address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// idle_timeout - The idle timeout of the `Connection`.
+    pub fn new_connection_with_idle_timeout<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        idle_timeout: Duration,
+    ) -> NewConnectionFuture {
+        self.new_connection_handle
+            .new_connection_with_idle_timeout(addr, server_name, idle_timeout)
+    }
+
+    /// Enters the drain mode, e.g. for a rolling deploy without downtime.
+    /// In the drain mode, new incoming `Connection`s are refused with `SERVER_BUSY`, which is
+    /// reported as `ErrorKind::ConnectionRefused` to the clients, so they can connect to another
+    /// server. The existing `Connection`s are served until they are closed. Outgoing
+    /// `Connection`s can still be created.
+    /// The drain mode can not be left.
+    pub fn enter_drain_mode(&self) {
+        self.drain.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns if this `Context` is in the drain mode, see `enter_drain_mode`.
+    pub fn is_draining(&self) -> bool {
+        self.drain.load(Ordering::SeqCst)
+    }
+
+    /// Returns the usage of the capacity, that was allocated upfront for the hinted number of
+    /// `Connection`s and `Stream`s, see `Config::set_capacity_hints`.
+    /// The high-water marks are also tracked without capacity hints.
+    pub fn capacity_stats(&self) -> CapacityStats {
+        *self.capacity_stats.lock().unwrap()
+    }
+
     /// Returns the handle to create new connections.
     pub fn get_new_connection_handle(&self) -> NewConnectionHandle {
         self.new_connection_handle.clone()
     }
+
+    /// Returns a future that resolves, when an incoming `Connection` received the first data on
+    /// a `Stream`. This combines accepting the `Connection`, waiting for its first `Stream` and
+    /// reading the first data, as needed by request/response servers.
+    /// The `Connection`s that wait for their first data are kept by the `Context`, so they are
+    /// not lost, when the future is dropped. The later `Stream`s of a returned `Connection` are
+    /// returned by polling the `Connection` as usual.
+    /// `accept_request` should not be mixed with polling this `Context` for `Connection`s, as
+    /// both take the incoming `Connection`s.
+    pub fn accept_request(&mut self) -> AcceptRequest<'_> {
+        AcceptRequest { context: self }
+    }
+
+    /// Polls for the first data of an incoming `Connection`, see `accept_request`.
+    fn poll_request(&mut self) -> Poll<Request, Error> {
+        loop {
+            match self.recv_con.poll() {
+                Ok(Ready(Some(con))) => self
+                    .pending_requests
+                    .push(PendingRequest { con, stream: None }),
+                Ok(NotReady) => break,
+                Ok(Ready(None)) | Err(_) => {
+                    if self.pending_requests.is_empty() {
+                        return Err(ErrorKind::Disconnected.into());
+                    }
+                    break;
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < self.pending_requests.len() {
+            match self.pending_requests[i].poll() {
+                Ok(Ready(Some(data))) => {
+                    let pending = self.pending_requests.swap_remove(i);
+
+                    return Ok(Ready(Request {
+                        connection: pending.con,
+                        stream: pending.stream.expect("data is received on a stream"),
+                        data,
+                    }));
+                }
+                Ok(NotReady) => i += 1,
+                // The `Connection` was closed before it received any data.
+                Ok(Ready(None)) | Err(_) => {
+                    self.pending_requests.swap_remove(i);
+                }
+            }
+        }
+
+        Ok(NotReady)
+    }
+}
+
+impl PendingRequest {
+    /// Polls for the first `Stream` of the `Connection` and its first data.
+    /// If the `Stream` is finished without any data, the next `Stream` is awaited.
+    fn poll(&mut self) -> Poll<Option<BytesMut>, Error> {
+        loop {
+            if let Some(ref mut stream) = self.stream {
+                match stream.poll() {
+                    Ok(Ready(Some(data))) => return Ok(Ready(Some(data))),
+                    Ok(NotReady) => return Ok(NotReady),
+                    Ok(Ready(None)) | Err(_) => {}
+                }
+            }
+
+            self.stream = match try_ready!(self.con.poll()) {
+                Some(stream) => Some(stream),
+                None => return Ok(Ready(None)),
+            };
+        }
+    }
+}
+
+/// Resolves into the first data of an incoming `Connection`, see `Context::accept_request`.
+pub struct AcceptRequest<'a> {
+    context: &'a mut Context,
+}
+
+impl<'a> Future for AcceptRequest<'a> {
+    type Item = Request;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.context.poll_request()
+    }
 }
 
 impl Stream for Context {
@@ -73,3 +358,119 @@ impl Stream for Context {
         self.recv_con.poll().map_err(|_| ErrorKind::Unknown.into())
     }
 }
+
+/// Binds an `UdpSocket` to the given address and applies the socket options of the given
+/// `Config` (the sizes of the kernel send and receive buffers, the don't fragment bit and the
+/// bind device).
+fn bind_udp_socket(addr: &SocketAddr, config: &Config, handle: &Handle) -> io::Result<UdpSocket> {
+    if config.socket_buffer_sizes.is_none() && !config.dont_fragment && config.bind_device.is_none()
+    {
+        return UdpSocket::bind(addr, handle);
+    }
+
+    let domain = if addr.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = RawSocket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+
+    if let Some((send, recv)) = config.socket_buffer_sizes {
+        socket.set_send_buffer_size(send)?;
+        socket.set_recv_buffer_size(recv)?;
+
+        if socket.send_buffer_size()? < send || socket.recv_buffer_size()? < recv {
+            warn!(
+                "socket buffer sizes were limited by the operating system (send: {}, recv: {})",
+                socket.send_buffer_size()?,
+                socket.recv_buffer_size()?
+            );
+        }
+    }
+
+    if config.dont_fragment {
+        set_dont_fragment(&socket, addr.is_ipv4())?;
+    }
+
+    if let Some(ref device) = config.bind_device {
+        bind_to_device(&socket, device)?;
+    }
+
+    socket.bind(&SockAddr::from(*addr))?;
+
+    UdpSocket::from_socket(socket.into_udp_socket(), handle)
+}
+
+/// Sets the don't fragment bit on all packets sent by the given socket, by enabling the path MTU
+/// discovery of the kernel (`IP_PMTUDISC_DO`).
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &RawSocket, ipv4: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let (level, name, value) = if ipv4 {
+        (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        )
+    } else {
+        (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        )
+    };
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_: &RawSocket, _: bool) -> io::Result<()> {
+    warn!("don't fragment is only supported on Linux");
+    Ok(())
+}
+
+/// Binds the given socket to the network interface with the given name (`SO_BINDTODEVICE`), so
+/// all packets are sent over this interface.
+#[cfg(target_os = "linux")]
+fn bind_to_device(socket: &RawSocket, device: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            device.as_ptr() as *const libc::c_void,
+            device.len() as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_: &RawSocket, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding to a device is only supported on Linux",
+    ))
+}
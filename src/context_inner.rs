@@ -1,19 +1,27 @@
-use config::{Config, Role};
+use config::{Config, OverflowPolicy, Role};
 use connection::{self, Connection};
 use error::*;
-use ffi::QuicCtx;
+use ffi::{self, QuicCtx, Routed};
+use packet_info;
+use peer_address_change::PeerAddressChangeHandler;
+use pool::{CapacityStats, Pool, PoolHandle};
 use stream;
+#[cfg(feature = "test-transport")]
+use test_transport;
 
 use picoquic_sys::picoquic::{
     picoquic_call_back_event_t, picoquic_cnx_t, PICOQUIC_MAX_PACKET_SIZE,
 };
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io;
 use std::mem;
 use std::net::SocketAddr;
 use std::os::raw::c_void;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use tokio_core::net::UdpSocket;
@@ -22,33 +30,173 @@ use tokio_core::reactor::{Handle, Timeout};
 use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::sync::oneshot;
 use futures::Async::{NotReady, Ready};
-use futures::{task, Future, Poll, Stream};
+use futures::{task, Async, Future, Poll, Stream};
+
+/// The maximum number of datagrams that are prepared in one batch.
+const BATCH_SIZE: usize = 16;
 
 type NewConnectionMsg = (
     SocketAddr,
     String,
+    Option<Duration>,
     oneshot::Sender<Result<Connection, Error>>,
 );
 
+/// A prepared datagram that could not be sent yet, because its socket was not writable.
+struct PendingDatagram {
+    data: Vec<u8>,
+    local_addr: Option<SocketAddr>,
+    to: SocketAddr,
+}
+
+/// The socket that is used by the `ContextInner` to send and receive packets.
+pub enum Socket {
+    Udp(UdpSocket),
+    #[cfg(feature = "test-transport")]
+    Memory(test_transport::Socket),
+}
+
+impl Socket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            Socket::Udp(ref socket) => socket.local_addr(),
+            #[cfg(feature = "test-transport")]
+            Socket::Memory(ref socket) => socket.local_addr(),
+        }
+    }
+
+    fn poll_write(&self) -> Async<()> {
+        match *self {
+            Socket::Udp(ref socket) => socket.poll_write(),
+            #[cfg(feature = "test-transport")]
+            Socket::Memory(ref socket) => socket.poll_write(),
+        }
+    }
+
+    /// Sends the given datagram to `target`.
+    /// If the socket is bound to an unspecified address, the datagram is sent from the given
+    /// local address.
+    fn send_to(
+        &self,
+        buf: &[u8],
+        local_addr: Option<SocketAddr>,
+        target: &SocketAddr,
+    ) -> io::Result<usize> {
+        match *self {
+            Socket::Udp(ref socket) => {
+                let from = local_addr.filter(|a| !a.ip().is_unspecified());
+
+                match from {
+                    Some(from) if socket.local_addr()?.ip().is_unspecified() => {
+                        packet_info::send_from(socket, buf, from.ip(), target)
+                    }
+                    _ => socket.send_to(buf, target),
+                }
+            }
+            #[cfg(feature = "test-transport")]
+            Socket::Memory(ref socket) => socket.send_to(buf, target),
+        }
+    }
+
+    /// Receives a datagram.
+    ///
+    /// ecn_marks - Should the ECN codepoint of the datagram be returned?
+    ///
+    /// # Returns
+    /// The length of the datagram, the address of the peer, the local address the datagram
+    /// was sent to and the ECN codepoint of the datagram, if it is known. If the socket is bound
+    /// to an unspecified address, the local address is taken from the destination address of
+    /// the datagram.
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        ecn_marks: bool,
+    ) -> io::Result<(usize, SocketAddr, SocketAddr, Option<u8>)> {
+        let local_addr = self.local_addr()?;
+
+        match *self {
+            Socket::Udp(ref socket) if local_addr.ip().is_unspecified() || ecn_marks => {
+                let (len, peer, local_ip, ecn) = packet_info::recv_from(socket, buf)?;
+                let local_addr = local_ip
+                    .map(|ip| SocketAddr::new(ip, local_addr.port()))
+                    .unwrap_or(local_addr);
+                Ok((len, peer, local_addr, ecn))
+            }
+            Socket::Udp(ref socket) => socket
+                .recv_from(buf)
+                .map(|(len, peer)| (len, peer, local_addr, None)),
+            #[cfg(feature = "test-transport")]
+            Socket::Memory(ref mut socket) => socket
+                .recv_from_with_ecn(buf)
+                .map(|(len, peer, ecn)| (len, peer, local_addr, Some(ecn).filter(|_| ecn_marks))),
+        }
+    }
+}
+
 pub struct ContextInner {
-    socket: UdpSocket,
+    sockets: Vec<Socket>,
     context: Rc<RefCell<CContext>>,
-    quic: QuicCtx,
+    /// The contexts of the connections keep `quic` alive, see `ffi::SharedConnection`.
+    quic: Rc<QuicCtx>,
     /// Temporary buffer used for receiving and sending
     buffer: Vec<u8>,
+    /// Buffer used for preparing the packets of all connections in one batch
+    batch_buffer: Vec<u8>,
+    /// The prepared datagrams that are waiting for their socket to become writable, one queue
+    /// per socket
+    pending_datagrams: Vec<VecDeque<PendingDatagram>>,
     /// Picoquic requires to be woken up to handle resend,
     /// drop of connections(because of inactivity), etc..
     timer: Timeout,
     recv_connect: UnboundedReceiver<NewConnectionMsg>,
     /// The keep alive interval for client connections
     client_keep_alive_interval: Option<Duration>,
+    heartbeat: bool,
+    peer_address_change: Option<PeerAddressChangeHandler>,
+    /// The handshake timeout for client connections
+    handshake_timeout: Option<Duration>,
+    max_recv_buffer: Option<(usize, OverflowPolicy)>,
+    /// Is set by the `Context` to enter the drain mode.
+    drain: Arc<AtomicBool>,
+    /// Are new incoming connections refused?
+    draining: bool,
+    pool: PoolHandle,
+    /// Are the ECN codepoints of the received packets counted?
+    ecn_marks: bool,
+}
+
+/// Sends the given datagram, if the socket is writable.
+///
+/// # Returns
+/// If the datagram was handed to the socket. If not, the current task is woken up, when the
+/// socket becomes writable.
+fn send_datagram(
+    socket: &Socket,
+    data: &[u8],
+    local_addr: Option<SocketAddr>,
+    to: &SocketAddr,
+) -> bool {
+    if socket.poll_write().is_not_ready() {
+        return false;
+    }
+
+    if let Err(e) = socket.send_to(data, local_addr, to) {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return false;
+        }
+
+        debug!("error while sending datagram to {}: {:?}", to, e);
+    }
+
+    true
 }
 
 impl ContextInner {
     pub fn new(
-        listen_address: &SocketAddr,
+        sockets: Vec<Socket>,
         handle: &Handle,
-        config: Config,
+        mut config: Config,
+        drain: Arc<AtomicBool>,
     ) -> Result<
         (
             ContextInner,
@@ -64,30 +212,100 @@ impl ContextInner {
             };
 
         let (send, recv) = unbounded();
-        let (context, c_ctx) = CContext::new(send, server_keep_alive_interval);
+        let heartbeat = config.heartbeat;
+        let handshake_timeout = config.handshake_timeout;
+        let max_recv_buffer = config.max_recv_buffer;
+        let ecn_marks = config.ecn_marks;
+        let (connections, streams) = config.capacity_hints.unwrap_or((0, 0));
+        let pool = Pool::new(connections, streams);
+        let peer_address_change = config
+            .peer_address_change_handler
+            .take()
+            .map(|h| Rc::new(RefCell::new(h)));
+        let (context, c_ctx) = CContext::new(
+            send,
+            server_keep_alive_interval,
+            heartbeat,
+            peer_address_change.clone(),
+            max_recv_buffer,
+            pool.clone(),
+        );
 
-        let quic = QuicCtx::new(config, c_ctx, Some(new_connection_callback))?;
+        let quic = Rc::new(QuicCtx::new(config, c_ctx, Some(new_connection_callback))?);
+        context.borrow_mut().quic = Rc::downgrade(&quic);
 
         let (send_connect, recv_connect) = unbounded();
         let connect = NewConnectionHandle { send: send_connect };
+        let pending_datagrams = sockets.iter().map(|_| VecDeque::new()).collect();
 
         Ok((
             ContextInner {
-                socket: UdpSocket::bind(listen_address, handle).context(ErrorKind::NetworkError)?,
+                sockets,
                 context,
                 quic,
                 buffer: vec![0; PICOQUIC_MAX_PACKET_SIZE as usize],
+                batch_buffer: vec![0; BATCH_SIZE * PICOQUIC_MAX_PACKET_SIZE as usize],
+                pending_datagrams,
                 timer: Timeout::new(Duration::from_secs(10), handle).context(ErrorKind::Unknown)?,
                 recv_connect,
                 client_keep_alive_interval,
+                heartbeat,
+                peer_address_change,
+                handshake_timeout,
+                max_recv_buffer,
+                drain,
+                draining: false,
+                pool,
+                ecn_marks,
             },
             recv,
             connect,
         ))
     }
 
-    pub fn local_addr(&self) -> SocketAddr {
-        self.socket.local_addr().unwrap()
+    /// Returns the statistics of the capacity that was allocated upfront for the `Connection`s.
+    pub fn capacity_stats(&self) -> Arc<Mutex<CapacityStats>> {
+        self.pool.borrow().stats()
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.sockets
+            .iter()
+            .map(|s| s.local_addr().unwrap())
+            .collect()
+    }
+
+    /// Returns the index of the socket that should be used to send packets from `local_addr` to
+    /// `peer_addr`. The socket that is bound to `local_addr` is preferred, so replies are sent
+    /// from the address the peer contacted.
+    fn socket_index(&self, local_addr: Option<SocketAddr>, peer_addr: &SocketAddr) -> usize {
+        let addrs = self.sockets.iter().map(|s| s.local_addr().unwrap());
+
+        local_addr
+            .and_then(|local| {
+                addrs.clone().position(|a| a == local).or_else(|| {
+                    addrs.clone().position(|a| {
+                        a.ip().is_unspecified()
+                            && a.port() == local.port()
+                            && a.is_ipv4() == local.is_ipv4()
+                    })
+                })
+            })
+            .or_else(|| {
+                addrs
+                    .clone()
+                    .position(|a| a.is_ipv4() == peer_addr.is_ipv4())
+            })
+            .unwrap_or(0)
+    }
+
+    /// Checks if the drain mode was entered and refuses all new incoming connections from then
+    /// on.
+    fn check_drain_mode(&mut self) {
+        if !self.draining && self.drain.load(Ordering::SeqCst) {
+            self.draining = true;
+            self.quic.set_server_busy(true);
+        }
     }
 
     /// Check if we should create a new connection
@@ -95,14 +313,17 @@ impl ContextInner {
         loop {
             match self.recv_connect.poll() {
                 Err(_) | Ok(NotReady) | Ok(Ready(None)) => break,
-                Ok(Ready(Some((addr, server_name, sender)))) => {
+                Ok(Ready(Some((addr, server_name, idle_timeout, sender)))) => {
+                    let index = self.socket_index(None, &addr);
+                    let local_addr = self.sockets[index].local_addr().unwrap();
+
                     let ctx = match Connection::new(
                         &self.quic,
                         addr,
-                        self.local_addr(),
+                        local_addr,
                         server_name,
+                        idle_timeout,
                         current_time,
-                        self.client_keep_alive_interval,
                         sender,
                     ) {
                         Ok(r) => r,
@@ -112,78 +333,212 @@ impl ContextInner {
                         }
                     };
 
+                    if let Some(interval) = self.client_keep_alive_interval {
+                        ctx.borrow().enable_keep_alive(interval);
+                    }
+
+                    if let Some(timeout) = self.handshake_timeout {
+                        ctx.borrow_mut().set_handshake_timeout(timeout);
+                    }
+
+                    if self.heartbeat {
+                        ctx.borrow_mut().enable_heartbeat();
+                    }
+
+                    if let Some((bytes, policy)) = self.max_recv_buffer {
+                        ctx.borrow_mut().set_max_recv_buffer(bytes, policy);
+                    }
+
+                    if let Some(ref handler) = self.peer_address_change {
+                        ctx.borrow_mut()
+                            .set_peer_address_change_handler(handler.clone());
+                    }
+
+                    ctx.borrow_mut().set_pool(self.pool.clone());
+
                     self.context.borrow_mut().connections.push(ctx);
                 }
             }
         }
     }
 
-    /// Iterates over all connections for data that is ready and sends it.
+    /// Prepares the data that is ready in all connections as one batch and sends it.
     fn send_connection_packets(&mut self, current_time: u64) {
-        let itr = self.quic.connection_iter();
+        if !self.send_pending_datagrams() {
+            // No socket is ready to send data, so we don't prepare packets that we may not be
+            // able to send.
+            return;
+        }
 
-        for con in itr {
-            if self.socket.poll_write().is_not_ready() {
-                // The socket is not ready to send data
-                break;
+        let batch =
+            self.quic
+                .prepare_outgoing_batch(&mut self.batch_buffer, BATCH_SIZE, current_time);
+
+        // `tokio-core` does not expose `sendmmsg`, so we send the datagrams one by one.
+        for datagram in batch {
+            let socket = self.socket_index(datagram.local_addr, &datagram.to);
+            let data = &self.batch_buffer[datagram.offset..datagram.offset + datagram.len];
+
+            // The datagram may be sent from a different socket than the one of its connection,
+            // for example while probing a new path. So, the readiness of the socket that
+            // actually sends the datagram is checked. The datagrams of a socket need to be sent
+            // in the order they were prepared, so a datagram is queued behind the datagrams that
+            // are already waiting for the socket.
+            let pending = &mut self.pending_datagrams[socket];
+
+            if !pending.is_empty()
+                || !send_datagram(
+                    &self.sockets[socket],
+                    data,
+                    datagram.local_addr,
+                    &datagram.to,
+                )
+            {
+                pending.push_back(PendingDatagram {
+                    data: data.to_vec(),
+                    local_addr: datagram.local_addr,
+                    to: datagram.to,
+                });
             }
+        }
+    }
 
-            if con.is_disconnected() {
-                con.delete();
-                break;
-            } else {
-                match con.prepare_packet(&mut self.buffer, current_time) {
-                    Ok(Some(len)) => {
-                        let _ = self.socket.send_to(&self.buffer[..len], &con.peer_addr());
-                    }
-                    Ok(None) => {}
-                    Err(e) => {
-                        error!("error while sending connections packets: {:?}", e);
-                    }
-                };
+    /// Sends the datagrams that are waiting for their socket to become writable.
+    /// A socket that is not writable, only blocks its own datagrams.
+    ///
+    /// # Returns
+    /// If at least one socket is ready to send new datagrams.
+    fn send_pending_datagrams(&mut self) -> bool {
+        let mut ready = false;
+
+        for (socket, pending) in self.sockets.iter().zip(self.pending_datagrams.iter_mut()) {
+            while let Some(sent) = pending
+                .front()
+                .map(|d| send_datagram(socket, &d.data, d.local_addr, &d.to))
+            {
+                if !sent {
+                    break;
+                }
+
+                pending.pop_front();
             }
+
+            ready |= pending.is_empty() && socket.poll_write().is_ready();
         }
+
+        ready
     }
 
-    /// Checks the `UdpSocket` for incoming data
+    /// Checks the sockets for incoming data
     fn check_for_incoming_data(&mut self, current_time: u64) {
         fn wrapper(
             buf: &mut [u8],
-            socket: &mut UdpSocket,
-            quic: &mut QuicCtx,
+            socket: &mut Socket,
+            quic: &QuicCtx,
             current_time: u64,
+            ecn_marks: bool,
         ) -> Poll<Option<()>, io::Error> {
             loop {
-                let (len, addr) = try_nb!(socket.recv_from(buf));
-                quic.incoming_data(
-                    &mut buf[..len],
-                    socket.local_addr().unwrap(),
-                    addr,
-                    current_time,
-                );
+                let (len, addr, local_addr, ecn) = try_nb!(socket.recv_from(buf, ecn_marks));
+                match quic.incoming_data(&mut buf[..len], local_addr, addr, ecn, current_time) {
+                    Routed::New(con) => {
+                        debug!("new incoming connection {} from {}", con.log_name(), addr)
+                    }
+                    Routed::Existing => trace!("processed packet from {}", addr),
+                    Routed::Dropped => trace!("dropped packet from {}", addr),
+                }
             }
         }
 
-        let _ = wrapper(
-            &mut self.buffer,
-            &mut self.socket,
-            &mut self.quic,
-            current_time,
-        );
+        for socket in &mut self.sockets {
+            let _ = wrapper(
+                &mut self.buffer,
+                socket,
+                &self.quic,
+                current_time,
+                self.ecn_marks,
+            );
+        }
     }
 
     fn send_stateless_packets(&mut self) {
         let itr = self.quic.stateless_packet_iter();
 
         for packet in itr {
-            if self.socket.poll_write().is_not_ready() {
+            let peer_addr = match packet.get_peer_addr() {
+                Some(addr) => addr,
+                None => {
+                    error!("stateless packet without a peer address");
+                    continue;
+                }
+            };
+            let local_addr = packet.get_local_addr();
+            let socket = &self.sockets[self.socket_index(local_addr, &peer_addr)];
+
+            if socket.poll_write().is_not_ready() {
                 // The socket is not ready to send data
-                break;
+                continue;
+            }
+
+            let _ = socket.send_to(packet.get_data(), local_addr, &peer_addr);
+        }
+    }
+
+    /// Processes the given datagrams synchronously, without any socket, and prepares all
+    /// datagrams the connections want to send in response. The current time is read once, so
+    /// all datagrams are processed and prepared at the same time.
+    /// Needs to be called from within a task, as the connection contexts are polled.
+    ///
+    /// local_addr - The address the datagrams were sent to.
+    ///
+    /// # Returns
+    /// The datagrams that were sent in response, with the address they were sent to.
+    #[cfg(feature = "test-transport")]
+    pub fn replay(
+        &mut self,
+        datagrams: &[(Vec<u8>, SocketAddr)],
+        local_addr: SocketAddr,
+    ) -> Vec<(Vec<u8>, SocketAddr)> {
+        let current_time = self.quic.get_current_time();
+        let mut responses = Vec::new();
+
+        for &(ref data, from) in datagrams {
+            let buf = &mut self.buffer[..data.len()];
+            buf.copy_from_slice(data);
+
+            match self
+                .quic
+                .incoming_data(buf, local_addr, from, None, current_time)
+            {
+                Routed::New(con) => {
+                    debug!("replayed new connection {} from {}", con.log_name(), from)
+                }
+                Routed::Existing => trace!("replayed packet from {}", from),
+                Routed::Dropped => trace!("dropped replayed packet from {}", from),
+            }
+        }
+
+        for packet in self.quic.stateless_packet_iter() {
+            if let Some(addr) = packet.get_peer_addr() {
+                responses.push((packet.get_data().to_vec(), addr));
+            }
+        }
+
+        assert!(self.context.borrow_mut().poll().is_ok());
+
+        loop {
+            let batch =
+                self.quic
+                    .prepare_outgoing_batch(&mut self.batch_buffer, BATCH_SIZE, current_time);
+
+            if batch.is_empty() {
+                return responses;
             }
 
-            let _ = self
-                .socket
-                .send_to(packet.get_data(), &packet.get_peer_addr());
+            for datagram in batch {
+                let data = &self.batch_buffer[datagram.offset..datagram.offset + datagram.len];
+                responses.push((data.to_vec(), datagram.to));
+            }
         }
     }
 
@@ -212,10 +567,17 @@ impl Future for ContextInner {
         loop {
             let current_time = self.quic.get_current_time();
 
+            // The drain mode needs to be entered before the incoming data is processed, so the
+            // packets that arrived after `Context::enter_drain_mode` can not create connections.
+            self.check_drain_mode();
+
             self.check_for_new_connection_request(current_time);
 
             self.check_for_incoming_data(current_time);
 
+            // The incoming data may contain new session tickets.
+            self.quic.prune_ticket_store();
+
             self.send_stateless_packets();
 
             // This checks all connection contexts if there is data that needs to be send
@@ -246,22 +608,51 @@ impl Future for ContextInner {
     }
 }
 
+impl Drop for ContextInner {
+    fn drop(&mut self) {
+        // The contexts of the connections keep `quic` alive, so the connections are deleted
+        // here to release the contexts, instead of when `quic` is dropped.
+        for cnx in self.quic.connection_iter() {
+            cnx.delete();
+        }
+
+        self.context.borrow_mut().connections.clear();
+    }
+}
+
 /// The callback context that is given as `ctx` argument to `new_connection_callback`.
 struct CContext {
     connections: Vec<Rc<RefCell<connection::Context>>>,
+    /// The `QuicCtx` of the incoming connections, it is owned by the `ContextInner`.
+    quic: Weak<QuicCtx>,
     send_con: UnboundedSender<Connection>,
     server_keep_alive_interval: Option<Duration>,
+    heartbeat: bool,
+    peer_address_change: Option<PeerAddressChangeHandler>,
+    max_recv_buffer: Option<(usize, OverflowPolicy)>,
+    pool: PoolHandle,
 }
 
 impl CContext {
     fn new(
         send_con: UnboundedSender<Connection>,
         server_keep_alive_interval: Option<Duration>,
+        heartbeat: bool,
+        peer_address_change: Option<PeerAddressChangeHandler>,
+        max_recv_buffer: Option<(usize, OverflowPolicy)>,
+        pool: PoolHandle,
     ) -> (Rc<RefCell<CContext>>, *mut c_void) {
+        let capacity = pool.borrow().stats().lock().unwrap().connection_capacity;
+
         let ctx = Rc::new(RefCell::new(CContext {
-            connections: Vec::new(),
+            connections: Vec::with_capacity(capacity),
+            quic: Weak::new(),
             send_con,
             server_keep_alive_interval,
+            heartbeat,
+            peer_address_change,
+            max_recv_buffer,
+            pool,
         }));
 
         let c_ctx = Rc::into_raw(ctx.clone()) as *mut c_void;
@@ -272,6 +663,17 @@ impl CContext {
     }
 
     fn new_connection(&mut self, con: Connection, ctx: Rc<RefCell<connection::Context>>) {
+        if let Some(ref handler) = self.peer_address_change {
+            ctx.borrow_mut()
+                .set_peer_address_change_handler(handler.clone());
+        }
+
+        if let Some((bytes, policy)) = self.max_recv_buffer {
+            ctx.borrow_mut().set_max_recv_buffer(bytes, policy);
+        }
+
+        ctx.borrow_mut().set_pool(self.pool.clone());
+
         self.connections.push(ctx);
         if self.send_con.unbounded_send(con).is_err() {
             error!("error propagating new `Connection`, the receiving side probably closed!");
@@ -285,6 +687,10 @@ impl Future for CContext {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.pool
+            .borrow()
+            .record_connections(self.connections.len());
+
         self.connections.retain(|c| {
             c.borrow_mut()
                 .poll()
@@ -311,6 +717,15 @@ unsafe extern "C" fn new_connection_callback(
 
     let ctx = get_context(ctx);
 
+    // picoquic only calls the callback, while the `ContextInner` that owns the `QuicCtx` hands
+    // packets to it or deletes the connections.
+    let quic = ctx
+        .borrow()
+        .quic
+        .upgrade()
+        .expect("`QuicCtx` of a new connection is alive");
+    let cnx = ffi::SharedConnection::new(quic, ffi::Connection::from_raw(cnx));
+
     let (con, con_ctx) = Connection::from_incoming(
         cnx,
         stream_id,
@@ -318,6 +733,7 @@ unsafe extern "C" fn new_connection_callback(
         length,
         event,
         ctx.borrow().server_keep_alive_interval,
+        ctx.borrow().heartbeat,
     );
 
     ctx.borrow_mut().new_connection(con, con_ctx);
@@ -339,10 +755,38 @@ impl NewConnectionHandle {
         &mut self,
         addr: SocketAddr,
         server_name: T,
+    ) -> NewConnectionFuture {
+        self.new_connection_impl(addr, server_name.into(), None)
+    }
+
+    /// Creates a new connection to the given server, that uses the given idle timeout instead of
+    /// the default idle timeout of picoquic.
+    /// The idle timeout is advertised to the server in the transport parameters. The effective
+    /// idle timeout of the connection is the minimum of the idle timeouts of both peers.
+    ///
+    /// addr - The address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// idle_timeout - The idle timeout of the connection.
+    pub fn new_connection_with_idle_timeout<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        idle_timeout: Duration,
+    ) -> NewConnectionFuture {
+        self.new_connection_impl(addr, server_name.into(), Some(idle_timeout))
+    }
+
+    fn new_connection_impl(
+        &mut self,
+        addr: SocketAddr,
+        server_name: String,
+        idle_timeout: Option<Duration>,
     ) -> NewConnectionFuture {
         let (sender, recv) = oneshot::channel();
 
-        let _ = self.send.unbounded_send((addr, server_name.into(), sender));
+        let _ = self
+            .send
+            .unbounded_send((addr, server_name, idle_timeout, sender));
 
         NewConnectionFuture { recv }
     }
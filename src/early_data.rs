@@ -0,0 +1,45 @@
+use accept_filter::IncomingInfo;
+
+/// The `EarlyDataFilter` trait is used by a server to decide per incoming `Connection`, if the
+/// 0-RTT data of the client should be accepted.
+pub trait EarlyDataFilter {
+    /// Will be called with the information about each incoming `Connection`, when the
+    /// `ClientHello` is processed. The decision only has an effect, if the client resumes a
+    /// session and sends 0-RTT data.
+    ///
+    /// # Returns
+    /// If the 0-RTT data should be accepted.
+    fn accept_early_data(&mut self, info: &IncomingInfo) -> bool;
+}
+
+impl<F> EarlyDataFilter for F
+where
+    F: FnMut(&IncomingInfo) -> bool,
+{
+    fn accept_early_data(&mut self, info: &IncomingInfo) -> bool {
+        self(info)
+    }
+}
+
+/// The policy of a server for the 0-RTT data (early data) of resumed sessions.
+///
+/// 0-RTT data is not protected against replays, an attacker can send the same 0-RTT data
+/// multiple times to the server. So, it should only be accepted, if all operations that can be
+/// triggered by 0-RTT data are idempotent.
+/// Rejected 0-RTT data is dropped by the server and the client retransmits it as 1-RTT data,
+/// after the handshake finished.
+pub enum EarlyDataPolicy {
+    /// All 0-RTT data is accepted (the default of picoquic).
+    Accept,
+    /// All 0-RTT data is rejected.
+    Reject,
+    /// The given filter decides per `Connection`.
+    Callback(Box<EarlyDataFilter>),
+}
+
+impl EarlyDataPolicy {
+    /// Creates an `EarlyDataPolicy::Callback` with the given filter.
+    pub fn callback<F: EarlyDataFilter + 'static>(filter: F) -> EarlyDataPolicy {
+        EarlyDataPolicy::Callback(Box::new(filter))
+    }
+}
@@ -9,14 +9,23 @@ use futures;
 
 use openssl;
 
+/// The error type of this crate.
+/// The underlying errors are available as `Fail::cause`. `Fail::compat` converts the error into
+/// a type that implements `std::error::Error`.
 #[derive(Debug)]
 pub struct Error {
     inner: Context<ErrorKind>,
+    /// The underlying error that caused this error.
+    cause: Option<Box<Fail>>,
 }
 
 impl Fail for Error {
     fn cause(&self) -> Option<&Fail> {
-        self.inner.cause()
+        match self.cause {
+            Some(ref cause) => Some(&**cause),
+            // Errors that are created by `ResultExt::context` store their cause in the `Context`.
+            None => self.inner.cause(),
+        }
     }
 
     fn backtrace(&self) -> Option<&Backtrace> {
@@ -34,37 +43,45 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         &self.inner.get_context()
     }
+
+    fn with_cause<E: Fail>(kind: ErrorKind, cause: E) -> Error {
+        Error {
+            inner: Context::new(kind),
+            cause: Some(Box::new(cause)),
+        }
+    }
 }
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error {
             inner: Context::new(kind),
+            cause: None,
         }
     }
 }
 
 impl From<Context<ErrorKind>> for Error {
     fn from(inner: Context<ErrorKind>) -> Error {
-        Error { inner }
+        Error { inner, cause: None }
     }
 }
 
 impl From<futures::Canceled> for Error {
-    fn from(_: futures::Canceled) -> Error {
-        ErrorKind::InternalError.into()
+    fn from(e: futures::Canceled) -> Error {
+        Error::with_cause(ErrorKind::InternalError, e)
     }
 }
 
 impl From<ffi::NulError> for Error {
-    fn from(_: ffi::NulError) -> Error {
-        ErrorKind::FFIError.into()
+    fn from(e: ffi::NulError) -> Error {
+        Error::with_cause(ErrorKind::FFIError, e)
     }
 }
 
 impl From<openssl::error::ErrorStack> for Error {
-    fn from(_: openssl::error::ErrorStack) -> Error {
-        ErrorKind::OpenSSLError.into()
+    fn from(e: openssl::error::ErrorStack) -> Error {
+        Error::with_cause(ErrorKind::OpenSSLError, e)
     }
 }
 
@@ -78,15 +95,16 @@ impl From<failure::Error> for Error {
 pub enum ErrorKind {
     #[fail(display = "A network error occurred.")]
     NetworkError,
-    #[fail(display = "A ffi error occurred.")]
+    #[fail(display = "A string that should be passed to C contains a null byte.")]
     FFIError,
     #[fail(display = "Could not allocate new memory.")]
     OutOfMemoryError,
-    #[fail(display = "Disconnected.")]
+    #[fail(display = "The connection is disconnected.")]
     Disconnected,
-    #[fail(display = "Unknown.")]
+    #[fail(display = "An unknown error occurred.")]
     Unknown,
-    #[fail(display = "Send failed.")]
+    /// The data that could not be sent is returned.
+    #[fail(display = "Could not send the data, because the receiving side is closed.")]
     SendError(BytesMut),
     #[fail(display = "An error occurred in the TLS handshake.")]
     TLSHandshakeError,
@@ -96,7 +114,35 @@ pub enum ErrorKind {
     NoneUnicode,
     #[fail(display = "An OpenSSL error occurred.")]
     OpenSSLError,
-    #[fail(display = "Error {}", _0)]
+    #[fail(display = "There is no open stream with the id {}.", _0)]
+    UnknownStream(u64),
+    #[fail(display = "The connection was refused by the server.")]
+    ConnectionRefused,
+    /// The server does not support the QUIC version of the client, the versions supported by the
+    /// server are returned.
+    #[fail(
+        display = "The server does not support the offered QUIC version, it supports: {:?}.",
+        _0
+    )]
+    VersionNegotiation(Vec<u32>),
+    /// The connection was closed with an error code that has no dedicated `ErrorKind`.
+    #[fail(display = "The connection was closed with the error code {:#x}.", _0)]
+    TransportError(u64),
+    #[fail(display = "The direction of the unidirectional stream does not allow this operation.")]
+    InvalidStreamDirection,
+    #[fail(display = "An error occurred while reading the data that should be sent.")]
+    ReaderError,
+    #[fail(display = "The operation did not finish before its deadline.")]
+    Timeout,
+    #[fail(display = "The handshake did not finish before the handshake timeout.")]
+    HandshakeTimeout,
+    #[fail(
+        display = "The peers do not support common TLS parameters (e.g. cipher suites or key exchange groups)."
+    )]
+    NoCommonTLSParameters,
+    #[fail(display = "The connection buffered more received data than allowed.")]
+    RecvBufferOverflow,
+    #[fail(display = "{}", _0)]
     Custom(failure::Error),
 }
 
@@ -109,3 +155,37 @@ macro_rules! bail {
         return Err(::failure::err_msg::<String>(format!($fmt, $($arg)+)).into());
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_error_displays_error_code() {
+        let err: Error = ErrorKind::TransportError(0x1a).into();
+        assert_eq!(
+            "The connection was closed with the error code 0x1a.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn error_from_nul_error_has_cause() {
+        let err: Error = ffi::CString::new("a\0b").unwrap_err().into();
+
+        assert!(err
+            .cause()
+            .and_then(|c| c.downcast_ref::<ffi::NulError>())
+            .is_some());
+    }
+
+    #[test]
+    fn compat_error_is_std_error() {
+        use std::error::Error as StdError;
+
+        let err: Error = ErrorKind::Disconnected.into();
+        let err: Box<StdError> = Box::new(err.compat());
+
+        assert_eq!("The connection is disconnected.", err.to_string());
+    }
+}
@@ -0,0 +1,145 @@
+use connection;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The type of a QUIC packet.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PacketType {
+    VersionNegotiation,
+    Initial,
+    Retry,
+    Handshake,
+    ZeroRtt,
+    OneRtt,
+    Unknown,
+}
+
+/// An event of the QUIC protocol that is reported by picoquic.
+#[derive(Debug, PartialEq, Clone)]
+pub enum QuicEvent {
+    /// A packet was sent.
+    PacketSent {
+        /// The local id of the `Connection`.
+        connection_id: connection::Id,
+        /// The label of the `Connection`, see `Connection::set_label`.
+        label: Option<String>,
+        packet_type: PacketType,
+        packet_number: u64,
+        /// The size of the packet in bytes.
+        size: usize,
+        /// The types of the frames in the packet.
+        frames: Vec<u64>,
+    },
+    /// A packet was received.
+    PacketReceived {
+        /// The local id of the `Connection`.
+        connection_id: connection::Id,
+        /// The label of the `Connection`, see `Connection::set_label`.
+        label: Option<String>,
+        packet_type: PacketType,
+        packet_number: u64,
+        /// The size of the packet in bytes.
+        size: usize,
+        /// The types of the frames in the packet.
+        frames: Vec<u64>,
+    },
+    /// A sent packet was declared as lost.
+    PacketLost {
+        /// The local id of the `Connection`.
+        connection_id: connection::Id,
+        /// The label of the `Connection`, see `Connection::set_label`.
+        label: Option<String>,
+        packet_type: PacketType,
+        packet_number: u64,
+    },
+    /// The round trip time estimation was updated.
+    RttUpdate {
+        /// The local id of the `Connection`.
+        connection_id: connection::Id,
+        /// The label of the `Connection`, see `Connection::set_label`.
+        label: Option<String>,
+        smoothed_rtt: Duration,
+        rtt_variance: Duration,
+        min_rtt: Duration,
+    },
+}
+
+/// The `EventSink` trait is used to consume the `QuicEvent`s of all `Connection`s of a `Context`.
+/// The handler is called from the `Context` while it processes the packets, so it should not
+/// block.
+pub trait EventSink {
+    /// Will be called for each `QuicEvent`.
+    fn event(&mut self, event: QuicEvent);
+}
+
+impl<F> EventSink for F
+where
+    F: FnMut(QuicEvent),
+{
+    fn event(&mut self, event: QuicEvent) {
+        self(event)
+    }
+}
+
+/// The number of frames per frame type, that were sent and received by a `Connection`.
+/// The frame types are given as their QUIC encoding, e.g. `0x02` for `ACK` or `0x10` for
+/// `MAX_DATA`. For frame types that encode flags in their type (like `STREAM` with the types
+/// `0x08` to `0x0f`), each variant is counted separately.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FrameStats {
+    sent: HashMap<u64, u64>,
+    received: HashMap<u64, u64>,
+}
+
+impl FrameStats {
+    /// Returns the number of sent frames with the given type.
+    pub fn sent(&self, frame_type: u64) -> u64 {
+        self.sent.get(&frame_type).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of received frames with the given type.
+    pub fn received(&self, frame_type: u64) -> u64 {
+        self.received.get(&frame_type).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of sent frames per frame type.
+    pub fn all_sent(&self) -> &HashMap<u64, u64> {
+        &self.sent
+    }
+
+    /// Returns the number of received frames per frame type.
+    pub fn all_received(&self) -> &HashMap<u64, u64> {
+        &self.received
+    }
+
+    pub(crate) fn add_sent(&mut self, frames: &[u64]) {
+        frames
+            .iter()
+            .for_each(|f| *self.sent.entry(*f).or_insert(0) += 1);
+    }
+
+    pub(crate) fn add_received(&mut self, frames: &[u64]) {
+        frames
+            .iter()
+            .for_each(|f| *self.received.entry(*f).or_insert(0) += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_stats_count_per_frame_type() {
+        let mut stats = FrameStats::default();
+        stats.add_sent(&[0x02, 0x08, 0x02]);
+        stats.add_received(&[0x10]);
+
+        assert_eq!(2, stats.sent(0x02));
+        assert_eq!(1, stats.sent(0x08));
+        assert_eq!(0, stats.sent(0x10));
+        assert_eq!(1, stats.received(0x10));
+        assert_eq!(0, stats.received(0x02));
+    }
+}
@@ -0,0 +1,204 @@
+use accept_filter::{AcceptDecision, AcceptFilter, IncomingInfo};
+use early_data::EarlyDataFilter;
+use ffi::{early_data, Connection, QuicCtx};
+
+use picoquic_sys::picoquic::{
+    picoquic_cnx_t, ptls_context_t, ptls_get_data_ptr, ptls_iovec_t,
+    ptls_on_client_hello_parameters_t, ptls_on_client_hello_t, ptls_t,
+    PTLS_ALERT_HANDSHAKE_FAILURE, PTLS_ALERT_INTERNAL_ERROR,
+};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::str;
+
+/// The context of the client hello callback, that calls the accept filter and the early data
+/// filter.
+/// picotls calls the callback with a pointer to `on_client_hello`, so it needs to be the first
+/// field.
+#[repr(C)]
+pub struct Context {
+    on_client_hello: ptls_on_client_hello_t,
+    /// The client hello callback of picoquic, that selects the ALPN protocol.
+    next: *mut ptls_on_client_hello_t,
+    filter: Option<Box<AcceptFilter>>,
+    early_data: Option<early_data::Filter>,
+    /// Did the filter ask the client of the current client hello to retry?
+    retry_requested: Cell<bool>,
+}
+
+impl Context {
+    /// Is an accept filter set?
+    pub fn has_filter(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// Returns if the filter asked the client of the last processed client hello to retry.
+    /// The connection of this client hello needs to be deleted and the `Initial` packet needs to
+    /// be answered with a stateless retry.
+    pub fn take_retry_request(&self) -> bool {
+        self.retry_requested.replace(false)
+    }
+}
+
+/// The leading fields of the TLS context of picoquic (`picoquic_tls_ctx_t`), that is not part of
+/// the public headers of picoquic.
+#[repr(C)]
+struct TlsCtx {
+    tls: *mut ptls_t,
+    cnx: *mut picoquic_cnx_t,
+}
+
+thread_local! {
+    /// The validated addresses of the clients, whose packets are currently processed by
+    /// picoquic. Set by `QuicCtx::incoming_data`, as picoquic processes the client hello while it
+    /// processes the `Initial` packet.
+    static VALIDATED_ADDRS: RefCell<HashSet<SocketAddr>> = RefCell::new(HashSet::new());
+}
+
+/// Marks the address of the client as validated, while picoquic processes its packet.
+pub fn set_address_validated(addr: SocketAddr, validated: bool) {
+    VALIDATED_ADDRS.with(|a| {
+        if validated {
+            a.borrow_mut().insert(addr);
+        } else {
+            a.borrow_mut().remove(&addr);
+        }
+    });
+}
+
+/// Sets up the client hello callback in picotls, that calls the given filters before the
+/// client hello callback of picoquic. The information about the incoming connection is only
+/// collected once per client hello for both filters.
+///
+/// # Returns
+/// The context of the callback. It needs to outlive the `QuicCtx`, as picotls stores a pointer
+/// to it.
+pub fn setup_callback(
+    quic: &QuicCtx,
+    filter: Option<Box<AcceptFilter>>,
+    early_data: Option<Box<EarlyDataFilter>>,
+) -> Box<Context> {
+    unsafe {
+        let tls_ctx = (*quic.as_ptr()).tls_master_ctx as *mut ptls_context_t;
+
+        let mut ctx = Box::new(Context {
+            on_client_hello: ptls_on_client_hello_t {
+                cb: Some(on_client_hello_callback),
+            },
+            next: (*tls_ctx).on_client_hello,
+            filter,
+            early_data: None,
+            retry_requested: Cell::new(false),
+        });
+
+        (*tls_ctx).on_client_hello = &mut ctx.on_client_hello;
+
+        // The early data filter copies the TLS context, after the callback was set. So, the
+        // callback is also called for a second client hello on the copy.
+        ctx.early_data = early_data.map(|f| early_data::Filter::new(quic, f));
+
+        ctx
+    }
+}
+
+/// Returns the address of the peer of the connection that belongs to the given TLS object.
+unsafe fn peer_addr(tls: *mut ptls_t) -> Option<SocketAddr> {
+    // picoquic stores its TLS context as data pointer of the TLS object.
+    let tls_ctx = *ptls_get_data_ptr(tls) as *mut TlsCtx;
+
+    if tls_ctx.is_null() || (*tls_ctx).cnx.is_null() {
+        return None;
+    }
+
+    // Called by picotls while picoquic processes the client hello, so the connection is alive.
+    Connection::from_raw((*tls_ctx).cnx).peer_addr()
+}
+
+fn iovec_to_string(iovec: &ptls_iovec_t) -> String {
+    if iovec.base.is_null() {
+        return String::new();
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(iovec.base, iovec.len) };
+    str::from_utf8(bytes).unwrap_or("").to_owned()
+}
+
+/// Returns the information about the incoming connection of the given client hello.
+unsafe fn incoming_info(
+    tls: *mut ptls_t,
+    params: *mut ptls_on_client_hello_parameters_t,
+) -> Option<IncomingInfo> {
+    let peer_addr = match peer_addr(tls) {
+        Some(peer_addr) => peer_addr,
+        None => {
+            error!("could not find the connection of a client hello");
+            return None;
+        }
+    };
+
+    let server_name = if (*params).server_name.base.is_null() {
+        None
+    } else {
+        Some(iovec_to_string(&(*params).server_name))
+    };
+
+    let protocols = &(*params).negotiated_protocols;
+    let alpn = if protocols.list.is_null() {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(protocols.list, protocols.count)
+            .iter()
+            .map(iovec_to_string)
+            .collect()
+    };
+
+    Some(IncomingInfo {
+        server_name,
+        alpn,
+        peer_addr,
+        address_validated: VALIDATED_ADDRS.with(|a| a.borrow().contains(&peer_addr)),
+    })
+}
+
+/// Will be called by picotls, when the client hello of a client is processed.
+unsafe extern "C" fn on_client_hello_callback(
+    ctx: *mut ptls_on_client_hello_t,
+    tls: *mut ptls_t,
+    params: *mut ptls_on_client_hello_parameters_t,
+) -> c_int {
+    let ctx = &mut *(ctx as *mut Context);
+    let info = incoming_info(tls, params);
+
+    if let (Some(filter), Some(info)) = (ctx.filter.as_mut(), info.as_ref()) {
+        // A panic must not unwind into the c code.
+        match panic::catch_unwind(AssertUnwindSafe(|| filter.filter(info))) {
+            Ok(AcceptDecision::Accept) => {}
+            Ok(AcceptDecision::Reject(alert)) => return c_int::from(alert),
+            Ok(AcceptDecision::Retry) if info.address_validated => {}
+            Ok(AcceptDecision::Retry) => {
+                // The handshake is aborted and `QuicCtx::incoming_data` deletes the connection,
+                // before the error can be sent to the client.
+                ctx.retry_requested.set(true);
+                return PTLS_ALERT_HANDSHAKE_FAILURE as c_int;
+            }
+            Err(_) => {
+                error!("accept filter panicked!");
+                return PTLS_ALERT_INTERNAL_ERROR as c_int;
+            }
+        }
+    }
+
+    if let Some(ref mut early_data) = ctx.early_data {
+        early_data.check(tls, info.as_ref());
+    }
+
+    match ctx.next.as_ref().and_then(|next| next.cb) {
+        Some(cb) => cb(ctx.next, tls, params),
+        None => 0,
+    }
+}
@@ -1,39 +1,132 @@
-use super::quic_ctx::{socket_addr_from_c, MicroSeconds, QuicCtx};
+use super::quic_ctx::{socket_addr_from_c, socket_addr_from_storage, MicroSeconds, QuicCtx};
+use super::verify_certificate;
 use connection;
 use error::*;
+use event_sink::FrameStats;
 use stream;
+use transport_parameters;
 use ConnectionType;
 
 use picoquic_sys::picoquic::{
-    self, picoquic_close, picoquic_cnx_t, picoquic_create_client_cnx, picoquic_delete_cnx,
-    picoquic_enable_keep_alive, picoquic_get_cnx_state, picoquic_get_first_cnx,
-    picoquic_get_local_addr, picoquic_get_local_cnxid, picoquic_get_local_error,
-    picoquic_get_next_cnx, picoquic_get_peer_addr, picoquic_get_remote_error, picoquic_is_client,
-    picoquic_prepare_packet, picoquic_quic_t, picoquic_state_enum_picoquic_state_client_ready,
+    self, picoquic_add_to_stream, picoquic_bbr_algorithm, picoquic_close, picoquic_cnx_t,
+    picoquic_connection_error, picoquic_connection_id_t, picoquic_create_cnx,
+    picoquic_cubic_algorithm, picoquic_delete_cnx, picoquic_enable_keep_alive,
+    picoquic_find_stream, picoquic_get_application_error, picoquic_get_cnx_state,
+    picoquic_get_first_cnx, picoquic_get_local_addr, picoquic_get_local_cnxid,
+    picoquic_get_local_error, picoquic_get_next_cnx, picoquic_get_peer_addr,
+    picoquic_get_remote_close_reason, picoquic_get_remote_cnxid, picoquic_get_remote_error,
+    picoquic_get_remote_extra_transport_parameters, picoquic_is_0rtt_available, picoquic_is_client,
+    picoquic_is_cnx_backlog_empty, picoquic_newreno_algorithm,
+    picoquic_packet_context_enum_picoquic_packet_context_application, picoquic_prepare_packet,
+    picoquic_quic_t, picoquic_set_callback, picoquic_set_congestion_algorithm,
+    picoquic_set_local_close_reason, picoquic_set_stream_flow_control_paused,
+    picoquic_start_client_cnx, picoquic_state_enum_picoquic_state_client_ready,
     picoquic_state_enum_picoquic_state_disconnected,
-    picoquic_state_enum_picoquic_state_server_ready, picoquic_val64_connection_id,
-    PICOQUIC_ERROR_DISCONNECTED, PICOQUIC_TLS_HANDSHAKE_FAILED,
+    picoquic_state_enum_picoquic_state_server_ready, picoquic_tls_get_negotiated_alpn,
+    picoquic_tls_is_psk_handshake, picoquic_val64_connection_id, ptls_get_cipher, ptls_t,
+    PICOQUIC_ERROR_DISCONNECTED, PICOQUIC_ERROR_IDLE_TIMEOUT, PICOQUIC_TLS_HANDSHAKE_FAILED,
+    PICOQUIC_TRANSPORT_FLOW_CONTROL_ERROR, PICOQUIC_TRANSPORT_SERVER_BUSY,
+    PTLS_ALERT_HANDSHAKE_FAILURE,
 };
 
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::mem;
 use std::net::SocketAddr;
 use std::ptr;
+use std::rc::Rc;
+use std::slice;
 use std::time::Duration;
 
 use socket2::SockAddr;
 
+use openssl::x509::X509;
+
+/// Returns the bytes of the given connection id.
+fn connection_id_bytes(id: picoquic_connection_id_t) -> Vec<u8> {
+    let len = (id.id_len as usize).min(id.id.len());
+    id.id[..len].to_vec()
+}
+
+/// The share of a connection in the weighted send scheduler of `QuicCtx`.
+struct SendShare {
+    weight: u32,
+    /// The round of the scheduler, `credit` belongs to.
+    round: u64,
+    /// The number of datagrams the connection may still send in `round`.
+    credit: u32,
+}
+
+/// The state of a connection that is kept by this crate, because picoquic has no field for it.
+#[derive(Default)]
+struct State {
+    /// The counted frames, if the frames are counted.
+    frame_stats: Option<FrameStats>,
+    /// The number of lost `Initial` and `Handshake` packets.
+    handshake_packets_lost: u32,
+    /// The share in the weighted send scheduler, once the connection was weighted or scheduled.
+    send_share: Option<SendShare>,
+    handshake_flights: HandshakeFlights,
+    /// The received packets per ECN codepoint, as picoquic does not see the codepoints.
+    received_ecn_counts: connection::EcnCounts,
+    label: Option<String>,
+    /// The reason phrase that is sent on close, as picoquic only stores a pointer to it.
+    close_reason: Option<CString>,
+}
+
+/// The handshake flights that were sent by a connection.
+#[derive(Default)]
+struct HandshakeFlights {
+    count: u32,
+    /// The number of bytes received from the peer, when the current flight was started.
+    received: u64,
+}
+
+thread_local! {
+    /// The `State` of each connection that is owned by this crate, see `Connection::with_state`.
+    static STATES: RefCell<HashMap<usize, State>> = RefCell::new(HashMap::new());
+    /// The `State`s of the connections that picoquic created while it processes an incoming
+    /// packet, see `collect_new_states`.
+    static NEW_STATES: RefCell<Option<HashMap<usize, State>>> = RefCell::default();
+}
+
+/// Starts to collect the `State`s of the connections that picoquic creates while it processes an
+/// incoming packet. picoquic may create and free a connection on its own while it processes the
+/// packet, so the `State`s are only kept for the connections that are adopted with
+/// `Connection::adopt_state`.
+pub fn collect_new_states() {
+    NEW_STATES.with(|n| *n.borrow_mut() = Some(HashMap::new()));
+}
+
+/// Stops to collect the `State`s of new connections and drops the `State`s of the connections
+/// that were not adopted, see `collect_new_states`.
+pub fn discard_new_states() {
+    NEW_STATES.with(|n| n.borrow_mut().take());
+}
+
+/// A connection of picoquic. The connection is owned by the `QuicCtx` that created it and is
+/// freed with it, so the lifetime `'ctx` ties the `Connection` to the borrow of its `QuicCtx`.
+/// The contexts of the callbacks outlive any borrow of the `QuicCtx`, so they store a
+/// `SharedConnection` instead.
 #[derive(Copy, Clone)]
-pub struct Connection {
+pub struct Connection<'ctx> {
     cnx: *mut picoquic_cnx_t,
+    _quic: PhantomData<&'ctx QuicCtx>,
 }
 
-impl Connection {
+impl<'ctx> Connection<'ctx> {
+    /// Creates a new client connection to the given server.
+    /// The given idle timeout is advertised to the server, instead of the default idle timeout of
+    /// picoquic.
     pub fn new(
-        quic: &QuicCtx,
+        quic: &'ctx QuicCtx,
         server_addr: SocketAddr,
         current_time: u64,
         server_name: String,
-    ) -> Result<Connection, Error> {
+        idle_timeout: Option<Duration>,
+    ) -> Result<Connection<'ctx>, Error> {
         assert!(
             !server_addr.ip().is_unspecified(),
             "server address must not be unspecified!"
@@ -43,16 +136,22 @@ impl Connection {
 
         let server_name = CString::new(server_name)?;
 
+        let null_id = picoquic_connection_id_t {
+            id: [0; 20],
+            id_len: 0,
+        };
+
         let cnx = unsafe {
-            picoquic_create_client_cnx(
+            picoquic_create_cnx(
                 quic.as_ptr(),
+                null_id,
+                null_id,
                 server_addr.as_ptr() as *mut picoquic::sockaddr,
                 current_time,
                 0,
                 server_name.as_c_str().as_ptr(),
-                ptr::null_mut(),
-                None,
-                ptr::null_mut(),
+                quic.alpn(),
+                1,
             )
         };
 
@@ -60,7 +159,47 @@ impl Connection {
             Err(ErrorKind::Unknown)?;
         }
 
-        Ok(Connection { cnx })
+        let cnx = Connection {
+            cnx,
+            _quic: PhantomData,
+        };
+        STATES.with(|s| s.borrow_mut().insert(cnx.cnx as usize, State::default()));
+
+        // The callback is set by the `Context` of the connection, until then picoquic should not
+        // call the default callback of the `QuicCtx`.
+        unsafe {
+            picoquic_set_callback(cnx.cnx, None, ptr::null_mut());
+        }
+
+        if let Some(rtt) = quic.initial_rtt() {
+            cnx.set_initial_rtt(rtt);
+        }
+
+        if let Some(timeout) = idle_timeout {
+            cnx.set_idle_timeout(timeout);
+        }
+
+        // The transport parameters are encoded into the ClientHello, so the handshake is started
+        // after all parameters are set.
+        if unsafe { picoquic_start_client_cnx(cnx.cnx) } != 0 {
+            cnx.delete();
+            Err(ErrorKind::Unknown)?;
+        }
+
+        Ok(cnx)
+    }
+
+    /// Seeds the round trip time estimator with the given round trip time.
+    /// The retransmission timer is set like it is done by picoquic for its default value.
+    fn set_initial_rtt(self, rtt: Duration) {
+        let rtt = rtt.as_micro_seconds();
+
+        unsafe {
+            let path = *(*self.cnx).path;
+            (*path).smoothed_rtt = rtt;
+            (*path).rtt_variant = rtt / 2;
+            (*path).retransmit_timer = rtt * 3;
+        }
     }
 
     pub fn as_ptr(self) -> *mut picoquic_cnx_t {
@@ -68,7 +207,9 @@ impl Connection {
     }
 
     /// Returns the peer address of this connection.
-    pub fn peer_addr(self) -> SocketAddr {
+    /// If picoquic does not know the address (e.g. before a path is established), `None` is
+    /// returned.
+    pub fn peer_addr(self) -> Option<SocketAddr> {
         let mut addr_len = 0;
         let mut addr: *mut picoquic::sockaddr = ptr::null_mut();
 
@@ -80,7 +221,9 @@ impl Connection {
     }
 
     /// Returns the local address of this connection.
-    pub fn local_addr(self) -> SocketAddr {
+    /// If picoquic does not know the address (e.g. before a path is established), `None` is
+    /// returned.
+    pub fn local_addr(self) -> Option<SocketAddr> {
         let mut addr_len = 0;
         let mut addr: *mut picoquic::sockaddr = ptr::null_mut();
 
@@ -96,13 +239,20 @@ impl Connection {
     /// The `Packet` will be stored in the given buffer.
     ///
     /// # Returns
-    /// The length of the `Packet` in the buffer or `None` if the packet does not contains any data.
+    /// The length of the `Packet` in the buffer, the local address the `Packet` should be sent
+    /// from and the address of the peer, the `Packet` should be sent to. If picoquic did not
+    /// select a local address, the local address is `None`.
+    /// If the packet does not contains any data, `None` is returned.
     pub fn prepare_packet(
         self,
         buffer: &mut [u8],
         current_time: u64,
-    ) -> Result<Option<usize>, Error> {
+    ) -> Result<Option<(usize, Option<SocketAddr>, SocketAddr)>, Error> {
         let mut send_len = 0;
+        let mut addr_to: picoquic::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut addr_from: picoquic::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut if_index = 0;
+
         let ret = unsafe {
             picoquic_prepare_packet(
                 self.as_ptr(),
@@ -110,6 +260,9 @@ impl Connection {
                 buffer.as_mut_ptr(),
                 buffer.len(),
                 &mut send_len,
+                &mut addr_to,
+                &mut addr_from,
+                &mut if_index,
             )
         };
 
@@ -117,7 +270,17 @@ impl Connection {
             Err(ErrorKind::Disconnected.into())
         } else if ret == 0 {
             if send_len > 0 {
-                Ok(Some(send_len))
+                let to = match socket_addr_from_storage(&mut addr_to).or_else(|| self.peer_addr()) {
+                    Some(to) => to,
+                    None => bail!("Packet has no peer address"),
+                };
+                let from = socket_addr_from_storage(&mut addr_from);
+
+                if !self.is_ready() {
+                    self.record_handshake_packet();
+                }
+
+                Ok(Some((send_len, from, to)))
             } else {
                 Ok(None)
             }
@@ -128,11 +291,199 @@ impl Connection {
 
     /// Deletes the underlying C pointer!
     pub fn delete(self) {
+        verify_certificate::remove_peer_certificates(self.cnx);
+        STATES.with(|s| s.borrow_mut().remove(&(self.cnx as usize)));
+
         unsafe {
             picoquic_delete_cnx(self.cnx);
         }
     }
 
+    /// Records a packet that was sent before the handshake finished. A packet starts a new
+    /// flight, if the peer sent data since the current flight was started.
+    fn record_handshake_packet(self) {
+        let received = unsafe { (**(*self.cnx).path).received };
+
+        self.with_state(|s| {
+            let flights = &mut s.handshake_flights;
+
+            if flights.count == 0 || flights.received != received {
+                flights.count += 1;
+                flights.received = received;
+            }
+        });
+    }
+
+    /// Returns the number of flights of packets that were sent, before the handshake finished.
+    /// A flight ends, when data from the peer is received.
+    pub fn handshake_flight_count(self) -> u32 {
+        self.with_state(|s| s.handshake_flights.count).unwrap_or(0)
+    }
+
+    /// Counts the given ECN codepoint of a packet that was received for this connection.
+    pub fn record_received_ecn(self, ecn: u8) {
+        self.with_state(|s| {
+            let counts = &mut s.received_ecn_counts;
+
+            match ecn & 0x03 {
+                0x00 => counts.not_ect += 1,
+                0x01 => counts.ect1 += 1,
+                0x02 => counts.ect0 += 1,
+                _ => counts.ce += 1,
+            }
+        });
+    }
+
+    /// Returns the number of received packets per ECN codepoint.
+    pub fn received_ecn_counts(self) -> connection::EcnCounts {
+        self.with_state(|s| s.received_ecn_counts)
+            .unwrap_or_default()
+    }
+
+    /// Sets the congestion control algorithm of this connection. picoquic resets the state of
+    /// the congestion controller.
+    pub fn set_congestion_algorithm(self, algorithm: connection::CongestionAlgorithm) {
+        unsafe {
+            let algorithm = match algorithm {
+                connection::CongestionAlgorithm::NewReno => picoquic_newreno_algorithm,
+                connection::CongestionAlgorithm::Cubic => picoquic_cubic_algorithm,
+                connection::CongestionAlgorithm::Bbr => picoquic_bbr_algorithm,
+            };
+
+            picoquic_set_congestion_algorithm(self.cnx, algorithm);
+        }
+    }
+
+    /// Returns the congestion control algorithm of this connection.
+    pub fn congestion_algorithm(self) -> Option<connection::CongestionAlgorithm> {
+        unsafe {
+            let algorithm = (*self.cnx).congestion_alg;
+
+            if algorithm == picoquic_newreno_algorithm {
+                Some(connection::CongestionAlgorithm::NewReno)
+            } else if algorithm == picoquic_cubic_algorithm {
+                Some(connection::CongestionAlgorithm::Cubic)
+            } else if algorithm == picoquic_bbr_algorithm {
+                Some(connection::CongestionAlgorithm::Bbr)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Sets the weight of this connection in the send scheduler. A weight of `0` is treated as
+    /// `1`.
+    pub fn set_weight(self, weight: u32) {
+        let weight = weight.max(1);
+
+        self.with_state(|s| match s.send_share {
+            Some(ref mut share) => share.weight = weight,
+            None => {
+                s.send_share = Some(SendShare {
+                    weight,
+                    round: 0,
+                    credit: 0,
+                })
+            }
+        });
+    }
+
+    /// Returns the number of datagrams this connection may send in the given round of the send
+    /// scheduler. At the start of a new round, the credit is refilled to the weight.
+    pub fn send_credit(self, round: u64) -> u32 {
+        self.with_state(|s| {
+            let share = s.send_share.get_or_insert(SendShare {
+                weight: 1,
+                round,
+                credit: 1,
+            });
+
+            if share.round != round {
+                share.round = round;
+                share.credit = share.weight;
+            }
+
+            share.credit
+        })
+        .unwrap_or(1)
+    }
+
+    /// Sets the number of datagrams this connection may still send in the current round of the
+    /// send scheduler.
+    pub fn set_send_credit(self, credit: u32) {
+        self.with_state(|s| {
+            if let Some(ref mut share) = s.send_share {
+                share.credit = credit;
+            }
+        });
+    }
+
+    /// Keeps the `State` of this connection, as it was accepted by this crate. The `State` may
+    /// already be collected, while picoquic processed the packet that created the connection,
+    /// see `collect_new_states`.
+    pub fn adopt_state(self) {
+        let key = self.cnx as usize;
+        let state = NEW_STATES
+            .with(|n| n.borrow_mut().as_mut().and_then(|n| n.remove(&key)))
+            .unwrap_or_default();
+
+        STATES.with(|s| {
+            s.borrow_mut().entry(key).or_insert(state);
+        });
+    }
+
+    /// Calls the given function with the `State` of this connection.
+    /// picoquic also reports connections to the callbacks, that it creates and frees on its own.
+    /// So, only the connections that were created or accepted by this crate have a `State` and
+    /// the function is not called for any other connection. The only exception are the
+    /// connections that are created by the incoming packet that is currently processed, see
+    /// `collect_new_states`.
+    fn with_state<R, F: FnOnce(&mut State) -> R>(self, f: F) -> Option<R> {
+        let key = self.cnx as usize;
+
+        STATES.with(|s| match s.borrow_mut().get_mut(&key) {
+            Some(state) => Some(f(state)),
+            None => NEW_STATES.with(|n| {
+                n.borrow_mut()
+                    .as_mut()
+                    .map(|n| f(n.entry(key).or_insert_with(State::default)))
+            }),
+        })
+    }
+
+    /// Counts a lost packet in the `Initial` or `Handshake` packet number space.
+    pub fn record_handshake_packet_lost(self) {
+        self.with_state(|s| s.handshake_packets_lost += 1);
+    }
+
+    /// Returns the number of lost packets in the `Initial` and `Handshake` packet number spaces.
+    pub fn handshake_packets_lost(self) -> u32 {
+        self.with_state(|s| s.handshake_packets_lost).unwrap_or(0)
+    }
+
+    /// Counts the frames of a sent or received packet of this connection.
+    pub fn count_frames(self, frames: &[u64], received: bool) {
+        self.with_state(|s| {
+            let stats = s.frame_stats.get_or_insert_with(FrameStats::default);
+
+            if received {
+                stats.add_received(frames);
+            } else {
+                stats.add_sent(frames);
+            }
+        });
+    }
+
+    /// Returns the counted frames of this connection, if the frames are counted.
+    pub fn frame_stats(self) -> Option<FrameStats> {
+        self.with_state(|s| s.frame_stats.clone()).and_then(|s| s)
+    }
+
+    /// Returns the certificates that were presented by the peer in the TLS handshake.
+    pub fn peer_certificates(self) -> Option<Vec<X509>> {
+        verify_certificate::peer_certificates(self.cnx)
+    }
+
     pub fn is_disconnected(self) -> bool {
         self.state() == picoquic_state_enum_picoquic_state_disconnected
     }
@@ -144,14 +495,64 @@ impl Connection {
             || state == picoquic_state_enum_picoquic_state_server_ready
     }
 
+    /// Is the handshake confirmed?
+    /// A client confirms the handshake when it receives the `HANDSHAKE_DONE` frame. A server
+    /// confirms the handshake, when the handshake is finished.
+    pub fn is_handshake_confirmed(self) -> bool {
+        match self.con_type() {
+            ConnectionType::Outgoing => unsafe { (*self.cnx).is_handshake_done_received != 0 },
+            ConnectionType::Incoming => self.is_ready(),
+        }
+    }
+
+    /// Returns the state of the connection.
+    /// The closing states of picoquic are mapped to `Closing`, if the connection was closed
+    /// locally, and to `Draining`, if the peer closed the connection.
+    pub fn connection_state(self) -> connection::State {
+        match self.state() {
+            picoquic::picoquic_state_enum_picoquic_state_client_ready
+            | picoquic::picoquic_state_enum_picoquic_state_server_ready => connection::State::Ready,
+            picoquic::picoquic_state_enum_picoquic_state_disconnecting
+            | picoquic::picoquic_state_enum_picoquic_state_closing => connection::State::Closing,
+            picoquic::picoquic_state_enum_picoquic_state_closing_received
+            | picoquic::picoquic_state_enum_picoquic_state_draining => connection::State::Draining,
+            picoquic::picoquic_state_enum_picoquic_state_disconnected => {
+                connection::State::Disconnected
+            }
+            _ => connection::State::Handshaking,
+        }
+    }
+
     fn state(self) -> u32 {
         unsafe { picoquic_get_cnx_state(self.cnx) }
     }
 
-    pub fn close(self) {
-        //TODO maybe replace 0 with an appropriate error code
+    /// Closes the connection with the given application error code and reason phrase.
+    pub fn close(self, error_code: u16, reason: &str) {
+        unsafe {
+            if !reason.is_empty() {
+                let reason = CString::new(reason.replace('\0', "")).unwrap_or_default();
+
+                // The phrase is only handed to picoquic, if it is kept alive by the `State`.
+                self.with_state(|s| {
+                    picoquic_set_local_close_reason(self.cnx, reason.as_ptr());
+                    s.close_reason = Some(reason);
+                });
+            }
+
+            picoquic_close(self.cnx, error_code);
+        }
+    }
+
+    /// Closes the connection with the transport error `FLOW_CONTROL_ERROR`. The error is
+    /// reported for `STREAM` frames, as they carried the data that exceeded the limit.
+    pub fn close_with_flow_control_error(self) {
         unsafe {
-            picoquic_close(self.cnx, 0);
+            picoquic_connection_error(
+                self.cnx,
+                PICOQUIC_TRANSPORT_FLOW_CONTROL_ERROR as _,
+                STREAM_FRAME_TYPE,
+            );
         }
     }
 
@@ -193,10 +594,40 @@ impl Connection {
     pub fn local_id(self) -> connection::Id {
         unsafe {
             let id = picoquic_get_local_cnxid(self.as_ptr());
-            picoquic_val64_connection_id(id)
+            picoquic_val64_connection_id(id).into()
         }
     }
 
+    /// Sets the label of this connection, that is included in the log lines and the
+    /// `QuicEvent`s of this connection.
+    pub fn set_label(self, label: String) {
+        self.with_state(|s| s.label = Some(label));
+    }
+
+    /// Returns the label of this connection, if a label is set.
+    pub fn label(self) -> Option<String> {
+        self.with_state(|s| s.label.clone()).and_then(|l| l)
+    }
+
+    /// Returns the name of this connection for log lines. The name is the local id, followed by
+    /// the label in brackets, if a label is set.
+    pub fn log_name(self) -> String {
+        match self.label() {
+            Some(label) => format!("{} [{}]", self.local_id(), label),
+            None => self.local_id().to_string(),
+        }
+    }
+
+    /// Returns the bytes of the local connection id for this connection.
+    pub fn local_id_bytes(self) -> Vec<u8> {
+        connection_id_bytes(unsafe { picoquic_get_local_cnxid(self.as_ptr()) })
+    }
+
+    /// Returns the bytes of the remote connection id for this connection.
+    pub fn remote_id_bytes(self) -> Vec<u8> {
+        connection_id_bytes(unsafe { picoquic_get_remote_cnxid(self.as_ptr()) })
+    }
+
     /// Returns the type of this connection.
     pub fn con_type(self) -> ConnectionType {
         unsafe {
@@ -208,6 +639,432 @@ impl Connection {
         }
     }
 
+    /// Returns the number of packets that were sent over this connection.
+    pub fn packets_sent(self) -> u64 {
+        unsafe {
+            (*self.cnx)
+                .pkt_ctx
+                .iter()
+                .map(|ctx| ctx.send_sequence)
+                .sum()
+        }
+    }
+
+    /// Returns the number of packets that were declared as lost on this connection.
+    pub fn packets_lost(self) -> u64 {
+        unsafe { (*self.cnx).nb_retransmission_total }
+    }
+
+    /// Is the peer blocked by the connection level flow control?
+    /// This is the case, when the peer sent all the data that we allowed it to send.
+    pub fn is_peer_data_blocked(self) -> bool {
+        unsafe { (*self.cnx).data_received >= (*self.cnx).maxdata_local }
+    }
+
+    /// Is the peer blocked by the flow control of the given stream?
+    /// This is the case, when the peer sent all the data that we allowed it to send.
+    pub fn is_peer_stream_data_blocked(self, id: stream::Id) -> bool {
+        unsafe {
+            let stream = picoquic_find_stream(self.cnx, id, 0);
+
+            if stream.is_null() {
+                false
+            } else {
+                (*stream).consumed_offset >= (*stream).maxdata_local
+            }
+        }
+    }
+
+    /// Returns the number of bytes that are queued on the `Stream` with the given id, but were not
+    /// sent yet.
+    pub fn queued_stream_bytes(self, id: stream::Id) -> usize {
+        unsafe {
+            let stream = picoquic_find_stream(self.cnx, id, 0);
+
+            if stream.is_null() {
+                return 0;
+            }
+
+            let mut queued = 0;
+            let mut data = (*stream).send_queue;
+
+            while !data.is_null() {
+                queued += (*data).length;
+                data = (*data).next_stream_data;
+            }
+
+            queued
+        }
+    }
+
+    /// Returns the number of stream bytes that were sent on this connection, without
+    /// retransmissions.
+    pub fn sent_stream_bytes(self) -> u64 {
+        unsafe { (*self.cnx).data_sent }
+    }
+
+    /// Returns the ALPN protocol that was negotiated in the TLS handshake.
+    pub fn negotiated_alpn(self) -> Option<String> {
+        unsafe {
+            let alpn = picoquic_tls_get_negotiated_alpn(self.cnx);
+
+            if alpn.is_null() {
+                None
+            } else {
+                CStr::from_ptr(alpn).to_str().ok().map(Into::into)
+            }
+        }
+    }
+
+    /// Returns the smoothed round trip time of the default path.
+    pub fn rtt(self) -> Duration {
+        unsafe { Duration::from_micro_seconds((**(*self.cnx).path).smoothed_rtt) }
+    }
+
+    /// Returns the estimated bandwidth of the default path in bytes per second.
+    pub fn bandwidth_estimate(self) -> u64 {
+        unsafe { (**(*self.cnx).path).bandwidth_estimate }
+    }
+
+    /// Returns the rate in bytes per second, that is used for pacing the packets on the default
+    /// path.
+    pub fn pacing_rate(self) -> u64 {
+        unsafe { (**(*self.cnx).path).pacing_rate }
+    }
+
+    /// Returns the limit that currently prevents this connection from sending faster.
+    pub fn send_limitation(self) -> connection::SendLimit {
+        unsafe {
+            let path = &**(*self.cnx).path;
+
+            if path.bytes_in_transit >= path.cwin {
+                connection::SendLimit::CongestionLimited
+            } else if (*self.cnx).data_sent >= (*self.cnx).maxdata_remote {
+                connection::SendLimit::FlowControlLimited
+            } else if path.pacing_bucket_nanosec <= 0 {
+                connection::SendLimit::PacingLimited
+            } else {
+                connection::SendLimit::AppLimited
+            }
+        }
+    }
+
+    /// Returns the internal state of the congestion controller of the default path, as it is
+    /// reported by the `alg_observe` function of the congestion algorithm.
+    pub fn congestion_debug(self) -> Option<connection::CongestionDebug> {
+        unsafe {
+            let alg: *const picoquic::picoquic_congestion_algorithm_t = (*self.cnx).congestion_alg;
+            let observe = alg.as_ref().and_then(|a| a.alg_observe)?;
+
+            let mut state = 0;
+            let mut param = 0;
+            observe(*(*self.cnx).path, &mut state, &mut param);
+
+            let debug = if ptr::eq(alg, picoquic_newreno_algorithm) {
+                connection::CongestionDebug::NewReno {
+                    state,
+                    ssthresh: param,
+                }
+            } else if ptr::eq(alg, picoquic_cubic_algorithm) {
+                connection::CongestionDebug::Cubic {
+                    state,
+                    w_max: param,
+                }
+            } else if ptr::eq(alg, picoquic_bbr_algorithm) {
+                connection::CongestionDebug::Bbr {
+                    mode: state,
+                    bandwidth: param,
+                }
+            } else {
+                let id = (*alg).congestion_algorithm_id;
+                connection::CongestionDebug::Other {
+                    algorithm: if id.is_null() {
+                        String::new()
+                    } else {
+                        CStr::from_ptr(id).to_string_lossy().into_owned()
+                    },
+                    state,
+                    param,
+                }
+            };
+
+            Some(debug)
+        }
+    }
+
+    /// Returns the maximum size of the packets that are sent on the default path.
+    pub fn path_mtu(self) -> usize {
+        unsafe { (**(*self.cnx).path).send_mtu as usize }
+    }
+
+    /// Returns the congestion window of the default path in bytes.
+    pub fn congestion_window(self) -> u64 {
+        unsafe { (**(*self.cnx).path).cwin }
+    }
+
+    /// Returns the number of packets that were sent on the default path and are neither
+    /// acknowledged nor declared as lost.
+    pub fn packets_in_flight(self) -> u64 {
+        let mut count = 0;
+
+        unsafe {
+            let path = *(*self.cnx).path;
+
+            for pkt_ctx in (*self.cnx).pkt_ctx.iter() {
+                let mut packet = pkt_ctx.retransmit_oldest;
+
+                while !packet.is_null() {
+                    if (*packet).send_path == path {
+                        count += 1;
+                    }
+
+                    packet = (*packet).previous_packet;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Is there stream data that was not sent yet or sent data that was not acknowledged yet?
+    pub fn has_unacked_data(self) -> bool {
+        unsafe { picoquic_is_cnx_backlog_empty(self.cnx) == 0 }
+    }
+
+    /// Returns the last point in time (in microseconds), where the connection made progress.
+    pub fn latest_progress_time(self) -> u64 {
+        unsafe { (*self.cnx).latest_progress_time }
+    }
+
+    /// Returns the number of bytes that can be sent before the amplification limit is reached.
+    /// The limit only applies to servers, as long as the address of the client is not validated.
+    pub fn amplification_budget(self) -> Option<u64> {
+        unsafe {
+            if self.con_type() == ConnectionType::Outgoing || (*self.cnx).initial_validated != 0 {
+                return None;
+            }
+
+            let path = &**(*self.cnx).path;
+            Some((path.received * 3).saturating_sub(path.bytes_sent))
+        }
+    }
+
+    /// Marks the in flight packet with the given packet number as lost.
+    /// The send time of the packet is reset, so the next retransmission check of picoquic
+    /// declares the packet as lost and retransmits its data.
+    #[cfg(feature = "loss-injection")]
+    pub fn mark_packet_lost(self, packet_number: u64) {
+        unsafe {
+            for pkt_ctx in (*self.cnx).pkt_ctx.iter_mut() {
+                let mut packet = pkt_ctx.retransmit_oldest;
+
+                while !packet.is_null() {
+                    if (*packet).sequence_number == packet_number {
+                        (*packet).send_time = 0;
+                    }
+
+                    packet = (*packet).previous_packet;
+                }
+            }
+        }
+    }
+
+    /// Requests that an acknowledgement for the received application packets is sent with the
+    /// next prepared packet, instead of waiting for the ack delay.
+    /// picoquic sends an acknowledgement without delay, once two packets were received since the
+    /// last sent acknowledgement. So, the highest acknowledged packet number is lowered to move
+    /// the ack deadline to now. The receive time of the largest received packet is kept, as the
+    /// ack delay that is reported to the peer is calculated from it.
+    pub fn send_ack_now(self) {
+        unsafe {
+            let pkt_ctx = &mut (*self.cnx).pkt_ctx
+                [picoquic_packet_context_enum_picoquic_packet_context_application as usize];
+            pkt_ctx.ack_needed = 1;
+            pkt_ctx.highest_ack_sent = pkt_ctx.first_sack_item.end_of_sack_range.saturating_sub(2);
+        }
+    }
+
+    /// Queues the given data for sending on the stream with the given id.
+    pub fn add_to_stream(self, id: stream::Id, data: &[u8]) {
+        unsafe {
+            picoquic_add_to_stream(self.cnx, id, data.as_ptr(), data.len(), 0);
+        }
+    }
+
+    /// Sets the `idle_timeout` transport parameter of this connection.
+    /// This needs to be done before the handshake starts.
+    fn set_idle_timeout(self, timeout: Duration) {
+        let millis = timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis());
+
+        unsafe {
+            (*self.cnx).local_parameters.idle_timeout = millis;
+        }
+    }
+
+    /// Returns the largest id of a `Stream` with the given type, that the peer allows us to open.
+    pub fn max_stream_id(self, stype: stream::Type) -> stream::Id {
+        unsafe {
+            match stype {
+                stream::Type::Bidirectional => (*self.cnx).max_stream_id_bidir_remote,
+                stream::Type::Unidirectional => (*self.cnx).max_stream_id_unidir_remote,
+            }
+        }
+    }
+
+    /// Returns the `max_udp_payload_size` transport parameter of the peer.
+    pub fn peer_max_udp_payload_size(self) -> usize {
+        unsafe { (*self.cnx).remote_parameters.max_packet_size as usize }
+    }
+
+    /// Returns the `max_ack_delay` transport parameter of the peer.
+    pub fn peer_max_ack_delay(self) -> Duration {
+        unsafe { Duration::from_micro_seconds((*self.cnx).remote_parameters.max_ack_delay) }
+    }
+
+    /// Returns the transport parameters of the peer, that are unknown to picoquic.
+    pub fn peer_custom_transport_parameters(self) -> Vec<(u64, Vec<u8>)> {
+        let data = unsafe {
+            let mut data = ptr::null();
+            let mut len = 0;
+            picoquic_get_remote_extra_transport_parameters(self.cnx, &mut data, &mut len);
+
+            if data.is_null() {
+                return Vec::new();
+            }
+
+            slice::from_raw_parts(data, len)
+        };
+
+        transport_parameters::parse(data).unwrap_or_else(|e| {
+            error!(
+                "could not parse the transport parameters of the peer of {}: {:?}",
+                self.log_name(),
+                e
+            );
+            Vec::new()
+        })
+    }
+
+    /// Returns the `ack_delay_exponent` transport parameter of the peer.
+    pub fn peer_ack_delay_exponent(self) -> u8 {
+        unsafe { (*self.cnx).remote_parameters.ack_delay_exponent }
+    }
+
+    /// Returns the extensions that were advertised in the transport parameters of the peer.
+    pub fn peer_extensions(self) -> connection::PeerExtensions {
+        let params = unsafe { &(*self.cnx).remote_parameters };
+
+        connection::PeerExtensions {
+            max_datagram_frame_size: if params.max_datagram_frame_size > 0 {
+                Some(params.max_datagram_frame_size as usize)
+            } else {
+                None
+            },
+            multipath: params.enable_multipath != 0,
+            grease_quic_bit: params.do_grease_quic_bit != 0,
+            migration_disabled: params.migration_disabled != 0,
+            min_ack_delay: if params.min_ack_delay > 0 {
+                Some(Duration::from_micro_seconds(params.min_ack_delay))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns the `idle_timeout` transport parameter of the peer.
+    pub fn peer_idle_timeout(self) -> Duration {
+        unsafe { Duration::from_millis((*self.cnx).remote_parameters.idle_timeout) }
+    }
+
+    /// Returns the `active_connection_id_limit` transport parameter of the peer.
+    pub fn peer_active_connection_id_limit(self) -> u32 {
+        unsafe { (*self.cnx).remote_parameters.active_connection_id_limit }
+    }
+
+    /// Returns if the TLS handshake resumed a prior session (PSK).
+    pub fn is_psk_handshake(self) -> bool {
+        unsafe { picoquic_tls_is_psk_handshake(self.cnx) != 0 }
+    }
+
+    /// Are the keys for sending or receiving 0-RTT data available?
+    pub fn is_0rtt_available(self) -> bool {
+        unsafe { picoquic_is_0rtt_available(self.cnx) != 0 }
+    }
+
+    /// Returns the id of the TLS cipher suite that was negotiated in the handshake.
+    pub fn cipher_suite(self) -> Option<u16> {
+        unsafe {
+            if (*self.cnx).tls_ctx.is_null() {
+                return None;
+            }
+
+            // The TLS context of picoquic starts with the pointer to the picotls object.
+            let tls = *((*self.cnx).tls_ctx as *mut *mut ptls_t);
+            if tls.is_null() {
+                return None;
+            }
+
+            let cipher = ptls_get_cipher(tls);
+            if cipher.is_null() {
+                None
+            } else {
+                Some((*cipher).id)
+            }
+        }
+    }
+
+    /// Was the connection disconnected, because the idle timeout expired?
+    pub fn is_idle_timeout(self) -> bool {
+        unsafe { picoquic_get_local_error(self.cnx) as u32 == PICOQUIC_ERROR_IDLE_TIMEOUT }
+    }
+
+    /// Returns the error codes and the reason phrase that were exchanged in the
+    /// `CONNECTION_CLOSE` frames.
+    pub fn close_info(self) -> connection::CloseInfo {
+        unsafe {
+            let remote_reason = picoquic_get_remote_close_reason(self.cnx);
+            let offending_frame_type = (*self.cnx).offending_frame_type;
+
+            connection::CloseInfo {
+                local_error: picoquic_get_local_error(self.cnx) as u64,
+                remote_error: picoquic_get_remote_error(self.cnx) as u64,
+                local_application_error: (*self.cnx).application_error,
+                remote_application_error: picoquic_get_application_error(self.cnx),
+                offending_frame_type: if offending_frame_type != 0 {
+                    Some(offending_frame_type)
+                } else {
+                    None
+                },
+                remote_reason: if remote_reason.is_null() {
+                    None
+                } else {
+                    Some(CStr::from_ptr(remote_reason).to_string_lossy().into_owned())
+                },
+            }
+        }
+    }
+
+    /// Did picoquic close the connection, because it detected an error (e.g. a protocol
+    /// violation of the peer)? In contrast to the remote error, the local error is set by this
+    /// side of the connection.
+    pub fn has_local_error(self) -> bool {
+        unsafe { picoquic_get_local_error(self.cnx) != 0 }
+    }
+
+    /// Pauses or resumes the flow control of the `Stream` with the given id.
+    /// While the flow control is paused, picoquic does not grant new credit with
+    /// `MAX_STREAM_DATA` frames, so the peer gets blocked after sending the data that was already
+    /// allowed.
+    pub fn set_stream_flow_control_paused(self, id: stream::Id, paused: bool) -> Result<(), Error> {
+        let ret = unsafe { picoquic_set_stream_flow_control_paused(self.cnx, id, paused as i32) };
+
+        if ret != 0 {
+            Err(ErrorKind::UnknownStream(id))?;
+        }
+
+        Ok(())
+    }
+
     /// Checks if the connection had an error.
     /// The returned closure, will always construct the same error.
     pub fn error(self) -> Option<Box<Fn() -> Error>> {
@@ -225,50 +1082,112 @@ impl Connection {
         } else {
             Some(Box::new(move || match error_code as u32 {
                 PICOQUIC_TLS_HANDSHAKE_FAILED => ErrorKind::TLSHandshakeError.into(),
-                _ => ErrorKind::Unknown.into(),
+                PICOQUIC_TRANSPORT_SERVER_BUSY => ErrorKind::ConnectionRefused.into(),
+                CRYPTO_ERROR_HANDSHAKE_FAILURE => ErrorKind::NoCommonTLSParameters.into(),
+                code => ErrorKind::TransportError(u64::from(code)).into(),
             }))
         }
     }
 }
 
-impl From<*mut picoquic_cnx_t> for Connection {
-    fn from(cnx: *mut picoquic_cnx_t) -> Connection {
-        Connection { cnx }
+/// The type of the `STREAM` frame without any flags.
+const STREAM_FRAME_TYPE: u64 = 0x08;
+
+/// The `CRYPTO_ERROR` of the `handshake_failure` TLS alert, that is sent if the peers do not
+/// support common TLS parameters.
+const CRYPTO_ERROR_HANDSHAKE_FAILURE: u32 = 0x100 + PTLS_ALERT_HANDSHAKE_FAILURE;
+
+impl<'ctx> Connection<'ctx> {
+    /// Creates a `Connection` from the given pointer, that is not tied to the borrow of a
+    /// `QuicCtx`.
+    ///
+    /// # Safety
+    /// The pointer needs to point to a connection of picoquic (or be null, if the `Connection` is
+    /// never used) and the `Connection` must not be used after the `QuicCtx` of the connection
+    /// was dropped. This holds for `Connection`s that are only used in the callbacks of picoquic.
+    /// A `Connection` that is stored needs to be converted into a `SharedConnection`.
+    pub unsafe fn from_raw(cnx: *mut picoquic_cnx_t) -> Connection<'ctx> {
+        Connection {
+            cnx,
+            _quic: PhantomData,
+        }
     }
 }
 
-pub struct ConnectionIter {
+/// A `Connection` that keeps its `QuicCtx` alive, so it can be stored outside of the borrow of
+/// the `QuicCtx`.
+/// The connection itself is still freed by picoquic, when it is closed. So, the connection must
+/// not be used after picoquic called the callback of the connection with a close event.
+#[derive(Clone)]
+pub struct SharedConnection {
+    cnx: *mut picoquic_cnx_t,
+    _quic: Rc<QuicCtx>,
+}
+
+impl SharedConnection {
+    /// Creates a `SharedConnection` for the given connection, that needs to be a connection of
+    /// the given `QuicCtx`.
+    pub fn new(quic: Rc<QuicCtx>, cnx: Connection) -> SharedConnection {
+        SharedConnection {
+            cnx: cnx.cnx,
+            _quic: quic,
+        }
+    }
+
+    /// Returns the `Connection`, tied to the borrow of this `SharedConnection`.
+    pub fn get(&self) -> Connection<'_> {
+        Connection {
+            cnx: self.cnx,
+            _quic: PhantomData,
+        }
+    }
+}
+
+pub struct ConnectionIter<'ctx> {
     iter: <Vec<*mut picoquic_cnx_t> as IntoIterator>::IntoIter,
+    _quic: PhantomData<&'ctx QuicCtx>,
 }
 
-impl ConnectionIter {
-    pub fn new(quic: *mut picoquic_quic_t) -> ConnectionIter {
+impl<'ctx> ConnectionIter<'ctx> {
+    pub fn new(quic: &'ctx QuicCtx) -> ConnectionIter<'ctx> {
+        unsafe { ConnectionIter::from_raw(quic.as_ptr()) }
+    }
+
+    /// Creates an iterator over the connections of the given pointer, that is not tied to the
+    /// borrow of a `QuicCtx`.
+    ///
+    /// # Safety
+    /// The pointer needs to point to a valid `picoquic_quic_t` and the `Connection`s must not be
+    /// used after it was freed, e.g. by only using them in a callback of picoquic.
+    pub unsafe fn from_raw(quic: *mut picoquic_quic_t) -> ConnectionIter<'ctx> {
         // We need to build a "stable" iterator.
         // Picoquic reorders the connections internally, while working with them and that can lead
         // to an infinite loop over the connections.
         // So, we build the list once and are safe to not loop infinitely.
         let mut vec = Vec::new();
-        unsafe {
-            let mut current = picoquic_get_first_cnx(quic);
+        let mut current = picoquic_get_first_cnx(quic);
 
-            while !current.is_null() {
-                vec.push(current);
+        while !current.is_null() {
+            vec.push(current);
 
-                current = picoquic_get_next_cnx(current);
-            }
+            current = picoquic_get_next_cnx(current);
         }
 
         ConnectionIter {
             iter: vec.into_iter(),
+            _quic: PhantomData,
         }
     }
 }
 
-impl Iterator for ConnectionIter {
-    type Item = Connection;
+impl<'ctx> Iterator for ConnectionIter<'ctx> {
+    type Item = Connection<'ctx>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(Connection::from)
+        self.iter.next().map(|cnx| Connection {
+            cnx,
+            _quic: PhantomData,
+        })
     }
 }
 
@@ -276,6 +1195,47 @@ impl Iterator for ConnectionIter {
 mod tests {
     use super::*;
 
+    #[test]
+    fn only_adopted_new_connections_keep_their_state() {
+        let (unknown, freed, adopted) = unsafe {
+            (
+                Connection::from_raw(0x10 as *mut picoquic_cnx_t),
+                Connection::from_raw(0x20 as *mut picoquic_cnx_t),
+                Connection::from_raw(0x30 as *mut picoquic_cnx_t),
+            )
+        };
+
+        // Connections that are not owned by this crate are ignored.
+        unknown.count_frames(&[0x01], true);
+        assert!(unknown.frame_stats().is_none());
+
+        collect_new_states();
+        freed.count_frames(&[0x06], true);
+        adopted.count_frames(&[0x06], true);
+        adopted.adopt_state();
+        discard_new_states();
+
+        assert!(freed.frame_stats().is_none());
+        assert_eq!(1, adopted.frame_stats().unwrap().received(0x06));
+
+        adopted.count_frames(&[0x06], false);
+        assert_eq!(1, adopted.frame_stats().unwrap().sent(0x06));
+    }
+
+    #[test]
+    fn connection_id_bytes_returns_all_bytes_of_the_id() {
+        let mut id = picoquic_connection_id_t {
+            id: [0; 20],
+            id_len: 12,
+        };
+        id.id[..12].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+        assert_eq!(
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            connection_id_bytes(id)
+        );
+    }
+
     #[test]
     fn client_bidirectional_stream_id_generation() {
         assert_eq!(
@@ -348,6 +1308,7 @@ mod tests {
             ([0, 0, 0, 0], 12345).into(),
             0,
             "server".into(),
+            None,
         );
     }
 }
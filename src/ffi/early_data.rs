@@ -0,0 +1,61 @@
+use accept_filter::IncomingInfo;
+use early_data::EarlyDataFilter;
+use ffi::QuicCtx;
+
+use picoquic_sys::picoquic::{ptls_context_t, ptls_set_context, ptls_t};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Decides per connection, if the early data of a client hello is accepted.
+pub struct Filter {
+    filter: Box<EarlyDataFilter>,
+    /// A copy of the TLS context of picoquic, that does not accept early data.
+    /// picotls reads the maximum size of early data from the TLS context of the connection,
+    /// after the client hello callback was called. So, a connection whose early data is rejected
+    /// is switched to this context, while all other connections keep the shared context.
+    reject_ctx: Box<ptls_context_t>,
+}
+
+impl Filter {
+    /// Creates the filter. The TLS context of picoquic is copied, so it needs to be completely
+    /// set up at this point.
+    pub fn new(quic: &QuicCtx, filter: Box<EarlyDataFilter>) -> Filter {
+        let mut reject_ctx = unsafe {
+            Box::new(ptr::read(
+                (*quic.as_ptr()).tls_master_ctx as *const ptls_context_t,
+            ))
+        };
+        reject_ctx.max_early_data_size = 0;
+
+        Filter { filter, reject_ctx }
+    }
+
+    /// Calls the filter for the connection of the given client hello and rejects the early data
+    /// of the connection, if the filter does not accept it or `info` is `None`.
+    pub unsafe fn check(&mut self, tls: *mut ptls_t, info: Option<&IncomingInfo>) {
+        let filter = &mut self.filter;
+        let accept = match info {
+            // A panic must not unwind into the c code.
+            Some(info) => panic::catch_unwind(AssertUnwindSafe(|| filter.accept_early_data(info)))
+                .unwrap_or_else(|_| {
+                    error!("early data filter panicked!");
+                    false
+                }),
+            None => false,
+        };
+
+        if !accept {
+            ptls_set_context(tls, &mut *self.reject_ctx);
+        }
+    }
+}
+
+/// Rejects all 0-RTT data, by not announcing any early data size in the session tickets and
+/// by rejecting the early data of all tickets.
+pub fn reject_early_data(quic: &QuicCtx) {
+    unsafe {
+        let tls_ctx = (*quic.as_ptr()).tls_master_ctx as *mut ptls_context_t;
+        (*tls_ctx).max_early_data_size = 0;
+    }
+}
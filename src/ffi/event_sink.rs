@@ -0,0 +1,281 @@
+use event_sink::{EventSink, PacketType, QuicEvent};
+use ffi::{self, MicroSeconds, QuicCtx};
+
+use picoquic_sys::picoquic::{
+    self, picoquic_cnx_t, picoquic_get_quic_ctx, picoquic_packet_header, picoquic_packet_type_enum,
+    picoquic_path_t, picoquic_skip_frame, picoquic_unified_logging_t,
+};
+
+use libc::{c_char, c_int};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::time::Duration;
+
+/// The consumers of the logging callbacks of a `picoquic_quic_t`.
+struct Handler {
+    sink: Option<Box<EventSink>>,
+    /// Should the frames of each connection be counted?
+    frame_stats: bool,
+}
+
+thread_local! {
+    /// Picoquic does not provide a context pointer to the logging callbacks, so we need to
+    /// store the handlers per `picoquic_quic_t`.
+    static HANDLERS: RefCell<HashMap<usize, Handler>> = RefCell::new(HashMap::new());
+}
+
+/// Sets up the logging callbacks in picoquic, that report the `QuicEvent`s to the given handler
+/// and count the frames of each connection, if `frame_stats` is set.
+/// The lost `Initial` and `Handshake` packets of each connection are always counted.
+///
+/// # Returns
+/// The logging functions that are registered in picoquic. They need to outlive the `QuicCtx`,
+/// as picoquic stores a pointer to them.
+pub fn setup_callback(
+    quic: &QuicCtx,
+    sink: Option<Box<EventSink>>,
+    frame_stats: bool,
+) -> Box<picoquic_unified_logging_t> {
+    HANDLERS.with(|h| {
+        h.borrow_mut()
+            .insert(quic.as_ptr() as usize, Handler { sink, frame_stats })
+    });
+
+    let mut fns: Box<picoquic_unified_logging_t> = Box::new(unsafe { mem::zeroed() });
+    fns.log_packet = Some(log_packet);
+    fns.log_packet_lost = Some(log_packet_lost);
+    fns.log_cc_dump = Some(log_cc_dump);
+
+    unsafe {
+        (*quic.as_ptr()).bin_log_fns = &mut *fns;
+    }
+
+    fns
+}
+
+/// Removes the handler of the given quic context, if one was set up.
+pub fn remove_callback(quic: &QuicCtx) {
+    HANDLERS.with(|h| h.borrow_mut().remove(&(quic.as_ptr() as usize)));
+}
+
+/// Returns if the quic context of the given connection has an event sink and counts frames.
+fn handler_flags(cnx: *mut picoquic_cnx_t) -> (bool, bool) {
+    let quic = unsafe { picoquic_get_quic_ctx(cnx) } as usize;
+
+    HANDLERS.with(|h| {
+        h.borrow()
+            .get(&quic)
+            .map(|h| (h.sink.is_some(), h.frame_stats))
+            .unwrap_or((false, false))
+    })
+}
+
+/// Reports the given event to the handler of the quic context of the given connection.
+fn emit(cnx: *mut picoquic_cnx_t, event: QuicEvent) {
+    let quic = unsafe { picoquic_get_quic_ctx(cnx) } as usize;
+
+    // A panic must not unwind into the c code.
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        HANDLERS.with(|h| {
+            if let Some(sink) = h.borrow_mut().get_mut(&quic).and_then(|h| h.sink.as_mut()) {
+                sink.event(event);
+            }
+        })
+    }));
+
+    if res.is_err() {
+        error!("event sink panicked!");
+    }
+}
+
+fn packet_type(ptype: picoquic_packet_type_enum) -> PacketType {
+    match ptype {
+        picoquic::picoquic_packet_type_enum_picoquic_packet_version_negotiation => {
+            PacketType::VersionNegotiation
+        }
+        picoquic::picoquic_packet_type_enum_picoquic_packet_initial => PacketType::Initial,
+        picoquic::picoquic_packet_type_enum_picoquic_packet_retry => PacketType::Retry,
+        picoquic::picoquic_packet_type_enum_picoquic_packet_handshake => PacketType::Handshake,
+        picoquic::picoquic_packet_type_enum_picoquic_packet_0rtt_protected => PacketType::ZeroRtt,
+        picoquic::picoquic_packet_type_enum_picoquic_packet_1rtt_protected => PacketType::OneRtt,
+        _ => PacketType::Unknown,
+    }
+}
+
+/// Decodes a QUIC variable length integer.
+///
+/// # Returns
+/// The decoded value and the number of bytes consumed.
+fn decode_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let first = *bytes.first()?;
+    let len = 1 << (first >> 6);
+
+    if bytes.len() < len {
+        return None;
+    }
+
+    let value = bytes[1..len]
+        .iter()
+        .fold(u64::from(first & 0x3f), |v, b| (v << 8) | u64::from(*b));
+
+    Some((value, len))
+}
+
+/// Returns the types of the frames in the given packet payload.
+fn frame_types(mut payload: &[u8]) -> Vec<u64> {
+    let mut frames = Vec::new();
+
+    while let Some((frame_type, _)) = decode_varint(payload) {
+        let mut consumed = 0;
+        let mut pure_ack = 0;
+
+        let res = unsafe {
+            picoquic_skip_frame(
+                payload.as_ptr(),
+                payload.len(),
+                &mut consumed,
+                &mut pure_ack,
+            )
+        };
+
+        frames.push(frame_type);
+
+        if res != 0 || consumed == 0 || consumed > payload.len() {
+            break;
+        }
+
+        payload = &payload[consumed..];
+    }
+
+    frames
+}
+
+unsafe extern "C" fn log_packet(
+    cnx: *mut picoquic_cnx_t,
+    _: *mut picoquic_path_t,
+    receiving: c_int,
+    _: u64,
+    ph: *mut picoquic_packet_header,
+    bytes: *const u8,
+    length: usize,
+) {
+    let (has_sink, count_frames) = handler_flags(cnx);
+
+    if !has_sink && !count_frames {
+        return;
+    }
+
+    let payload_end = ((*ph).offset + (*ph).payload_length).min(length);
+    let frames = if (*ph).offset < payload_end {
+        frame_types(&slice::from_raw_parts(bytes, length)[(*ph).offset..payload_end])
+    } else {
+        Vec::new()
+    };
+
+    if count_frames {
+        ffi::Connection::from_raw(cnx).count_frames(&frames, receiving != 0);
+    }
+
+    if !has_sink {
+        return;
+    }
+
+    let connection_id = ffi::Connection::from_raw(cnx).local_id();
+    let label = ffi::Connection::from_raw(cnx).label();
+    let packet_type = packet_type((*ph).ptype);
+    let packet_number = (*ph).pn64;
+
+    let event = if receiving != 0 {
+        QuicEvent::PacketReceived {
+            connection_id,
+            label,
+            packet_type,
+            packet_number,
+            size: length,
+            frames,
+        }
+    } else {
+        QuicEvent::PacketSent {
+            connection_id,
+            label,
+            packet_type,
+            packet_number,
+            size: length,
+            frames,
+        }
+    };
+
+    emit(cnx, event);
+}
+
+unsafe extern "C" fn log_packet_lost(
+    cnx: *mut picoquic_cnx_t,
+    _: *mut picoquic_path_t,
+    ptype: picoquic_packet_type_enum,
+    sequence_number: u64,
+    _: *const c_char,
+) {
+    if ptype == picoquic::picoquic_packet_type_enum_picoquic_packet_initial
+        || ptype == picoquic::picoquic_packet_type_enum_picoquic_packet_handshake
+    {
+        ffi::Connection::from_raw(cnx).record_handshake_packet_lost();
+    }
+
+    if !handler_flags(cnx).0 {
+        return;
+    }
+
+    emit(
+        cnx,
+        QuicEvent::PacketLost {
+            connection_id: ffi::Connection::from_raw(cnx).local_id(),
+            label: ffi::Connection::from_raw(cnx).label(),
+            packet_type: packet_type(ptype),
+            packet_number: sequence_number,
+        },
+    );
+}
+
+unsafe extern "C" fn log_cc_dump(cnx: *mut picoquic_cnx_t, _: u64) {
+    let path = *(*cnx).path;
+
+    emit(
+        cnx,
+        QuicEvent::RttUpdate {
+            connection_id: ffi::Connection::from_raw(cnx).local_id(),
+            label: ffi::Connection::from_raw(cnx).label(),
+            smoothed_rtt: Duration::from_micro_seconds((*path).smoothed_rtt),
+            rtt_variance: Duration::from_micro_seconds((*path).rtt_variant),
+            min_rtt: Duration::from_micro_seconds((*path).rtt_min),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_varint_of_all_lengths() {
+        assert_eq!(Some((37, 1)), decode_varint(&[0x25]));
+        assert_eq!(Some((15293, 2)), decode_varint(&[0x7b, 0xbd]));
+        assert_eq!(
+            Some((494_878_333, 4)),
+            decode_varint(&[0x9d, 0x7f, 0x3e, 0x7d])
+        );
+        assert_eq!(
+            Some((151_288_809_941_952_652, 8)),
+            decode_varint(&[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c])
+        );
+    }
+
+    #[test]
+    fn decode_varint_of_truncated_input() {
+        assert_eq!(None, decode_varint(&[]));
+        assert_eq!(None, decode_varint(&[0x7b]));
+    }
+}
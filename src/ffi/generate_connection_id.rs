@@ -0,0 +1,80 @@
+use generate_connection_id::GenerateConnectionId;
+
+use picoquic_sys::picoquic::{picoquic_cnx_by_id, picoquic_connection_id_t, picoquic_quic_t};
+
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use openssl::rand::rand_bytes;
+
+/// The maximum number of times we generate a new connection id, if the generated one collides.
+/// This needs to match the number of attempts that is documented at `GenerateConnectionId`.
+const MAX_ATTEMPTS: usize = 8;
+
+/// The context that is given as `cnx_id_cb_data` to `generate_connection_id_callback`.
+pub struct Context {
+    handler: Box<GenerateConnectionId>,
+    quic: *mut picoquic_quic_t,
+}
+
+impl Context {
+    pub fn new(handler: Box<GenerateConnectionId>) -> Box<Context> {
+        Box::new(Context {
+            handler,
+            quic: ptr::null_mut(),
+        })
+    }
+
+    /// Sets the quic context, this is required to check for collisions.
+    pub fn set_quic(&mut self, quic: *mut picoquic_quic_t) {
+        self.quic = quic;
+    }
+
+    pub fn as_ptr(&mut self) -> *mut c_void {
+        self as *mut Context as *mut c_void
+    }
+}
+
+/// Will be called by picoquic to generate a new local connection id
+pub unsafe extern "C" fn generate_connection_id_callback(
+    cnx_id_local: picoquic_connection_id_t,
+    _: picoquic_connection_id_t,
+    ctx: *mut c_void,
+    cnx_id_returned: *mut picoquic_connection_id_t,
+) {
+    assert!(!ctx.is_null());
+    let ctx = &mut *(ctx as *mut Context);
+
+    let mut id = cnx_id_local;
+    let len = id.id_len as usize;
+
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 && rand_bytes(&mut id.id[..len]).is_err() {
+            break;
+        }
+
+        // A panic must not unwind into the c code.
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            ctx.handler.generate(&mut id.id[..len]);
+        }));
+
+        if res.is_err() {
+            error!("connection id generator panicked, falling back to a random connection id!");
+            *cnx_id_returned = cnx_id_local;
+            return;
+        }
+
+        if picoquic_cnx_by_id(ctx.quic, id).is_null() {
+            *cnx_id_returned = id;
+            return;
+        }
+    }
+
+    error!(
+        "could not generate a connection id without collision in {} attempts, falling back to \
+         the random connection id of picoquic that does not carry routing information!",
+        MAX_ATTEMPTS
+    );
+    *cnx_id_returned = cnx_id_local;
+}
@@ -0,0 +1,107 @@
+use error::*;
+use ffi::QuicCtx;
+use keylog::Keylog;
+
+use picoquic_sys::picoquic::{
+    ptls_context_t, ptls_get_client_random, ptls_iovec_t, ptls_log_secret_t, ptls_t,
+};
+
+use std::ffi::CStr;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::slice;
+
+/// The context of the secret logging callback.
+/// picotls calls the callback with a pointer to `log_secret`, so it needs to be the first field.
+#[repr(C)]
+pub struct Context {
+    log_secret: ptls_log_secret_t,
+    handler: Box<Keylog>,
+}
+
+/// Sets up the secret logging callback in picotls.
+///
+/// # Returns
+/// The context of the callback. It needs to outlive the `QuicCtx`, as picotls stores a pointer
+/// to it.
+pub fn setup_callback(quic: &QuicCtx, handler: Box<Keylog>) -> Box<Context> {
+    let mut ctx = Box::new(Context {
+        log_secret: ptls_log_secret_t {
+            cb: Some(log_secret_callback),
+        },
+        handler,
+    });
+
+    unsafe {
+        let tls_ctx = (*quic.as_ptr()).tls_master_ctx as *mut ptls_context_t;
+        (*tls_ctx).log_secret = &mut ctx.log_secret;
+    }
+
+    ctx
+}
+
+/// Creates a handler that appends the lines to the file at the given path.
+/// The file is created, if it does not exist.
+pub fn file_handler(path: &Path) -> Result<Box<Keylog>, Error> {
+    let mut file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(e) => bail!("Error at opening the keylog file: {}", e),
+    };
+
+    Ok(Box::new(move |line: &str| {
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("could not write to the keylog file: {}", e);
+        }
+    }))
+}
+
+/// Formats a secret as line in the NSS key log format.
+fn format_line(label: &str, client_random: &[u8], secret: &[u8]) -> String {
+    format!("{} {} {}", label, to_hex(client_random), to_hex(secret))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Will be called by picotls for each derived secret.
+unsafe extern "C" fn log_secret_callback(
+    ctx: *mut ptls_log_secret_t,
+    tls: *mut ptls_t,
+    label: *const c_char,
+    secret: ptls_iovec_t,
+) {
+    let ctx = &mut *(ctx as *mut Context);
+    let client_random = ptls_get_client_random(tls);
+
+    let line = format_line(
+        &CStr::from_ptr(label).to_string_lossy(),
+        slice::from_raw_parts(client_random.base, client_random.len),
+        slice::from_raw_parts(secret.base, secret.len),
+    );
+
+    // A panic must not unwind into the c code.
+    if panic::catch_unwind(AssertUnwindSafe(|| ctx.handler.log(&line))).is_err() {
+        error!("keylog handler panicked!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_formatted_in_nss_key_log_format() {
+        assert_eq!(
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET 00ff10 abcdef",
+            format_line(
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                &[0x00, 0xff, 0x10],
+                &[0xab, 0xcd, 0xef]
+            )
+        );
+    }
+}
@@ -1,8 +1,17 @@
+mod accept_filter;
 mod connection;
+mod early_data;
+mod event_sink;
+mod generate_connection_id;
+mod keylog;
 mod quic_ctx;
+mod random;
+mod select_alpn;
 mod stateless_packet;
 mod verify_certificate;
 
 pub use self::connection::Connection;
+pub use self::connection::SharedConnection;
 pub use self::quic_ctx::MicroSeconds;
 pub use self::quic_ctx::QuicCtx;
+pub use self::quic_ctx::Routed;
@@ -1,17 +1,33 @@
-use super::connection::ConnectionIter;
+use super::connection::{collect_new_states, discard_new_states, Connection, ConnectionIter};
 use super::stateless_packet::StatelessPacketIter;
-use config::{Config, FileFormat};
+use config::{Config, FileFormat, RateLimitPolicy};
+use connection::{CipherSuite, KeyExchangeGroup};
+use early_data::EarlyDataPolicy;
 use error::*;
-use ffi::verify_certificate;
+use event_sink::PacketType;
+use ffi::generate_connection_id::{self, generate_connection_id_callback};
+use ffi::{accept_filter, early_data, event_sink, keylog, random, select_alpn, verify_certificate};
+use packet::Packet;
+use random::is_random_source_set;
+use transport_parameters;
 
 use picoquic_sys::picoquic::{
-    self, picoquic_create, picoquic_current_time, picoquic_free, picoquic_get_next_wake_delay,
-    picoquic_incoming_packet, picoquic_quic_t, picoquic_set_client_authentication,
-    picoquic_set_tls_certificate_chain, picoquic_set_tls_key, picoquic_set_tls_root_certificates,
-    picoquic_stream_data_cb_fn, ptls_iovec_t,
+    self, picoquic_cnx_by_net, picoquic_create, picoquic_current_time, picoquic_free,
+    picoquic_free_tickets, picoquic_get_first_cnx, picoquic_get_next_wake_delay,
+    picoquic_incoming_packet, picoquic_quic_t, picoquic_set_callback,
+    picoquic_set_client_authentication, picoquic_set_cookie_mode,
+    picoquic_set_default_connection_id_length, picoquic_set_extra_transport_parameters,
+    picoquic_set_initial_send_mtu, picoquic_set_max_ack_ranges, picoquic_set_max_data_control,
+    picoquic_set_padding_policy, picoquic_set_tls_certificate_chain, picoquic_set_tls_key,
+    picoquic_set_tls_root_certificates, picoquic_stored_ticket_t, picoquic_stream_data_cb_fn,
+    picoquic_unified_logging_t, ptls_cipher_suite_t, ptls_context_t, ptls_iovec_t,
+    ptls_key_exchange_algorithm_t, PICOQUIC_CONNECTION_ID_MAX_SIZE, PICOQUIC_ENFORCED_INITIAL_MTU,
+    PICOQUIC_MAX_PACKET_SIZE,
 };
 
 use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
     ffi::CString,
     mem,
     net::SocketAddr,
@@ -41,6 +57,10 @@ fn create_cstring(path: Option<PathBuf>) -> Result<Option<CString>, Error> {
     }
 }
 
+/// How long a client that was asked to retry by the accept filter, needs to come back with a
+/// valid token.
+const RETRY_ADDR_LIFETIME: Duration = Duration::from_secs(10);
+
 fn c_str_or_null(string: &Option<CString>) -> *const c_char {
     string
         .as_ref()
@@ -48,9 +68,106 @@ fn c_str_or_null(string: &Option<CString>) -> *const c_char {
         .unwrap_or_else(ptr::null)
 }
 
+/// A datagram that was prepared by `QuicCtx::prepare_outgoing_batch`.
+pub struct OutgoingDatagram {
+    /// The offset of the datagram in the batch buffer.
+    pub offset: usize,
+    /// The length of the datagram.
+    pub len: usize,
+    /// The local address the datagram should be sent from. If the address is not known, `None`
+    /// is used.
+    pub local_addr: Option<SocketAddr>,
+    /// The address of the peer the datagram should be sent to.
+    pub to: SocketAddr,
+}
+
+/// The result of processing an incoming packet with `QuicCtx::incoming_data`.
+pub enum Routed<'ctx> {
+    /// The packet created the given new incoming connection.
+    New(Connection<'ctx>),
+    /// The packet was routed to an existing connection or answered statelessly (e.g. with a
+    /// version negotiation).
+    /// The connection is not looked up, as picoquic does not return it and searching it for each
+    /// packet is too expensive.
+    Existing,
+    /// The packet was dropped.
+    Dropped,
+}
+
+/// A token bucket that limits the rate of new incoming connections.
+struct RateLimit {
+    per_sec: u32,
+    policy: RateLimitPolicy,
+    /// The available tokens in micro tokens, a new connection takes `1_000_000` micro tokens.
+    tokens: Cell<u64>,
+    /// The time the tokens were refilled the last time.
+    last_refill: Cell<u64>,
+}
+
+impl RateLimit {
+    fn new(per_sec: u32, policy: RateLimitPolicy) -> RateLimit {
+        RateLimit {
+            per_sec,
+            policy,
+            tokens: Cell::new(u64::from(per_sec) * 1_000_000),
+            last_refill: Cell::new(0),
+        }
+    }
+
+    /// Takes the token of a new connection at the given time, in micro seconds.
+    ///
+    /// # Returns
+    /// If a token was available.
+    fn take(&self, current_time: u64) -> bool {
+        let max_tokens = u64::from(self.per_sec) * 1_000_000;
+        let elapsed = current_time.saturating_sub(self.last_refill.get());
+        let tokens = elapsed
+            .saturating_mul(u64::from(self.per_sec))
+            .saturating_add(self.tokens.get())
+            .min(max_tokens);
+        self.last_refill.set(current_time);
+
+        if tokens >= 1_000_000 {
+            self.tokens.set(tokens - 1_000_000);
+            true
+        } else {
+            self.tokens.set(tokens);
+            false
+        }
+    }
+}
+
 pub struct QuicCtx {
     quic: *mut picoquic_quic_t,
     max_delay: Duration,
+    alpn: Option<CString>,
+    /// Must outlive `quic`, as picoquic stores a pointer to it.
+    _cid_generator: Option<Box<generate_connection_id::Context>>,
+    /// Must outlive `quic`, as picoquic stores a pointer to it.
+    _ticket_store_filename: Option<CString>,
+    /// The initial round trip time of outgoing connections.
+    initial_rtt: Option<Duration>,
+    /// Must outlive `quic`, as picoquic stores a pointer to it.
+    _event_sink_fns: Option<Box<picoquic_unified_logging_t>>,
+    /// Must outlive `quic`, as picotls stores a pointer to it.
+    _keylog: Option<Box<keylog::Context>>,
+    /// The context of the client hello callback, that calls the accept filter and the early data
+    /// filter.
+    /// Must outlive `quic`, as picotls stores a pointer to it.
+    accept_filter: Option<Box<accept_filter::Context>>,
+    /// The addresses of the clients that were asked to retry by the accept filter, with the
+    /// point in time (in microseconds) of the retry.
+    retry_addrs: RefCell<HashMap<SocketAddr, u64>>,
+    /// The maximum number of session tickets in the ticket store.
+    ticket_store_capacity: Option<usize>,
+    /// Must outlive `quic`, as picotls stores a pointer to it.
+    _cipher_suites: Option<Vec<*mut ptls_cipher_suite_t>>,
+    /// Must outlive `quic`, as picotls stores a pointer to it.
+    _key_exchanges: Option<Vec<*mut ptls_key_exchange_algorithm_t>>,
+    /// The current round of the weighted send scheduler.
+    send_round: Cell<u64>,
+    /// The rate limit of new incoming connections.
+    rate_limit: Option<RateLimit>,
 }
 
 impl QuicCtx {
@@ -66,6 +183,20 @@ impl QuicCtx {
         let cert_filename = create_cstring(config.certificate_chain_filename)?;
         let key_filename = create_cstring(config.private_key_filename)?;
         let root_cert_filename = create_cstring(config.root_certificate_filename)?;
+        let ticket_store_filename = create_cstring(config.ticket_store_filename)?;
+        let alpn = match config.alpn.take() {
+            Some(alpn) => Some(CString::new(alpn)?),
+            None => None,
+        };
+
+        let mut cid_generator = config
+            .connection_id_generator
+            .take()
+            .map(generate_connection_id::Context::new);
+        let (cid_callback, cid_callback_ctx) = match cid_generator {
+            Some(ref mut ctx) => (Some(generate_connection_id_callback as _), ctx.as_ptr()),
+            None => (None, ptr::null_mut()),
+        };
 
         let reset_seed = config
             .reset_seed
@@ -73,30 +204,53 @@ impl QuicCtx {
             .map(|v| v.as_mut_ptr())
             .unwrap_or_else(ptr::null_mut);
 
+        let (ticket_key, ticket_key_len) = config
+            .ticket_encryption_key
+            .as_ref()
+            .map(|k| (k.as_ptr(), k.len()))
+            .unwrap_or((ptr::null(), 0));
+
         let quic = unsafe {
             picoquic_create(
                 connection_buckets,
                 c_str_or_null(&cert_filename),
                 c_str_or_null(&key_filename),
                 c_str_or_null(&root_cert_filename),
-                ptr::null(),
+                c_str_or_null(&alpn),
                 default_callback,
                 default_ctx,
-                None,
-                ptr::null_mut(),
+                cid_callback,
+                cid_callback_ctx,
                 reset_seed,
                 picoquic_current_time(),
                 ptr::null_mut(),
-                ptr::null(),
-                ptr::null(),
-                0,
+                c_str_or_null(&ticket_store_filename),
+                ticket_key,
+                ticket_key_len,
             )
         };
         assert!(!quic.is_null());
 
+        if let Some(ref mut ctx) = cid_generator {
+            ctx.set_quic(quic);
+        }
+
         let mut quic = QuicCtx {
             quic,
             max_delay: Duration::from_secs(10),
+            alpn,
+            _cid_generator: cid_generator,
+            _ticket_store_filename: ticket_store_filename,
+            initial_rtt: config.initial_rtt,
+            _event_sink_fns: None,
+            _keylog: None,
+            accept_filter: None,
+            retry_addrs: RefCell::new(HashMap::new()),
+            ticket_store_capacity: None,
+            _cipher_suites: None,
+            _key_exchanges: None,
+            send_round: Cell::new(0),
+            rate_limit: None,
         };
 
         if config.client_authentication {
@@ -105,6 +259,64 @@ impl QuicCtx {
             }
         }
 
+        if is_random_source_set() {
+            random::setup_callback(&quic);
+        }
+
+        if let Some(len) = config.local_connection_id_length {
+            quic.set_local_cid_length(len)?;
+        }
+
+        quic.set_disable_migration(config.disable_migration);
+
+        if let Some(tickets) = config.ticket_store_capacity {
+            quic.set_ticket_store_capacity(tickets)?;
+        }
+
+        quic.set_grease_quic_bit(config.grease_quic_bit);
+
+        if let Some(limit) = config.active_connection_id_limit {
+            quic.set_active_connection_id_limit(limit)?;
+        }
+
+        quic.set_ack_delays(config.max_ack_delay, config.min_ack_delay)?;
+
+        if let Some(exponent) = config.ack_delay_exponent {
+            quic.set_ack_delay_exponent(exponent)?;
+        }
+
+        if let Some(ranges) = config.max_ack_ranges {
+            quic.set_max_ack_ranges(ranges)?;
+        }
+
+        if let Some((min_size, multiple)) = config.padding_policy {
+            quic.set_padding_policy(min_size, multiple)?;
+        }
+
+        if let Some(size) = config.max_handshake_packet_size {
+            quic.set_max_handshake_packet_size(size)?;
+        }
+
+        if let Some((per_sec, policy)) = config.new_connection_rate_limit {
+            quic.set_new_connection_rate_limit(per_sec, policy)?;
+        }
+
+        if let Some((bytes, _)) = config.max_recv_buffer {
+            quic.set_max_recv_buffer(bytes)?;
+        }
+
+        if let Some(ref suites) = config.cipher_suites {
+            quic.set_cipher_suites(suites)?;
+        }
+
+        if let Some(ref groups) = config.key_exchange_groups {
+            quic.set_key_exchange_groups(groups)?;
+        }
+
+        if !config.custom_transport_parameters.is_empty() {
+            quic.set_custom_transport_parameters(&config.custom_transport_parameters)?;
+        }
+
         if let Some((format, chain)) = config.certificate_chain {
             quic.set_tls_certificate_chain(chain, format)?;
         }
@@ -121,6 +333,44 @@ impl QuicCtx {
             verify_certificate::setup_callback(&quic, handler)?;
         }
 
+        if let Some(handler) = config.alpn_select_handler.take() {
+            select_alpn::setup_callback(&quic, handler);
+        }
+
+        quic._event_sink_fns = Some(event_sink::setup_callback(
+            &quic,
+            config.event_sink.take(),
+            config.frame_stats,
+        ));
+
+        let keylog_handler = match (config.keylog_handler.take(), config.keylog_filename) {
+            (Some(handler), _) => Some(handler),
+            (None, Some(path)) => Some(keylog::file_handler(&path)?),
+            (None, None) => None,
+        };
+
+        if let Some(handler) = keylog_handler {
+            quic._keylog = Some(keylog::setup_callback(&quic, handler));
+        }
+
+        let early_data_filter = match config.early_data_policy.take() {
+            Some(EarlyDataPolicy::Reject) => {
+                early_data::reject_early_data(&quic);
+                None
+            }
+            Some(EarlyDataPolicy::Callback(filter)) => Some(filter),
+            Some(EarlyDataPolicy::Accept) | None => None,
+        };
+
+        let accept_filter = config.accept_filter.take();
+        if accept_filter.is_some() || early_data_filter.is_some() {
+            quic.accept_filter = Some(accept_filter::setup_callback(
+                &quic,
+                accept_filter,
+                early_data_filter,
+            ));
+        }
+
         Ok(quic)
     }
 
@@ -132,6 +382,19 @@ impl QuicCtx {
         QuicCtx {
             quic: ptr::null_mut(),
             max_delay: Duration::from_secs(10),
+            alpn: None,
+            _cid_generator: None,
+            _ticket_store_filename: None,
+            initial_rtt: None,
+            _event_sink_fns: None,
+            _keylog: None,
+            accept_filter: None,
+            retry_addrs: RefCell::new(HashMap::new()),
+            ticket_store_capacity: None,
+            _cipher_suites: None,
+            _key_exchanges: None,
+            send_round: Cell::new(0),
+            rate_limit: None,
         }
     }
 
@@ -139,22 +402,254 @@ impl QuicCtx {
         self.quic
     }
 
-    pub fn connection_iter(&self) -> ConnectionIter {
-        ConnectionIter::new(self.quic)
+    /// Returns the configured initial round trip time of outgoing connections.
+    pub fn initial_rtt(&self) -> Option<Duration> {
+        self.initial_rtt
+    }
+
+    /// Returns the configured ALPN protocol or `NULL`.
+    pub fn alpn(&self) -> *const c_char {
+        c_str_or_null(&self.alpn)
     }
 
+    pub fn connection_iter(&self) -> ConnectionIter<'_> {
+        ConnectionIter::new(self)
+    }
+
+    /// Prepares the outgoing packets of all connections in one batch.
+    /// The connections are walked in weighted rounds, so each connection gets as many datagrams
+    /// per round as its weight, until no connection has data left or the batch is full. A round
+    /// may span multiple batches, so a connection that used up its share waits until all other
+    /// connections had their turn. Each datagram occupies a slot of `PICOQUIC_MAX_PACKET_SIZE`
+    /// bytes in the given buffer.
+    /// Disconnected connections are deleted and end the batch, as picoquic reorders its
+    /// connections on deletion.
+    ///
+    /// # Returns
+    /// The prepared datagrams, in the order they should be sent.
+    pub fn prepare_outgoing_batch(
+        &self,
+        buffer: &mut [u8],
+        max_datagrams: usize,
+        current_time: u64,
+    ) -> Vec<OutgoingDatagram> {
+        let slot_size = PICOQUIC_MAX_PACKET_SIZE as usize;
+        let max_datagrams = max_datagrams.min(buffer.len() / slot_size);
+        let mut datagrams = Vec::new();
+        // Did the current pass start with a new round?
+        let mut new_round = false;
+
+        loop {
+            let prepared = datagrams.len();
+            let round = self.send_round.get();
+
+            for con in self.connection_iter() {
+                if datagrams.len() >= max_datagrams {
+                    return datagrams;
+                }
+
+                if con.is_disconnected() {
+                    con.delete();
+                    return datagrams;
+                }
+
+                let mut credit = con.send_credit(round);
+
+                while credit > 0 && datagrams.len() < max_datagrams {
+                    let offset = datagrams.len() * slot_size;
+                    match con.prepare_packet(&mut buffer[offset..offset + slot_size], current_time)
+                    {
+                        Ok(Some((len, from, to))) => {
+                            datagrams.push(OutgoingDatagram {
+                                offset,
+                                len,
+                                local_addr: from.or_else(|| con.local_addr()),
+                                to,
+                            });
+                            credit -= 1;
+                        }
+                        Ok(None) => credit = 0,
+                        Err(e) => {
+                            error!("error while sending connections packets: {:?}", e);
+                            credit = 0;
+                        }
+                    }
+                }
+
+                con.set_send_credit(credit);
+            }
+
+            // All connections had their turn, so the next round starts.
+            self.send_round.set(round.wrapping_add(1));
+
+            if datagrams.len() == prepared && new_round {
+                return datagrams;
+            }
+
+            new_round = true;
+        }
+    }
+
+    /// Processes an incoming packet.
+    ///
+    /// ecn - The ECN codepoint of the packet, if it is known. It is counted for the connection
+    ///       of the peer address.
+    ///
+    /// # Returns
+    /// If the packet created a new connection, was routed to an existing connection or was
+    /// dropped.
     pub fn incoming_data(
-        &mut self,
+        &self,
         buf: &mut [u8],
         addr_to: SocketAddr,
         addr_from: SocketAddr,
+        ecn: Option<u8>,
         current_time: u64,
-    ) {
+    ) -> Routed<'_> {
+        let peer_addr = addr_from;
         let addr_to = SockAddr::from(addr_to);
         let addr_from = SockAddr::from(addr_from);
 
-        let ret = unsafe {
-            picoquic_incoming_packet(
+        // Only the `Initial` packets of new connections are limited or checked for a retry.
+        let is_new_initial = (self.rate_limit.is_some() || self.has_accept_filter())
+            && Packet::parse_header(buf)
+                .map(|h| h.packet_type == PacketType::Initial)
+                .unwrap_or(false)
+            && unsafe {
+                picoquic_cnx_by_net(self.quic, addr_from.as_ptr() as *const picoquic::sockaddr)
+            }
+            .is_null();
+
+        // A client that was asked to retry by the accept filter, needs to come back with a
+        // valid token.
+        let mut force_retry = is_new_initial && self.retry_addrs.borrow().contains_key(&peer_addr);
+
+        if let Some(ref limit) = self.rate_limit {
+            if is_new_initial && !limit.take(current_time) {
+                match limit.policy {
+                    RateLimitPolicy::Drop => {
+                        debug!("new connection rate limit exceeded, dropping `Initial` packet");
+                        return Routed::Dropped;
+                    }
+                    RateLimitPolicy::Retry => force_retry = true,
+                }
+            }
+        }
+
+        // picoquic decrypts the packet in place, so a copy is kept to answer it with a retry, if
+        // the accept filter asks for it.
+        let mut retry_packet = if is_new_initial && !force_retry && self.has_accept_filter() {
+            Some(buf.to_vec())
+        } else {
+            None
+        };
+
+        // Picoquic inserts new connections at the front of its list of connections.
+        let first_cnx = unsafe { picoquic_get_first_cnx(self.quic) };
+
+        let ret = self.incoming_packet(buf, &addr_to, &addr_from, current_time, force_retry);
+
+        let retry_requested = self
+            .accept_filter
+            .as_ref()
+            .map(|f| f.take_retry_request())
+            .unwrap_or(false);
+
+        let new_first_cnx = unsafe { picoquic_get_first_cnx(self.quic) };
+        let new_cnx = if new_first_cnx != first_cnx && !new_first_cnx.is_null() {
+            Some(new_first_cnx)
+        } else {
+            None
+        };
+
+        if retry_requested {
+            // The connection was not seen by the application yet, so it is deleted without
+            // calling its callback and without sending the handshake error to the client.
+            if let Some(cnx) = new_cnx {
+                unsafe {
+                    picoquic_set_callback(cnx, None, ptr::null_mut());
+                    Connection::from_raw(cnx).delete();
+                }
+            }
+
+            if let Some(ref mut packet) = retry_packet {
+                self.incoming_packet(packet, &addr_to, &addr_from, current_time, true);
+
+                let lifetime = RETRY_ADDR_LIFETIME.as_micro_seconds();
+                let mut retry_addrs = self.retry_addrs.borrow_mut();
+                retry_addrs.retain(|_, time| current_time.saturating_sub(*time) < lifetime);
+                retry_addrs.insert(peer_addr, current_time);
+            }
+
+            return Routed::Dropped;
+        }
+
+        if ret != 0 {
+            error!("`picoquic_incoming_packet` returned: {}", ret);
+            return Routed::Dropped;
+        }
+
+        // picoquic does not return the connection of the packet, so it is only looked up, if the
+        // ECN codepoint needs to be counted.
+        if let Some(ecn) = ecn {
+            let cnx = unsafe {
+                picoquic_cnx_by_net(self.quic, addr_from.as_ptr() as *const picoquic::sockaddr)
+            };
+
+            if !cnx.is_null() {
+                unsafe { Connection::from_raw(cnx).record_received_ecn(ecn) };
+            }
+        }
+
+        match new_cnx {
+            Some(cnx) => {
+                self.retry_addrs.borrow_mut().remove(&peer_addr);
+                Routed::New(unsafe { Connection::from_raw(cnx) })
+            }
+            None => Routed::Existing,
+        }
+    }
+
+    /// Is an accept filter set, that can ask clients to retry?
+    fn has_accept_filter(&self) -> bool {
+        self.accept_filter
+            .as_ref()
+            .map(|f| f.has_filter())
+            .unwrap_or(false)
+    }
+
+    /// Hands the given packet to picoquic.
+    ///
+    /// If `force_retry` is set, picoquic is switched to the cookie mode while it processes the
+    /// packet. So, an `Initial` packet without a valid token is answered with a stateless retry
+    /// and a connection is only created for a client that validated its address. picoquic only
+    /// checks the cookie mode while it processes a packet, so the previous mode is restored
+    /// afterwards and no other packet is affected.
+    fn incoming_packet(
+        &self,
+        buf: &mut [u8],
+        addr_to: &SockAddr,
+        addr_from: &SockAddr,
+        current_time: u64,
+        force_retry: bool,
+    ) -> i32 {
+        let peer_addr = addr_from.as_std();
+
+        if let Some(addr) = peer_addr {
+            accept_filter::set_address_validated(addr, force_retry);
+        }
+
+        collect_new_states();
+
+        unsafe {
+            // picoquic stores the two bits of the cookie mode as separate flags.
+            let previous_mode = (*self.quic).check_token | ((*self.quic).provide_token << 1);
+
+            if force_retry {
+                picoquic_set_cookie_mode(self.quic, previous_mode | 1);
+            }
+
+            let ret = picoquic_incoming_packet(
                 self.quic,
                 buf.as_mut_ptr(),
                 buf.len() as u32,
@@ -163,11 +658,19 @@ impl QuicCtx {
                 // as long as we only support one udp socket, we don't need to change this index
                 0,
                 current_time,
-            )
-        };
+            );
 
-        if ret != 0 {
-            error!("`picoquic_incoming_packet` returned: {}", ret);
+            if force_retry {
+                picoquic_set_cookie_mode(self.quic, previous_mode);
+            }
+
+            discard_new_states();
+
+            if let Some(addr) = peer_addr {
+                accept_filter::set_address_validated(addr, false);
+            }
+
+            ret
         }
     }
 
@@ -199,6 +702,232 @@ impl QuicCtx {
         unsafe { picoquic_current_time() }
     }
 
+    /// Sets the length of the connection ids that are generated for local connections.
+    fn set_local_cid_length(&mut self, len: u8) -> Result<(), Error> {
+        if len == 0 || u32::from(len) > PICOQUIC_CONNECTION_ID_MAX_SIZE {
+            bail!(
+                "Connection id length must be between 1 and {}",
+                PICOQUIC_CONNECTION_ID_MAX_SIZE
+            )
+        }
+
+        let res = unsafe { picoquic_set_default_connection_id_length(self.as_ptr(), len) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::Unknown.into())
+        }
+    }
+
+    /// Sets the `disable_migration` transport parameter that is send to the peers.
+    fn set_disable_migration(&mut self, disable: bool) {
+        unsafe {
+            (*self.quic).default_tp.migration_disabled = disable as u32;
+        }
+    }
+
+    /// Sets the `active_connection_id_limit` transport parameter that is send to the peers.
+    fn set_active_connection_id_limit(&mut self, limit: u32) -> Result<(), Error> {
+        if limit < 2 {
+            bail!("Active connection id limit must be at least 2");
+        }
+
+        unsafe {
+            (*self.quic).default_tp.active_connection_id_limit = limit;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the `max_ack_delay` and `min_ack_delay` transport parameters that are send to the
+    /// peers. Sending `min_ack_delay` enables the ack frequency extension.
+    fn set_ack_delays(
+        &mut self,
+        max_ack_delay: Option<Duration>,
+        min_ack_delay: Option<Duration>,
+    ) -> Result<(), Error> {
+        if let (Some(max), Some(min)) = (max_ack_delay, min_ack_delay) {
+            if min > max {
+                bail!("Minimum ack delay must not be greater than the maximum ack delay");
+            }
+        }
+
+        if let Some(max) = max_ack_delay {
+            // The transport parameter is limited to 2^14 milliseconds.
+            if max >= Duration::from_millis(1 << 14) {
+                bail!("Maximum ack delay must be less than 2^14 milliseconds");
+            }
+
+            unsafe {
+                (*self.quic).default_tp.max_ack_delay = max.as_micro_seconds();
+            }
+        }
+
+        if let Some(min) = min_ack_delay {
+            unsafe {
+                (*self.quic).default_tp.min_ack_delay = min.as_micro_seconds();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the `ack_delay_exponent` transport parameter that is send to the peers and that is
+    /// used to encode the ack delay of the sent acknowledgements.
+    fn set_ack_delay_exponent(&mut self, exponent: u8) -> Result<(), Error> {
+        // Values above 20 are invalid, see RFC 9000, section 18.2.
+        if exponent > 20 {
+            bail!("Ack delay exponent must not be greater than 20");
+        }
+
+        unsafe {
+            (*self.quic).default_tp.ack_delay_exponent = exponent;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of ack ranges in the sent acknowledgements.
+    fn set_max_ack_ranges(&mut self, ranges: u32) -> Result<(), Error> {
+        if ranges == 0 {
+            bail!("Maximum number of ack ranges must be at least 1");
+        }
+
+        unsafe {
+            picoquic_set_max_ack_ranges(self.quic, ranges);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the padding policy of the packets that are not `Initial` packets.
+    fn set_padding_policy(&mut self, min_size: u32, multiple: u32) -> Result<(), Error> {
+        if min_size > PICOQUIC_MAX_PACKET_SIZE || multiple > PICOQUIC_MAX_PACKET_SIZE {
+            bail!("Padding must not be greater than the maximum packet size");
+        }
+
+        unsafe {
+            picoquic_set_padding_policy(self.quic, min_size, multiple);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the maximum size of the packets that are sent before the path MTU is discovered,
+    /// for IPv4 and IPv6 paths.
+    fn set_max_handshake_packet_size(&mut self, size: u32) -> Result<(), Error> {
+        if !(PICOQUIC_ENFORCED_INITIAL_MTU..=PICOQUIC_MAX_PACKET_SIZE).contains(&size) {
+            bail!(
+                "The maximum handshake packet size must be between {} and {} bytes",
+                PICOQUIC_ENFORCED_INITIAL_MTU,
+                PICOQUIC_MAX_PACKET_SIZE
+            );
+        }
+
+        unsafe {
+            picoquic_set_initial_send_mtu(self.quic, size, size);
+        }
+
+        Ok(())
+    }
+
+    /// Limits the flow control window, that picoquic grants to the peer of a connection.
+    fn set_max_recv_buffer(&mut self, bytes: usize) -> Result<(), Error> {
+        if bytes == 0 {
+            bail!("The receive buffer must not be empty");
+        }
+
+        unsafe {
+            picoquic_set_max_data_control(self.quic, bytes as u64);
+        }
+
+        Ok(())
+    }
+
+    /// Restricts the cipher suites of picotls to the given cipher suites.
+    fn set_cipher_suites(&mut self, suites: &[CipherSuite]) -> Result<(), Error> {
+        let ids = suites.iter().map(|s| s.id()).collect::<Vec<_>>();
+
+        unsafe {
+            let tls_ctx = (*self.quic).tls_master_ctx as *mut ptls_context_t;
+            let mut list = filter_algorithms((*tls_ctx).cipher_suites, &ids, |s| (*s).id);
+
+            if list.len() == 1 {
+                bail!("None of the cipher suites is supported");
+            }
+
+            (*tls_ctx).cipher_suites = list.as_mut_ptr();
+            self._cipher_suites = Some(list);
+        }
+
+        Ok(())
+    }
+
+    /// Restricts the key exchange algorithms of picotls to the given groups.
+    fn set_key_exchange_groups(&mut self, groups: &[KeyExchangeGroup]) -> Result<(), Error> {
+        let ids = groups.iter().map(|g| g.id()).collect::<Vec<_>>();
+
+        unsafe {
+            let tls_ctx = (*self.quic).tls_master_ctx as *mut ptls_context_t;
+            let mut list = filter_algorithms((*tls_ctx).key_exchanges, &ids, |k| (*k).id);
+
+            if list.len() == 1 {
+                bail!("None of the key exchange groups is supported");
+            }
+
+            (*tls_ctx).key_exchanges = list.as_mut_ptr();
+            self._key_exchanges = Some(list);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the custom transport parameters that are send to the peers, in addition to the
+    /// transport parameters of picoquic.
+    fn set_custom_transport_parameters(&mut self, params: &[(u64, Vec<u8>)]) -> Result<(), Error> {
+        let data = transport_parameters::encode(params)?;
+
+        // picoquic copies the parameters.
+        let ret = unsafe {
+            picoquic_set_extra_transport_parameters(self.quic, data.as_ptr(), data.len())
+        };
+
+        if ret != 0 {
+            bail!("Could not set the custom transport parameters: {}", ret);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the rate limit of new incoming connections.
+    fn set_new_connection_rate_limit(
+        &mut self,
+        per_sec: u32,
+        policy: RateLimitPolicy,
+    ) -> Result<(), Error> {
+        if per_sec == 0 {
+            bail!("The new connection rate limit must be at least 1 connection per second");
+        }
+
+        self.rate_limit = Some(RateLimit::new(per_sec, policy));
+        Ok(())
+    }
+
+    /// Sets if new incoming connections are refused with `SERVER_BUSY`.
+    pub fn set_server_busy(&self, busy: bool) {
+        unsafe {
+            (*self.quic).server_busy = busy as i32;
+        }
+    }
+
+    /// Sets the `grease_quic_bit` transport parameter that is send to the peers.
+    fn set_grease_quic_bit(&mut self, grease: bool) {
+        unsafe {
+            (*self.quic).default_tp.do_grease_quic_bit = grease as i32;
+        }
+    }
+
     /// Sets the tls certificate chain.
     fn set_tls_certificate_chain(
         &mut self,
@@ -214,6 +943,36 @@ impl QuicCtx {
         Ok(())
     }
 
+    /// Sets the maximum number of session tickets in the ticket store and drops the tickets
+    /// that were loaded from the ticket store file above this number.
+    fn set_ticket_store_capacity(&mut self, tickets: usize) -> Result<(), Error> {
+        if tickets == 0 {
+            bail!("The ticket store capacity must not be 0");
+        }
+
+        self.ticket_store_capacity = Some(tickets);
+        self.prune_ticket_store();
+
+        Ok(())
+    }
+
+    /// Drops the oldest session tickets, until the ticket store holds no more tickets than its
+    /// capacity.
+    pub fn prune_ticket_store(&self) {
+        let capacity = match self.ticket_store_capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        unsafe {
+            let mut oldest = detach_oldest_tickets(&mut (*self.quic).p_first_ticket, capacity);
+
+            if !oldest.is_null() {
+                picoquic_free_tickets(&mut oldest);
+            }
+        }
+    }
+
     /// Sets the tls root certificates.
     fn set_tls_root_certificates(
         &mut self,
@@ -255,8 +1014,65 @@ impl QuicCtx {
     }
 }
 
+/// Detaches the oldest tickets from the given list of stored tickets, so the list keeps at most
+/// `capacity` tickets. Picoquic appends new tickets to the end of the list.
+///
+/// # Returns
+/// The list of the detached tickets or `NULL`, if no ticket was detached.
+unsafe fn detach_oldest_tickets(
+    first: &mut *mut picoquic_stored_ticket_t,
+    capacity: usize,
+) -> *mut picoquic_stored_ticket_t {
+    let mut len = 0;
+    let mut ticket = *first;
+
+    while !ticket.is_null() {
+        len += 1;
+        ticket = (*ticket).next_ticket;
+    }
+
+    if len <= capacity {
+        return ptr::null_mut();
+    }
+
+    let oldest = *first;
+    let mut last_oldest = oldest;
+
+    for _ in 1..len - capacity {
+        last_oldest = (*last_oldest).next_ticket;
+    }
+
+    *first = (*last_oldest).next_ticket;
+    (*last_oldest).next_ticket = ptr::null_mut();
+
+    oldest
+}
+
+/// Returns the algorithms of the given `NULL` terminated list of picotls, that have one of the
+/// given ids. The returned list is `NULL` terminated as well.
+unsafe fn filter_algorithms<T, F>(mut list: *mut *mut T, ids: &[u16], id: F) -> Vec<*mut T>
+where
+    F: Fn(*mut T) -> u16,
+{
+    let mut filtered = Vec::new();
+
+    while !list.is_null() && !(*list).is_null() {
+        if ids.contains(&id(*list)) {
+            filtered.push(*list);
+        }
+
+        list = list.offset(1);
+    }
+
+    filtered.push(ptr::null_mut());
+    filtered
+}
+
 impl Drop for QuicCtx {
     fn drop(&mut self) {
+        select_alpn::remove_callback(self);
+        event_sink::remove_callback(self);
+
         unsafe {
             picoquic_free(self.quic);
         }
@@ -296,14 +1112,38 @@ fn make_certs_iovec(
     Ok((certs_ptr, len))
 }
 
-pub fn socket_addr_from_c(sock_addr: *mut picoquic::sockaddr, sock_len: i32) -> SocketAddr {
+/// Converts the given `sockaddr_storage` to a `SocketAddr`.
+/// If the address is neither an IPv4 nor an IPv6 address (e.g. the storage is not set),
+/// `None` is returned.
+pub fn socket_addr_from_storage(storage: &mut picoquic::sockaddr_storage) -> Option<SocketAddr> {
+    let socket_family = i32::from(storage.ss_family);
+    let addr = storage as *mut picoquic::sockaddr_storage as *mut picoquic::sockaddr;
+
+    let socket_len = if socket_family == libc::AF_INET {
+        mem::size_of::<libc::sockaddr_in>()
+    } else if socket_family == libc::AF_INET6 {
+        mem::size_of::<libc::sockaddr_in6>()
+    } else {
+        return None;
+    };
+
+    socket_addr_from_c(addr, socket_len as i32)
+}
+
+/// Converts the given c socket address to a `SocketAddr`.
+/// If the pointer is null or the address is neither an IPv4 nor an IPv6 address, `None` is
+/// returned.
+pub fn socket_addr_from_c(sock_addr: *mut picoquic::sockaddr, sock_len: i32) -> Option<SocketAddr> {
+    if sock_addr.is_null() || sock_len <= 0 {
+        return None;
+    }
+
     let addr =
         unsafe { SockAddr::from_raw_parts(sock_addr as *const libc::sockaddr, sock_len as u32) };
 
     addr.as_inet()
         .map(|v| v.into())
         .or_else(|| addr.as_inet6().map(|v| v.into()))
-        .expect("neither ipv4 nor ipv6?")
 }
 
 pub trait MicroSeconds {
@@ -342,6 +1182,123 @@ mod tests {
         assert_eq!(Duration::new(0, 500000), Duration::from_micro_seconds(500));
     }
 
+    #[test]
+    fn new_connection_rate_limit_refills_tokens_over_time() {
+        let limit = RateLimit::new(2, RateLimitPolicy::Drop);
+
+        assert!(limit.take(0));
+        assert!(limit.take(0));
+        assert!(!limit.take(100_000));
+        assert!(limit.take(500_000));
+        assert!(!limit.take(500_000));
+        // The bucket does not fill up beyond the burst size.
+        assert!(limit.take(10_000_000));
+        assert!(limit.take(10_000_000));
+        assert!(!limit.take(10_000_000));
+
+        assert!(QuicCtx::dummy()
+            .set_new_connection_rate_limit(0, RateLimitPolicy::Retry)
+            .is_err());
+    }
+
+    #[test]
+    fn local_cid_length_out_of_range_is_rejected() {
+        assert!(QuicCtx::dummy().set_local_cid_length(0).is_err());
+        assert!(QuicCtx::dummy()
+            .set_local_cid_length(PICOQUIC_CONNECTION_ID_MAX_SIZE as u8 + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn zero_ticket_store_capacity_is_rejected() {
+        assert!(QuicCtx::dummy().set_ticket_store_capacity(0).is_err());
+    }
+
+    #[test]
+    fn oldest_tickets_above_the_capacity_are_detached() {
+        let mut tickets: Vec<picoquic_stored_ticket_t> =
+            (0..4).map(|_| unsafe { mem::zeroed() }).collect();
+        for i in 0..3 {
+            tickets[i].next_ticket = &mut tickets[i + 1];
+        }
+        let mut first: *mut picoquic_stored_ticket_t = &mut tickets[0];
+
+        unsafe {
+            assert!(detach_oldest_tickets(&mut first, 4).is_null());
+
+            let oldest = detach_oldest_tickets(&mut first, 1);
+            assert_eq!(&mut tickets[0] as *mut _, oldest);
+            assert_eq!(&mut tickets[3] as *mut _, first);
+            assert!((*first).next_ticket.is_null());
+            assert!(tickets[2].next_ticket.is_null());
+        }
+    }
+
+    #[test]
+    fn active_connection_id_limit_below_two_is_rejected() {
+        assert!(QuicCtx::dummy().set_active_connection_id_limit(0).is_err());
+        assert!(QuicCtx::dummy().set_active_connection_id_limit(1).is_err());
+    }
+
+    #[test]
+    fn invalid_ack_delays_are_rejected() {
+        assert!(QuicCtx::dummy()
+            .set_ack_delays(Some(Duration::from_secs(20)), None)
+            .is_err());
+        assert!(QuicCtx::dummy()
+            .set_ack_delays(
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(20))
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_ack_delay_exponent_and_ack_ranges_are_rejected() {
+        assert!(QuicCtx::dummy().set_ack_delay_exponent(21).is_err());
+        assert!(QuicCtx::dummy().set_max_ack_ranges(0).is_err());
+    }
+
+    #[test]
+    fn custom_transport_parameters_with_defined_ids_are_rejected() {
+        assert!(QuicCtx::dummy()
+            .set_custom_transport_parameters(&[(0x04, vec![1])])
+            .is_err());
+    }
+
+    #[test]
+    fn padding_larger_than_packet_size_is_rejected() {
+        assert!(QuicCtx::dummy()
+            .set_padding_policy(PICOQUIC_MAX_PACKET_SIZE + 1, 0)
+            .is_err());
+        assert!(QuicCtx::dummy()
+            .set_padding_policy(0, PICOQUIC_MAX_PACKET_SIZE + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn max_handshake_packet_size_out_of_range_is_rejected() {
+        let mut quic = QuicCtx::dummy();
+
+        assert!(quic.set_max_handshake_packet_size(1199).is_err());
+        assert!(quic
+            .set_max_handshake_packet_size(PICOQUIC_MAX_PACKET_SIZE + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn empty_recv_buffer_is_rejected() {
+        assert!(QuicCtx::dummy().set_max_recv_buffer(0).is_err());
+    }
+
+    #[test]
+    fn invalid_c_socket_addresses_are_not_converted() {
+        assert!(socket_addr_from_c(ptr::null_mut(), 0).is_none());
+
+        let mut storage: picoquic::sockaddr_storage = unsafe { mem::zeroed() };
+        assert!(socket_addr_from_storage(&mut storage).is_none());
+    }
+
     #[test]
     fn as_micro_seconds() {
         assert_eq!(Duration::from_secs(1).as_micro_seconds(), 1_000_000);
@@ -0,0 +1,35 @@
+use ffi::QuicCtx;
+use random::RANDOM_SOURCE;
+
+use picoquic_sys::picoquic::ptls_context_t;
+
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::slice;
+
+/// Sets up the random bytes callback in picotls
+pub fn setup_callback(quic: &QuicCtx) {
+    unsafe {
+        let tls_ctx = (*quic.as_ptr()).tls_master_ctx as *mut ptls_context_t;
+        (*tls_ctx).random_bytes = Some(random_bytes_callback);
+    }
+}
+
+/// Will be called by picotls to fill the given buffer with random bytes
+unsafe extern "C" fn random_bytes_callback(buf: *mut c_void, len: usize) {
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let source = RANDOM_SOURCE.read().unwrap();
+        let source = source
+            .as_ref()
+            .expect("random bytes callback is only set up with a random source");
+        source(buf)
+    }));
+
+    if res.is_err() {
+        error!("random source panicked, aborting!");
+        process::abort();
+    }
+}
@@ -0,0 +1,61 @@
+use ffi::QuicCtx;
+use select_alpn::SelectAlpn;
+
+use picoquic_sys::picoquic::{picoquic_quic_t, picoquic_set_alpn_select_fn, ptls_iovec_t};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::str;
+
+thread_local! {
+    /// Picoquic does not provide a context pointer to the ALPN select callback, so we need to
+    /// store the handlers per `picoquic_quic_t`.
+    static HANDLERS: RefCell<HashMap<usize, Box<SelectAlpn>>> = RefCell::new(HashMap::new());
+}
+
+/// Sets up the ALPN select callback in picoquic
+pub fn setup_callback(quic: &QuicCtx, handler: Box<SelectAlpn>) {
+    HANDLERS.with(|h| h.borrow_mut().insert(quic.as_ptr() as usize, handler));
+
+    unsafe {
+        picoquic_set_alpn_select_fn(quic.as_ptr(), Some(alpn_select_callback));
+    }
+}
+
+/// Removes the handler of the given quic context, if one was set up.
+pub fn remove_callback(quic: &QuicCtx) {
+    HANDLERS.with(|h| h.borrow_mut().remove(&(quic.as_ptr() as usize)));
+}
+
+/// Will be called by picoquic to select the ALPN protocol.
+/// Returning `count` means that no protocol was selected.
+unsafe extern "C" fn alpn_select_callback(
+    quic: *mut picoquic_quic_t,
+    list: *mut ptls_iovec_t,
+    count: usize,
+) -> usize {
+    let protocols = slice::from_raw_parts(list, count)
+        .iter()
+        .map(|p| str::from_utf8(slice::from_raw_parts(p.base, p.len)).unwrap_or(""))
+        .collect::<Vec<_>>();
+
+    // A panic must not unwind into the c code.
+    let selected = panic::catch_unwind(AssertUnwindSafe(|| {
+        HANDLERS.with(|h| {
+            h.borrow_mut()
+                .get_mut(&(quic as usize))
+                .and_then(|handler| handler.select(&protocols))
+        })
+    }));
+
+    match selected {
+        Ok(Some(index)) if index < count => index,
+        Ok(_) => count,
+        Err(_) => {
+            error!("ALPN select handler panicked!");
+            count
+        }
+    }
+}
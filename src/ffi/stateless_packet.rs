@@ -1,17 +1,14 @@
-use super::quic_ctx::socket_addr_from_c;
+use super::quic_ctx::socket_addr_from_storage;
 use picoquic_sys::picoquic::{
-    self, picoquic_delete_stateless_packet, picoquic_dequeue_stateless_packet, picoquic_quic_t,
+    picoquic_delete_stateless_packet, picoquic_dequeue_stateless_packet, picoquic_quic_t,
     picoquic_stateless_packet_t,
 };
 
 use std::iter::Iterator;
 use std::marker::PhantomData;
-use std::mem;
 use std::net::SocketAddr;
 use std::slice;
 
-use libc;
-
 pub struct StatelessPacket {
     packet: *mut picoquic_stateless_packet_t,
 }
@@ -21,20 +18,16 @@ impl StatelessPacket {
         StatelessPacket { packet }
     }
 
-    pub fn get_peer_addr(&self) -> SocketAddr {
-        let addr = unsafe {
-            mem::transmute::<_, *mut libc::sockaddr_storage>(&mut (*self.packet).addr_to)
-                as *mut picoquic::sockaddr
-        };
-        let socket_family = unsafe { (*self.packet).addr_to.ss_family };
-
-        let socket_len = if i32::from(socket_family) == libc::AF_INET {
-            mem::size_of::<libc::sockaddr_in>()
-        } else {
-            mem::size_of::<libc::sockaddr_in6>()
-        };
+    /// Returns the address of the peer, the packet should be sent to.
+    /// If picoquic did not set a valid address, `None` is returned.
+    pub fn get_peer_addr(&self) -> Option<SocketAddr> {
+        unsafe { socket_addr_from_storage(&mut (*self.packet).addr_to) }
+    }
 
-        socket_addr_from_c(addr, socket_len as i32)
+    /// Returns the local address, the packet should be sent from.
+    /// If picoquic did not set a local address, `None` is returned.
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        unsafe { socket_addr_from_storage(&mut (*self.packet).addr_local) }
     }
 
     pub fn get_data(&self) -> &[u8] {
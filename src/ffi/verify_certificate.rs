@@ -9,6 +9,8 @@ use picoquic_sys::picoquic::{
     PTLS_ERROR_LIBRARY, PTLS_ERROR_NO_MEMORY,
 };
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::slice;
@@ -25,6 +27,22 @@ use openssl_sys::{X509_V_ERR_CERT_HAS_EXPIRED, X509_V_ERR_CERT_REVOKED, X509_V_E
 
 pub type PubKey = PKey<Public>;
 
+thread_local! {
+    /// The certificates presented by the peer of each `picoquic_cnx_t`.
+    static PEER_CERTIFICATES: RefCell<HashMap<usize, Vec<X509>>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the certificates that were presented by the peer of the given connection.
+/// The certificates are only known, if a verify certificate handler is set up.
+pub fn peer_certificates(cnx: *mut picoquic_cnx_t) -> Option<Vec<X509>> {
+    PEER_CERTIFICATES.with(|c| c.borrow().get(&(cnx as usize)).cloned())
+}
+
+/// Removes the peer certificates of the given connection.
+pub fn remove_peer_certificates(cnx: *mut picoquic_cnx_t) {
+    PEER_CERTIFICATES.with(|c| c.borrow_mut().remove(&(cnx as usize)));
+}
+
 /// Sets up the verify certificate callback in picoquic
 pub fn setup_callback(quic: &QuicCtx, handler: Box<VerifyCertificate>) -> Result<(), Error> {
     let result;
@@ -144,7 +162,8 @@ fn verify_certificate_callback_impl(
         Err(_) => return PTLS_ALERT_BAD_CERTIFICATE,
     };
 
-    let cnx = Connection::from(cnx);
+    // Called by picoquic in the handshake of the connection, so the connection is alive.
+    let cnx = unsafe { Connection::from_raw(cnx) };
 
     let id = cnx.local_id();
 
@@ -163,6 +182,10 @@ fn verify_certificate_callback_impl(
         Err(e) => return ssl_error_to_error_code(&e),
     };
 
+    let mut certs = vec![cert];
+    certs.extend(chain);
+    PEER_CERTIFICATES.with(|c| c.borrow_mut().insert(cnx.as_ptr() as usize, certs));
+
     unsafe {
         *verify_sign = Some(verify_sign_callback);
         *verify_sign_ctx = Box::into_raw(Box::new(pkey)) as *mut c_void;
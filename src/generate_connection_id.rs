@@ -0,0 +1,23 @@
+/// The `GenerateConnectionId` trait is used to generate the local connection ids.
+pub trait GenerateConnectionId {
+    /// Will be called to generate a new connection id.
+    ///
+    /// The given buffer has the configured connection id length and is already filled with
+    /// random bytes. Only the bytes that should carry information (e.g. a server identifier for a
+    /// load balancer) should be overwritten, to keep the connection ids unpredictable.
+    /// If the generated connection id collides with an existing one, the function is called
+    /// again with new random bytes. After 8 collisions (or if the function panics), the random
+    /// connection id chosen by picoquic is used instead and an error is logged. This connection
+    /// id does not carry the information of the generator, so a load balancer may route its
+    /// packets to the wrong server.
+    fn generate(&mut self, id: &mut [u8]);
+}
+
+impl<F> GenerateConnectionId for F
+where
+    F: FnMut(&mut [u8]),
+{
+    fn generate(&mut self, id: &mut [u8]) {
+        self(id)
+    }
+}
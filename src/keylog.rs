@@ -0,0 +1,19 @@
+/// The `Keylog` trait is used to consume the TLS secrets of all `Connection`s of a `Context`.
+///
+/// The secrets are given as lines in the NSS key log format (`SSLKEYLOGFILE`), which can be
+/// used by Wireshark to decrypt captured QUIC packets. Anyone with access to these lines can
+/// decrypt the traffic, so this must only be used for debugging and never in production!
+pub trait Keylog {
+    /// Will be called for each secret that is derived in a TLS handshake.
+    /// The line is given without a trailing newline.
+    fn log(&mut self, line: &str);
+}
+
+impl<F> Keylog for F
+where
+    F: FnMut(&str),
+{
+    fn log(&mut self, line: &str) {
+        self(line)
+    }
+}
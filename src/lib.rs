@@ -102,7 +102,7 @@ fn main() {
         .run(server.for_each(|c| {
             let handle = handle.clone();
 
-            println!("New connection from: {}", c.peer_addr());
+            println!("New connection from: {:?}", c.peer_addr());
 
             handle.clone().spawn(c.for_each(move |s| {
                 // We print the received message and sent a new one, after that we collect all
@@ -157,6 +157,8 @@ extern crate failure;
 extern crate failure_derive;
 #[macro_use]
 extern crate futures;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 #[macro_use]
 extern crate log;
@@ -167,22 +169,55 @@ extern crate socket2;
 #[macro_use]
 extern crate tokio_core;
 
+mod accept_filter;
 mod config;
 mod connection;
+mod connection_pool;
 mod context;
 mod context_inner;
+mod early_data;
 #[macro_use]
 mod error;
+mod event_sink;
 mod ffi;
+mod generate_connection_id;
+mod keylog;
+mod packet;
+mod packet_info;
+mod peer_address_change;
+mod pool;
+mod random;
+mod select_alpn;
 mod stream;
+#[cfg(feature = "test-transport")]
+pub mod test_transport;
+#[cfg(feature = "test-transport")]
+pub mod test_util;
+mod transport_parameters;
 mod verify_certificate;
 
-pub use self::config::{Config, FileFormat, Role};
+pub use self::accept_filter::{AcceptDecision, AcceptFilter, IncomingInfo};
+pub use self::config::{Config, FileFormat, OverflowPolicy, RateLimitPolicy, Role};
 pub use self::connection::{
-    Connection, Id as ConnectionId, NewStreamFuture, NewStreamHandle, Type as ConnectionType,
+    CipherSuite, CloseInfo, CongestionAlgorithm, CongestionCause, CongestionDebug, CongestionEvent,
+    Connection, DisconnectReason, EcnCounts, Event, EventFlags, Events, HealthPolicy,
+    Id as ConnectionId, KeyExchangeGroup, NewStreamFuture, NewStreamHandle, PauseStreamFuture,
+    PeerExtensions, ReadHalf as ConnectionReadHalf, SendFromReaderFuture, SendLimit,
+    State as ConnectionState, StreamCounts, Type as ConnectionType,
+    WriteHalf as ConnectionWriteHalf,
 };
-pub use self::context::Context;
+pub use self::connection_pool::{ConnectionPool, GetConnection};
+pub use self::context::{AcceptRequest, Context, Request};
 pub use self::context_inner::{NewConnectionFuture, NewConnectionHandle};
+pub use self::early_data::{EarlyDataFilter, EarlyDataPolicy};
 pub use self::error::{Error, ErrorKind};
-pub use self::stream::{Stream, Type as SType};
+pub use self::event_sink::{EventSink, FrameStats, PacketType, QuicEvent};
+pub use self::generate_connection_id::GenerateConnectionId;
+pub use self::keylog::Keylog;
+pub use self::packet::{Packet, PacketHeader};
+pub use self::peer_address_change::PeerAddressChange;
+pub use self::pool::CapacityStats;
+pub use self::random::set_random_source;
+pub use self::select_alpn::SelectAlpn;
+pub use self::stream::{Direction as StreamDirection, Id as StreamId, Stream, Type as SType};
 pub use self::verify_certificate::{default_verify_certificate, VerifyCertificate};
@@ -0,0 +1,171 @@
+use error::*;
+use event_sink::PacketType;
+
+use picoquic_sys::picoquic::PICOQUIC_CONNECTION_ID_MAX_SIZE;
+
+/// The length of the connection ids that are generated by picoquic by default.
+const DEFAULT_CONNECTION_ID_LENGTH: usize = 8;
+
+/// The public header fields of a QUIC packet, that can be read without decrypting the packet.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PacketHeader {
+    pub packet_type: PacketType,
+    /// The QUIC version of a packet with a long header. Packets with a short header do not
+    /// carry a version.
+    pub version: Option<u32>,
+    pub destination_id: Vec<u8>,
+    /// The source connection id of a packet with a long header. Packets with a short header do
+    /// not carry a source connection id.
+    pub source_id: Option<Vec<u8>>,
+}
+
+/// Helper functions for working with encoded QUIC packets.
+pub struct Packet;
+
+impl Packet {
+    /// Parses the public header of the given packet. The destination connection id of a packet
+    /// with a short header is not length prefixed, so the default length of picoquic is assumed.
+    /// Use `parse_header_with_id_length`, if `Config::set_local_connection_id_length` is used.
+    pub fn parse_header(data: &[u8]) -> Result<PacketHeader, Error> {
+        Packet::parse_header_with_id_length(data, DEFAULT_CONNECTION_ID_LENGTH)
+    }
+
+    /// Parses the public header of the given packet. The destination connection id of a packet
+    /// with a short header is assumed to be `short_header_id_length` bytes long.
+    pub fn parse_header_with_id_length(
+        data: &[u8],
+        short_header_id_length: usize,
+    ) -> Result<PacketHeader, Error> {
+        let first = match data.first() {
+            Some(first) => *first,
+            None => bail!("Packet is empty"),
+        };
+
+        if first & 0x80 == 0 {
+            let destination_id = match data.get(1..1 + short_header_id_length) {
+                Some(id) => id.to_vec(),
+                None => bail!("Packet is too short for the destination connection id"),
+            };
+
+            return Ok(PacketHeader {
+                packet_type: PacketType::OneRtt,
+                version: None,
+                destination_id,
+                source_id: None,
+            });
+        }
+
+        let version = match data.get(1..5) {
+            Some(v) => v.iter().fold(0, |v, b| (v << 8) | u32::from(*b)),
+            None => bail!("Packet is too short for the version"),
+        };
+
+        let (destination_id, rest) = read_connection_id(&data[5..], version)?;
+        let (source_id, _) = read_connection_id(rest, version)?;
+
+        let packet_type = if version == 0 {
+            PacketType::VersionNegotiation
+        } else {
+            match (first >> 4) & 0x03 {
+                0 => PacketType::Initial,
+                1 => PacketType::ZeroRtt,
+                2 => PacketType::Handshake,
+                _ => PacketType::Retry,
+            }
+        };
+
+        Ok(PacketHeader {
+            packet_type,
+            version: Some(version),
+            destination_id,
+            source_id: Some(source_id),
+        })
+    }
+}
+
+/// Reads a length prefixed connection id.
+///
+/// # Returns
+/// The connection id and the remaining data.
+fn read_connection_id(data: &[u8], version: u32) -> Result<(Vec<u8>, &[u8]), Error> {
+    let len = match data.first() {
+        Some(len) => *len as usize,
+        None => bail!("Packet is too short for the connection id length"),
+    };
+
+    // Version negotiation packets may carry longer connection ids of other versions.
+    if version != 0 && len > PICOQUIC_CONNECTION_ID_MAX_SIZE as usize {
+        bail!(
+            "Connection id is longer than {} bytes",
+            PICOQUIC_CONNECTION_ID_MAX_SIZE
+        );
+    }
+
+    match data.get(1..1 + len) {
+        Some(id) => Ok((id.to_vec(), &data[1 + len..])),
+        None => bail!("Packet is too short for the connection id"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_initial_header() {
+        let packet = [
+            0xc3, 0x00, 0x00, 0x00, 0x01, 0x02, 0xaa, 0xbb, 0x01, 0xcc, 0x00, 0x41,
+        ];
+
+        assert_eq!(
+            PacketHeader {
+                packet_type: PacketType::Initial,
+                version: Some(1),
+                destination_id: vec![0xaa, 0xbb],
+                source_id: Some(vec![0xcc]),
+            },
+            Packet::parse_header(&packet).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_handshake_and_version_negotiation_header() {
+        let handshake = [0xe0, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        assert_eq!(
+            PacketType::Handshake,
+            Packet::parse_header(&handshake).unwrap().packet_type
+        );
+
+        let negotiation = [
+            0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+        assert_eq!(
+            PacketType::VersionNegotiation,
+            Packet::parse_header(&negotiation).unwrap().packet_type
+        );
+    }
+
+    #[test]
+    fn parse_short_header() {
+        let packet = [0x41, 0x01, 0x02, 0x03, 0x04, 0x55];
+
+        assert_eq!(
+            PacketHeader {
+                packet_type: PacketType::OneRtt,
+                version: None,
+                destination_id: vec![0x01, 0x02, 0x03, 0x04],
+                source_id: None,
+            },
+            Packet::parse_header_with_id_length(&packet, 4).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        assert!(Packet::parse_header(&[]).is_err());
+        assert!(Packet::parse_header(&[0x41, 0x01]).is_err());
+        assert!(Packet::parse_header(&[0xc3, 0x00, 0x00]).is_err());
+        assert!(Packet::parse_header(&[0xc3, 0x00, 0x00, 0x00, 0x01, 0x04, 0xaa]).is_err());
+        assert!(Packet::parse_header(&[0xc3, 0x00, 0x00, 0x00, 0x01, 0x15]).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Receiving and sending datagrams with the local address of the datagram (`IP_PKTINFO`).
+//!
+//! A socket that is bound to an unspecified address (`0.0.0.0` or `::`) receives the datagrams
+//! of all local addresses. To reply from the address the peer contacted, the destination address
+//! of each received datagram is requested from the kernel and given as source address when
+//! sending the replies.
+//!
+//! The ECN codepoint of each received datagram (the two lowest bits of the TOS byte or the
+//! traffic class) can be requested from the kernel in the same way (`IP_RECVTOS`).
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use tokio_core::net::UdpSocket;
+
+/// The bits of the TOS byte or the traffic class that carry the ECN codepoint.
+#[cfg(target_os = "linux")]
+const ECN_MASK: u8 = 0x03;
+
+/// Requests the destination address of each received datagram from the kernel.
+#[cfg(target_os = "linux")]
+pub fn enable(socket: &UdpSocket, ipv4: bool) -> io::Result<()> {
+    if ipv4 {
+        enable_option(socket, libc::IPPROTO_IP, libc::IP_PKTINFO)
+    } else {
+        enable_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable(_: &UdpSocket, _: bool) -> io::Result<()> {
+    warn!("the local address of received datagrams is only supported on Linux");
+    Ok(())
+}
+
+/// Requests the ECN codepoint of each received datagram from the kernel.
+#[cfg(target_os = "linux")]
+pub fn enable_ecn(socket: &UdpSocket, ipv4: bool) -> io::Result<()> {
+    if ipv4 {
+        enable_option(socket, libc::IPPROTO_IP, libc::IP_RECVTOS)
+    } else {
+        enable_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_ecn(_: &UdpSocket, _: bool) -> io::Result<()> {
+    warn!("the ECN codepoint of received datagrams is only supported on Linux");
+    Ok(())
+}
+
+/// Enables the given boolean socket option.
+#[cfg(target_os = "linux")]
+fn enable_option(socket: &UdpSocket, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let value: libc::c_int = 1;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Receives a datagram from the given socket.
+///
+/// # Returns
+/// The length of the datagram, the address of the peer, the local address the datagram was
+/// sent to and the ECN codepoint of the datagram, if the kernel reported them.
+#[cfg(target_os = "linux")]
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>, Option<u8>)> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use socket2::SockAddr;
+
+    if socket.poll_read().is_not_ready() {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut control = [0u8; 64];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of_val(&addr) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+
+    if len < 0 {
+        let err = io::Error::last_os_error();
+
+        if err.kind() == io::ErrorKind::WouldBlock {
+            // `tokio-core` only clears the readiness of the socket, if its own functions would
+            // block. If a datagram arrived in the meantime, it is returned without local address
+            // and ECN codepoint.
+            return socket
+                .recv_from(buf)
+                .map(|(len, peer)| (len, peer, None, None));
+        }
+
+        return Err(err);
+    }
+
+    let peer = unsafe {
+        SockAddr::from_raw_parts(&addr as *const _ as *const _, msg.msg_namelen).as_std()
+    };
+    let peer = match peer {
+        Some(peer) => peer,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown peer address",
+            ))
+        }
+    };
+
+    let mut local = None;
+    let mut ecn = None;
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        while !cmsg.is_null() {
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info =
+                        ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                    local = Some(IpAddr::from(Ipv4Addr::from(u32::from_be(
+                        info.ipi_addr.s_addr,
+                    ))));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info =
+                        ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo);
+                    local = Some(IpAddr::from(info.ipi6_addr.s6_addr));
+                }
+                // The TOS byte is delivered as a single byte, the traffic class as an integer.
+                (libc::IPPROTO_IP, libc::IP_TOS) => {
+                    ecn = Some(*libc::CMSG_DATA(cmsg) & ECN_MASK);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let tclass = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    ecn = Some(tclass as u8 & ECN_MASK);
+                }
+                _ => {}
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((len as usize, peer, local, ecn))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>, Option<u8>)> {
+    socket
+        .recv_from(buf)
+        .map(|(len, peer)| (len, peer, None, None))
+}
+
+/// Sends the given datagram from the given local address to the peer.
+#[cfg(target_os = "linux")]
+pub fn send_from(
+    socket: &UdpSocket,
+    buf: &[u8],
+    from: IpAddr,
+    to: &SocketAddr,
+) -> io::Result<usize> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use socket2::SockAddr;
+
+    if socket.poll_write().is_not_ready() {
+        return Err(io::ErrorKind::WouldBlock.into());
+    }
+
+    let to_addr = SockAddr::from(*to);
+    let mut control = [0u8; 64];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = to_addr.as_ptr() as *mut libc::c_void;
+    msg.msg_namelen = to_addr.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+
+    unsafe {
+        match from {
+            IpAddr::V4(ip) => {
+                let mut info: libc::in_pktinfo = mem::zeroed();
+                info.ipi_spec_dst.s_addr = u32::from(ip).to_be();
+
+                msg.msg_controllen = libc::CMSG_SPACE(mem::size_of_val(&info) as u32) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::IPPROTO_IP;
+                (*cmsg).cmsg_type = libc::IP_PKTINFO;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&info) as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo, info);
+            }
+            IpAddr::V6(ip) => {
+                let mut info: libc::in6_pktinfo = mem::zeroed();
+                info.ipi6_addr.s6_addr = ip.octets();
+
+                msg.msg_controllen = libc::CMSG_SPACE(mem::size_of_val(&info) as u32) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+                (*cmsg).cmsg_type = libc::IPV6_PKTINFO;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&info) as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo, info);
+            }
+        }
+    }
+
+    let len = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+
+    if len < 0 {
+        let err = io::Error::last_os_error();
+
+        if err.kind() == io::ErrorKind::WouldBlock {
+            // `tokio-core` only clears the readiness of the socket, if its own functions would
+            // block. If the socket got writable in the meantime, the datagram is sent from the
+            // address chosen by the kernel.
+            return socket.send_to(buf, to);
+        }
+
+        return Err(err);
+    }
+
+    Ok(len as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_from(socket: &UdpSocket, buf: &[u8], _: IpAddr, to: &SocketAddr) -> io::Result<usize> {
+    socket.send_to(buf, to)
+}
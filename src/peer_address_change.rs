@@ -0,0 +1,39 @@
+use connection;
+use error::*;
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+/// The handler is shared by all `Connection`s of a `Context`.
+pub(crate) type PeerAddressChangeHandler = Rc<RefCell<Box<PeerAddressChange>>>;
+
+/// The `PeerAddressChange` trait is used to enforce a policy on the migration of `Connection`s.
+pub trait PeerAddressChange {
+    /// Will be called, when the address of the peer of a `Connection` changed.
+    ///
+    /// # Result
+    ///
+    /// An error, if the change is not allowed. The `Connection` is closed in this case and the
+    /// error is propagated to the `Connection`.
+    fn on_peer_address_change(
+        &mut self,
+        id: connection::Id,
+        old: SocketAddr,
+        new: SocketAddr,
+    ) -> Result<(), Error>;
+}
+
+impl<F> PeerAddressChange for F
+where
+    F: FnMut(connection::Id, SocketAddr, SocketAddr) -> Result<(), Error>,
+{
+    fn on_peer_address_change(
+        &mut self,
+        id: connection::Id,
+        old: SocketAddr,
+        new: SocketAddr,
+    ) -> Result<(), Error> {
+        self(id, old, new)
+    }
+}
@@ -0,0 +1,141 @@
+use stream;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// The `Pool` is shared by all `Connection`s of a `Context`.
+pub(crate) type PoolHandle = Rc<RefCell<Pool>>;
+
+/// The map that stores the `Stream`s of a `Connection`.
+pub(crate) type StreamMap = HashMap<stream::Id, stream::Context>;
+
+/// The usage of the capacity that is allocated upfront for the `Connection`s of a `Context`, see
+/// `Config::set_capacity_hints`. The high-water marks can be used to tune the hints.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct CapacityStats {
+    /// The hinted number of `Connection`s.
+    pub connection_capacity: usize,
+    /// The hinted number of `Stream`s per `Connection`.
+    pub stream_capacity: usize,
+    /// The highest number of `Connection`s that were open at the same time.
+    pub connections_high_water: usize,
+    /// The highest number of `Stream`s that were open at the same time in one `Connection`.
+    pub streams_high_water: usize,
+    /// The number of `Connection`s that needed to allocate their map of `Stream`s, because all
+    /// maps that were allocated upfront were in use.
+    pub exhausted: usize,
+}
+
+/// A pool of the maps that store the `Stream`s of the `Connection`s.
+/// A `Connection` takes a map when it is created and returns it, when it is dropped.
+pub(crate) struct Pool {
+    stream_maps: Vec<StreamMap>,
+    stats: Arc<Mutex<CapacityStats>>,
+    /// The high-water marks of the `stats`, so the `stats` are only locked for a new maximum.
+    connections_high_water: Cell<usize>,
+    streams_high_water: Cell<usize>,
+}
+
+impl Pool {
+    /// Creates a new `Pool` with the given number of maps, where each map has the capacity for
+    /// the given number of `Stream`s.
+    pub fn new(connections: usize, streams: usize) -> PoolHandle {
+        let stats = CapacityStats {
+            connection_capacity: connections,
+            stream_capacity: streams,
+            ..Default::default()
+        };
+
+        Rc::new(RefCell::new(Pool {
+            stream_maps: (0..connections)
+                .map(|_| HashMap::with_capacity(streams))
+                .collect(),
+            stats: Arc::new(Mutex::new(stats)),
+            connections_high_water: Cell::new(0),
+            streams_high_water: Cell::new(0),
+        }))
+    }
+
+    /// Returns the statistics of this `Pool`, that can be read from any thread.
+    pub fn stats(&self) -> Arc<Mutex<CapacityStats>> {
+        self.stats.clone()
+    }
+
+    /// Takes a map that was allocated upfront. If all maps are in use, a new map is allocated.
+    pub fn take_stream_map(&mut self) -> StreamMap {
+        match self.stream_maps.pop() {
+            Some(map) => map,
+            None => {
+                let mut stats = self.stats.lock().unwrap();
+                stats.exhausted += 1;
+                HashMap::with_capacity(stats.stream_capacity)
+            }
+        }
+    }
+
+    /// Returns a map to the pool. The map is dropped, if the pool is already full.
+    pub fn put_stream_map(&mut self, mut map: StreamMap) {
+        map.clear();
+
+        if self.stream_maps.len() < self.stats.lock().unwrap().connection_capacity {
+            self.stream_maps.push(map);
+        }
+    }
+
+    /// Records the number of currently open `Connection`s.
+    pub fn record_connections(&self, connections: usize) {
+        if connections > self.connections_high_water.get() {
+            self.connections_high_water.set(connections);
+            self.stats.lock().unwrap().connections_high_water = connections;
+        }
+    }
+
+    /// Records the number of currently open `Stream`s of a `Connection`.
+    pub fn record_streams(&self, streams: usize) {
+        if streams > self.streams_high_water.get() {
+            self.streams_high_water.set(streams);
+            self.stats.lock().unwrap().streams_high_water = streams;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_are_reused_and_allocated_when_exhausted() {
+        let pool = Pool::new(1, 4);
+        let stats = pool.borrow().stats();
+
+        let first = pool.borrow_mut().take_stream_map();
+        assert!(first.capacity() >= 4);
+        assert_eq!(0, stats.lock().unwrap().exhausted);
+
+        let second = pool.borrow_mut().take_stream_map();
+        assert_eq!(1, stats.lock().unwrap().exhausted);
+
+        pool.borrow_mut().put_stream_map(first);
+        pool.borrow_mut().put_stream_map(second);
+        assert_eq!(1, pool.borrow().stream_maps.len());
+
+        pool.borrow_mut().take_stream_map();
+        assert_eq!(1, stats.lock().unwrap().exhausted);
+    }
+
+    #[test]
+    fn high_water_marks_keep_the_maximum() {
+        let pool = Pool::new(0, 0);
+
+        pool.borrow().record_connections(3);
+        pool.borrow().record_connections(1);
+        pool.borrow().record_streams(5);
+        pool.borrow().record_streams(2);
+
+        let stats = *pool.borrow().stats().lock().unwrap();
+        assert_eq!(3, stats.connections_high_water);
+        assert_eq!(5, stats.streams_high_water);
+    }
+}
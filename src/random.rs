@@ -0,0 +1,28 @@
+use std::sync::RwLock;
+
+pub(crate) type RandomSource = Box<Fn(&mut [u8]) + Send + Sync>;
+
+lazy_static! {
+    pub(crate) static ref RANDOM_SOURCE: RwLock<Option<RandomSource>> = RwLock::new(None);
+}
+
+/// Sets the source of randomness that is used by picoquic and picotls.
+///
+/// The source must fill the given buffer with cryptographically strong random bytes. Picotls
+/// does not support a source per `Context`, so the source is global and used by all `Context`s
+/// that are created after calling this function.
+///
+/// # Security
+///
+/// The random bytes are used for the TLS key exchange, the connection ids and the stateless
+/// reset tokens. A weak or predictable source breaks the confidentiality of all connections and
+/// makes it possible for an attacker to hijack or to reset connections.
+/// If the source panics, the process is aborted, as continuing without random bytes is unsafe.
+pub fn set_random_source<F: Fn(&mut [u8]) + Send + Sync + 'static>(source: F) {
+    *RANDOM_SOURCE.write().unwrap() = Some(Box::new(source));
+}
+
+/// Returns if a random source was set with `set_random_source`.
+pub(crate) fn is_random_source_set() -> bool {
+    RANDOM_SOURCE.read().unwrap().is_some()
+}
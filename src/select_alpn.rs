@@ -0,0 +1,19 @@
+/// The `SelectAlpn` trait is used by a server to select the ALPN protocol in the TLS handshake.
+pub trait SelectAlpn {
+    /// Will be called with the protocols that are offered by the client.
+    /// Protocols that are not valid UTF-8 are given as empty strings.
+    ///
+    /// # Result
+    ///
+    /// The index of the selected protocol or `None`, if none of the protocols should be selected.
+    fn select(&mut self, protocols: &[&str]) -> Option<usize>;
+}
+
+impl<F> SelectAlpn for F
+where
+    F: FnMut(&[&str]) -> Option<usize>,
+{
+    fn select(&mut self, protocols: &[&str]) -> Option<usize> {
+        self(protocols)
+    }
+}
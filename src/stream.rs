@@ -1,22 +1,50 @@
 use error::*;
 use ffi;
 use picoquic_sys::picoquic::{
-    self, picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_reset_stream,
-    picoquic_stop_sending,
+    self, picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_mark_active_stream,
+    picoquic_provide_stream_data_buffer, picoquic_reset_stream, picoquic_stop_sending,
 };
 
 use bytes::BytesMut;
 
+use failure::Fail;
+
 use futures::{
-    sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::AtomicTask,
     Async::{NotReady, Ready},
     Future, Poll, Sink, StartSend, Stream as FStream,
 };
 
-use std::{net::SocketAddr, ptr};
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{self, Read},
+    net::SocketAddr,
+    os::raw::{c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 pub type Id = u64;
 
+/// Produces the data of a `Stream` on demand, see `Connection::set_stream_data_provider`.
+pub(crate) type DataProvider = Box<FnMut(&mut [u8]) -> (usize, bool) + Send>;
+
+/// The maximum number of bytes of a reader, that are queued in picoquic and not yet sent.
+const READER_MAX_QUEUED_BYTES: usize = 64 * 1024;
+/// The number of bytes that are read at once from a reader.
+const READER_CHUNK_SIZE: usize = 16 * 1024;
+
 /// A `Message` is used by the `Stream` to propagate information from the peer or to send
 /// information to the peer.
 #[derive(Debug)]
@@ -26,17 +54,56 @@ enum Message {
     /// Send data.
     Data(BytesMut),
     Error(Error),
+    /// Finish the sending side of the `Stream` with a `FIN`.
+    Finish,
     /// Reset the `Stream`.
     Reset,
 }
 
 /// A `Stream` can either be unidirectional or bidirectional.
-#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Type {
     Unidirectional,
     Bidirectional,
 }
 
+/// The direction of the data on a `Stream`.
+/// Unidirectional `Stream`s can only be used for sending by the side that opened it, while they
+/// can only be used for receiving by the peer.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Direction {
+    /// Data can be sent and received.
+    Bidirectional,
+    /// Data can only be sent (a locally opened unidirectional `Stream`).
+    SendOnly,
+    /// Data can only be received (a unidirectional `Stream` opened by the peer).
+    ReceiveOnly,
+}
+
+impl Direction {
+    /// Returns the `Direction` of the `Stream` with the given id.
+    fn from_id(id: Id, is_client_con: bool) -> Direction {
+        if !is_unidirectional(id) {
+            Direction::Bidirectional
+        } else if is_client_initiated(id) == is_client_con {
+            Direction::SendOnly
+        } else {
+            Direction::ReceiveOnly
+        }
+    }
+}
+
+impl Type {
+    /// Returns the `Type` that is encoded in the given stream id.
+    pub(crate) fn from_id(id: Id) -> Type {
+        if is_unidirectional(id) {
+            Type::Unidirectional
+        } else {
+            Type::Bidirectional
+        }
+    }
+}
+
 /// A `Stream` is part of a `Connection`. A `Connection` can consists of multiple `Stream`s.
 /// Each `Stream` is a new channel over the `Connection` to the Peer. All traffic of a `Stream`
 /// is always unique for each `Stream`.
@@ -46,50 +113,80 @@ pub struct Stream {
     recv_msg: UnboundedReceiver<Message>,
     send_msg: UnboundedSender<Message>,
     id: Id,
-    peer_addr: SocketAddr,
-    local_addr: SocketAddr,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
     stream_reset: bool,
+    /// The number of received bytes that were not read yet.
+    buffered_bytes: Arc<AtomicUsize>,
+    /// Is notified, when received data was read.
+    read_task: Arc<AtomicTask>,
+    direction: Direction,
 }
 
 impl Stream {
     pub(crate) fn new(
         id: Id,
-        cnx: ffi::Connection,
-        local_addr: SocketAddr,
+        cnx: ffi::SharedConnection,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
         is_client_con: bool,
+        read_task: Arc<AtomicTask>,
+        given_bytes: Rc<Cell<u64>>,
     ) -> (Stream, Context) {
         let (recv_msg, recv_send) = unbounded();
         let (send_msg, send_recv) = unbounded();
 
-        let ctx = Context::new(recv_msg, send_recv, id, cnx, is_client_con);
+        let buffered_bytes = Arc::new(AtomicUsize::new(0));
+
+        let ctx = Context::new(
+            recv_msg,
+            send_recv,
+            id,
+            cnx,
+            is_client_con,
+            buffered_bytes.clone(),
+            given_bytes,
+        );
         let stream = Stream {
             recv_msg: recv_send,
             send_msg,
             id,
-            peer_addr: cnx.peer_addr(),
+            peer_addr,
             local_addr,
             stream_reset: false,
+            buffered_bytes,
+            read_task,
+            direction: Direction::from_id(id, is_client_con),
         };
 
         (stream, ctx)
     }
 
+    /// Returns the id of this `Stream`.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
     /// Returns the type of this `Stream`, either `Type::Unidirectional` or `Type::Bidirectional`.
     pub fn get_type(&self) -> Type {
-        if is_unidirectional(self.id) {
-            Type::Unidirectional
-        } else {
-            Type::Bidirectional
-        }
+        Type::from_id(self.id)
+    }
+
+    /// Returns the `Direction` of this `Stream`.
+    /// Sending data on a `Direction::ReceiveOnly` `Stream` or receiving data on a
+    /// `Direction::SendOnly` `Stream` fails with `ErrorKind::InvalidStreamDirection`.
+    pub fn direction(&self) -> Direction {
+        self.direction
     }
 
-    /// Returns the address of the `Connection`'s peer.
-    pub fn peer_addr(&self) -> SocketAddr {
+    /// Returns the address of the `Connection`'s peer, see `Connection::peer_addr`.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
         self.peer_addr
     }
 
-    /// Returns the address of the `Connection`'s local `Context`, where it is listening on.
-    pub fn local_addr(&self) -> SocketAddr {
+    /// Returns the address of the `Connection`'s local `Context`, where it is listening on, see
+    /// `Connection::local_addr`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
         self.local_addr
     }
 
@@ -98,6 +195,20 @@ impl Stream {
         let _ = self.send_msg.unbounded_send(Message::Reset);
     }
 
+    /// Finishes the sending side of this `Stream` with a `FIN`, after all data that was sent
+    /// before. This also works for a `Stream` that did not send any data, so the peer observes
+    /// a new `Stream` that ends immediately. Dropping such a `Stream` would reset it instead.
+    /// The `Stream` can still be used for receiving data from the peer.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.direction == Direction::ReceiveOnly {
+            return Err(ErrorKind::InvalidStreamDirection.into());
+        }
+
+        self.send_msg
+            .unbounded_send(Message::Finish)
+            .map_err(|_| ErrorKind::Unknown.into())
+    }
+
     /// Returns if this stream received a reset.
     pub fn is_reset(&self) -> bool {
         self.stream_reset
@@ -109,13 +220,21 @@ impl FStream for Stream {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.direction == Direction::SendOnly {
+            return Err(ErrorKind::InvalidStreamDirection.into());
+        }
+
         match try_ready!(
             self.recv_msg
                 .poll()
                 .map_err(|_| Error::from(ErrorKind::Unknown))
         ) {
-            Some(Message::Close) | None => Ok(Ready(None)),
-            Some(Message::Data(d)) => Ok(Ready(Some(d))),
+            Some(Message::Close) | Some(Message::Finish) | None => Ok(Ready(None)),
+            Some(Message::Data(d)) => {
+                self.buffered_bytes.fetch_sub(d.len(), Ordering::SeqCst);
+                self.read_task.notify();
+                Ok(Ready(Some(d)))
+            }
             Some(Message::Error(err)) => Err(err),
             Some(Message::Reset) => {
                 self.stream_reset = true;
@@ -137,6 +256,10 @@ impl Sink for Stream {
             }
         }
 
+        if self.direction == Direction::ReceiveOnly {
+            return Err(ErrorKind::InvalidStreamDirection.into());
+        }
+
         self.send_msg
             .start_send(Message::Data(item))
             .map_err(|e| ErrorKind::SendError(extract_data(e.into_inner())).into())
@@ -161,12 +284,48 @@ pub(crate) struct Context {
     send_msg: UnboundedReceiver<Message>,
     id: Id,
     finished: bool,
-    cnx: ffi::Connection,
+    /// Did the peer finish its sending side of this `Stream` with a `FIN`?
+    fin_received: bool,
+    cnx: ffi::SharedConnection,
     /// Is the connection this Stream belongs to, a client connection?
     is_client_con: bool,
     /// Did this stream send any data?
     data_send: bool,
     stop_sending: bool,
+    /// The number of received bytes that were not read yet by the `Stream`.
+    buffered_bytes: Arc<AtomicUsize>,
+    /// The number of bytes that were given to picoquic on all `Stream`s of the connection.
+    given_bytes: Rc<Cell<u64>>,
+    /// Were the bytes that are queued in picoquic removed from `given_bytes`?
+    queued_bytes_discarded: bool,
+    /// Is the delivery of received messages to the `Stream` paused?
+    paused: bool,
+    /// The received messages that are buffered while the `Stream` is paused.
+    paused_msgs: VecDeque<Message>,
+    /// Is the peer blocked by the flow control of this `Stream`?
+    peer_blocked: bool,
+    /// Is the sending side of this `Stream` finished (by sending a `FIN` or a reset)?
+    send_finished: bool,
+    /// The reader that is currently sent on this `Stream`.
+    reader: Option<ReaderSend>,
+    /// The provider that is asked for the data of this `Stream`, when picoquic is ready to send.
+    data_provider: Option<DataProvider>,
+    /// Did the data provider have no data, the last time it was asked? Then the `Stream` is not
+    /// active in picoquic, until the next poll of this `Context`.
+    data_provider_idle: bool,
+    /// Should this `Stream` be closed, after the reader or the data provider finished?
+    close_after_send: bool,
+}
+
+/// A reader that is sent on a `Stream`, see `Connection::send_stream_from_reader`.
+struct ReaderSend {
+    reader: Box<Read + Send>,
+    /// The point in time, until all data of the reader needs to be sent.
+    deadline: Option<Instant>,
+    /// Was all data read from the reader?
+    eof: bool,
+    /// Is notified, when all data of the reader is sent or an error occurred.
+    done: oneshot::Sender<Result<(), Error>>,
 }
 
 impl Context {
@@ -174,8 +333,10 @@ impl Context {
         recv_msg: UnboundedSender<Message>,
         mut send_msg: UnboundedReceiver<Message>,
         id: Id,
-        cnx: ffi::Connection,
+        cnx: ffi::SharedConnection,
         is_client_con: bool,
+        buffered_bytes: Arc<AtomicUsize>,
+        given_bytes: Rc<Cell<u64>>,
     ) -> Context {
         // We need to poll this once, so the current `Task` is registered to be woken up, when
         // new data should be send.
@@ -186,17 +347,32 @@ impl Context {
             send_msg,
             id,
             finished: false,
+            fin_received: false,
             cnx,
             is_client_con,
             data_send: false,
             stop_sending: false,
+            buffered_bytes,
+            given_bytes,
+            queued_bytes_discarded: false,
+            paused: false,
+            paused_msgs: VecDeque::new(),
+            peer_blocked: false,
+            send_finished: false,
+            reader: None,
+            data_provider: None,
+            data_provider_idle: false,
+            close_after_send: false,
         }
     }
 
     fn reset(&mut self) {
         self.finished = true;
+        self.send_finished = true;
+        self.data_provider = None;
+        self.discard_queued_bytes();
         unsafe {
-            picoquic_reset_stream(self.cnx.as_ptr(), self.id, 0);
+            picoquic_reset_stream(self.cnx.get().as_ptr(), self.id, 0);
         }
     }
 
@@ -205,79 +381,422 @@ impl Context {
             if self.finished {
                 error!("stream({}) received data after being finished!", self.id);
             } else {
-                let data = BytesMut::from(data);
-
-                let _ = self.recv_msg.unbounded_send(Message::Data(data));
+                self.forward_msg(Message::Data(BytesMut::from(data)));
             }
         }
 
         if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_reset {
             self.finished = true;
-            let _ = self.recv_msg.unbounded_send(Message::Reset);
+            self.forward_msg(Message::Reset);
         } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stop_sending {
+            self.discard_queued_bytes();
             self.stop_sending = true;
             self.send_msg.close();
         } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_fin {
-            let _ = self.recv_msg.unbounded_send(Message::Close);
+            self.forward_msg(Message::Close);
             self.finished = true;
+            self.fin_received = true;
         }
     }
 
+    /// Forwards the given message to the `Stream` or buffers it, if the `Stream` is paused.
+    fn forward_msg(&mut self, msg: Message) {
+        if self.paused {
+            self.paused_msgs.push_back(msg);
+            return;
+        }
+
+        // Increase the counter before sending, the `Stream` decreases it on receive.
+        if let Message::Data(ref data) = msg {
+            self.buffered_bytes.fetch_add(data.len(), Ordering::SeqCst);
+        }
+
+        if let Err(e) = self.recv_msg.unbounded_send(msg) {
+            if let Message::Data(data) = e.into_inner() {
+                self.buffered_bytes.fetch_sub(data.len(), Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Pauses the delivery of received messages to the `Stream`.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Is the delivery of received messages to the `Stream` paused?
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Resumes the delivery of received messages to the `Stream`.
+    /// All messages that were buffered while being paused, are delivered.
+    pub fn resume(&mut self) {
+        self.paused = false;
+
+        while let Some(msg) = self.paused_msgs.pop_front() {
+            self.forward_msg(msg);
+        }
+    }
+
+    /// Checks if the peer is blocked by the flow control of this `Stream`.
+    ///
+    /// # Returns
+    /// `true`, if the peer was not blocked at the last call of this function.
+    pub fn check_peer_blocked(&mut self) -> bool {
+        // The peer can not send on our outgoing unidirectional streams.
+        if self.finished || (is_unidirectional(self.id) && self.is_unidirectional_send_allowed()) {
+            return false;
+        }
+
+        let blocked = self.cnx.get().is_peer_stream_data_blocked(self.id);
+        let newly_blocked = blocked && !self.peer_blocked;
+        self.peer_blocked = blocked;
+        newly_blocked
+    }
+
+    /// Returns the number of received bytes that were not read yet by the `Stream`.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Did the peer finish its sending side of this `Stream` with a `FIN`?
+    pub fn is_fin_received(&self) -> bool {
+        self.fin_received
+    }
+
+    /// Did the peer send a `FIN` and was all data up to the `FIN` read by the `Stream`?
+    pub fn is_all_received(&self) -> bool {
+        self.fin_received && self.unread_bytes() == 0
+    }
+
+    /// Is there data from a reader or a data provider, that was not given to picoquic yet?
+    pub fn has_pending_send_data(&self) -> bool {
+        self.reader.is_some() || self.data_provider.is_some()
+    }
+
+    /// Returns the number of received bytes that were not read yet, including the bytes that
+    /// are buffered while the `Stream` is paused.
+    pub fn unread_bytes(&self) -> usize {
+        let paused = self
+            .paused_msgs
+            .iter()
+            .map(|m| match *m {
+                Message::Data(ref data) => data.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+
+        self.buffered_bytes() + paused
+    }
+
+    /// Resets this `Stream` with the given error code. The sending side is reset with
+    /// `RESET_STREAM` and the receiving side is aborted with `STOP_SENDING`, as far as the
+    /// direction of this `Stream` allows it. The `Stream` is notified about the reset.
+    pub fn reset_with_error_code(&mut self, error_code: u64) {
+        if let Some(reader) = self.reader.take() {
+            let _ = reader
+                .done
+                .send(Err(ErrorKind::SendError(BytesMut::new()).into()));
+        }
+
+        let send_allowed = !is_unidirectional(self.id) || self.is_unidirectional_send_allowed();
+
+        unsafe {
+            if send_allowed {
+                self.discard_queued_bytes();
+                picoquic_reset_stream(self.cnx.get().as_ptr(), self.id, error_code);
+            }
+
+            if !is_unidirectional(self.id) || !send_allowed {
+                picoquic_stop_sending(self.cnx.get().as_ptr(), self.id, error_code);
+            }
+        }
+
+        self.finished = true;
+        self.send_finished = true;
+        self.stop_sending = true;
+        self.data_provider = None;
+        self.close_after_send = false;
+        self.send_msg.close();
+
+        // Deliver all buffered messages, before the `Stream` is shut down.
+        self.resume();
+        let _ = self.recv_msg.unbounded_send(Message::Reset);
+    }
+
     /// Handle a connection error.
-    pub fn handle_connection_error(&mut self, err: Error) {
-        let _ = self.recv_msg.unbounded_send(Message::Error(err));
+    pub fn handle_connection_error(&mut self, err: &Fn() -> Error) {
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.done.send(Err(err()));
+        }
+        self.data_provider = None;
+
+        // Deliver all buffered messages, before the `Stream` is shut down.
+        self.resume();
+        let _ = self.recv_msg.unbounded_send(Message::Error(err()));
     }
 
     /// Handle connection close.
     pub fn handle_connection_close(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.done.send(Err(ErrorKind::Disconnected.into()));
+        }
+        self.data_provider = None;
+
+        // Deliver all buffered messages, before the `Stream` is shut down.
+        self.resume();
         let _ = self.recv_msg.unbounded_send(Message::Close);
     }
 
-    fn send_data(&mut self, data: &BytesMut) {
+    /// Starts sending all data of the given reader on this `Stream`, followed by a `FIN`.
+    /// The result is propagated to `done`.
+    pub fn send_from_reader(
+        &mut self,
+        reader: Box<Read + Send>,
+        deadline: Option<Instant>,
+        done: oneshot::Sender<Result<(), Error>>,
+    ) {
+        if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
+            let _ = done.send(Err(ErrorKind::InvalidStreamDirection.into()));
+        } else if self.stop_sending
+            || self.send_finished
+            || self.reader.is_some()
+            || self.data_provider.is_some()
+        {
+            let _ = done.send(Err(ErrorKind::SendError(BytesMut::new()).into()));
+        } else {
+            self.reader = Some(ReaderSend {
+                reader,
+                deadline,
+                eof: false,
+                done,
+            });
+            self.send_reader_data();
+        }
+    }
+
+    /// Sends the data of the reader, until `READER_MAX_QUEUED_BYTES` are queued in picoquic.
+    /// The remaining data is sent in a later call, after picoquic sent the queued data. So, the
+    /// reader is only read as fast as the flow and congestion control allows.
+    /// When all data is read and sent by picoquic, `done` is notified.
+    fn send_reader_data(&mut self) {
+        let mut reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return,
+        };
+
+        if self.stop_sending {
+            let _ = reader
+                .done
+                .send(Err(ErrorKind::SendError(BytesMut::new()).into()));
+            return;
+        }
+
+        if reader
+            .deadline
+            .map(|d| d <= Instant::now())
+            .unwrap_or(false)
+        {
+            // The peer should not assume that it received all data.
+            self.reset();
+            let _ = reader.done.send(Err(ErrorKind::Timeout.into()));
+            return;
+        }
+
+        let mut buf = vec![0; READER_CHUNK_SIZE];
+
+        while !reader.eof && self.cnx.get().queued_stream_bytes(self.id) < READER_MAX_QUEUED_BYTES {
+            match reader.reader.read(&mut buf) {
+                Ok(0) => {
+                    self.send_fin();
+                    reader.eof = true;
+                }
+                Ok(len) => self.send_data(&buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    // The peer should not assume that it received all data.
+                    self.reset();
+                    let _ = reader
+                        .done
+                        .send(Err(e.context(ErrorKind::ReaderError).into()));
+                    return;
+                }
+            }
+        }
+
+        // All data is sent, when picoquic does not have any queued data left.
+        if reader.eof && self.cnx.get().queued_stream_bytes(self.id) == 0 {
+            let _ = reader.done.send(Ok(()));
+        } else {
+            self.reader = Some(reader);
+        }
+    }
+
+    /// Sets the provider that is asked for the data of this `Stream`, when picoquic is ready to
+    /// send data on it.
+    pub fn set_data_provider(&mut self, provider: DataProvider) {
+        if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
+            error!("tried to set a data provider for an incoming unidirectional stream!");
+        } else if !self.stop_sending && !self.send_finished && self.reader.is_none() {
+            self.data_provider = Some(provider);
+            self.data_provider_idle = false;
+            unsafe {
+                picoquic_mark_active_stream(self.cnx.get().as_ptr(), self.id, 1, ptr::null_mut());
+            }
+        }
+    }
+
+    /// Asks the data provider for at most `space` bytes and hands them over to picoquic.
+    /// `context` is the context of the `prepare_to_send` callback of picoquic.
+    pub fn provide_data(&mut self, context: *mut c_void, space: usize) {
+        let mut buf = vec![0; space];
+
+        let result = match self.data_provider.as_mut() {
+            // A panic must not unwind into the c code.
+            Some(provider) => panic::catch_unwind(AssertUnwindSafe(|| provider(&mut buf))),
+            None => {
+                provide_no_data(context);
+                return;
+            }
+        };
+
+        match result {
+            Ok((0, false)) => {
+                // The `Stream` is deactivated, otherwise picoquic would ask for data again and
+                // again, without waiting for anything. It is activated again on the next poll.
+                provide_no_data(context);
+                self.data_provider_idle = true;
+            }
+            Ok((len, fin)) => {
+                let len = len.min(space);
+
+                unsafe {
+                    let data = picoquic_provide_stream_data_buffer(
+                        context,
+                        len,
+                        fin as c_int,
+                        !fin as c_int,
+                    );
+
+                    if !data.is_null() {
+                        ptr::copy_nonoverlapping(buf.as_ptr(), data, len);
+                    }
+                }
+
+                self.data_send = self.data_send || len > 0;
+                self.given_bytes.set(self.given_bytes.get() + len as u64);
+
+                if fin {
+                    self.send_finished = true;
+                    self.data_provider = None;
+                }
+            }
+            Err(_) => {
+                error!("stream data provider panicked!");
+                provide_no_data(context);
+                // The peer should not assume that it received all data.
+                self.reset();
+            }
+        }
+    }
+
+    /// Activates the `Stream` in picoquic again, if the data provider had no data the last time
+    /// it was asked.
+    fn resume_data_provider(&mut self) {
+        if self.data_provider_idle && self.data_provider.is_some() {
+            self.data_provider_idle = false;
+            unsafe {
+                picoquic_mark_active_stream(self.cnx.get().as_ptr(), self.id, 1, ptr::null_mut());
+            }
+        }
+    }
+
+    fn send_fin(&mut self) {
+        self.send_finished = true;
+        unsafe {
+            picoquic_add_to_stream(self.cnx.get().as_ptr(), self.id, ptr::null(), 0, 1);
+        }
+    }
+
+    /// Finishes the sending side of this `Stream` with a `FIN`, even if no data was sent.
+    fn finish(&mut self) {
+        if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
+            error!("tried to finish incoming unidirectional stream!");
+        } else if self.has_pending_send_data() {
+            error!(
+                "stream({}) can not be finished while sending from a reader or a data provider!",
+                self.id
+            );
+        } else if !self.stop_sending && !self.send_finished {
+            self.send_fin();
+        }
+    }
+
+    fn send_data(&mut self, data: &[u8]) {
         if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
-            //TODO: maybe we should do more than just printing
             error!("tried to send data to incoming unidirectional stream!");
-        } else if !self.stop_sending {
+        } else if !self.stop_sending && !self.send_finished {
             self.data_send = self.data_send || !data.is_empty();
+            self.given_bytes
+                .set(self.given_bytes.get() + data.len() as u64);
             unsafe {
                 // TODO handle the result
-                picoquic_add_to_stream(self.cnx.as_ptr(), self.id, data.as_ptr(), data.len(), 0);
+                picoquic_add_to_stream(
+                    self.cnx.get().as_ptr(),
+                    self.id,
+                    data.as_ptr(),
+                    data.len(),
+                    0,
+                );
             }
         }
     }
 
+    /// Removes the bytes that are still queued in picoquic from `given_bytes`, because picoquic
+    /// will not send them after this `Stream` is reset.
+    fn discard_queued_bytes(&mut self) {
+        if self.queued_bytes_discarded {
+            return;
+        }
+
+        self.queued_bytes_discarded = true;
+        let queued = self.cnx.get().queued_stream_bytes(self.id) as u64;
+        self.given_bytes
+            .set(self.given_bytes.get().saturating_sub(queued));
+    }
+
     fn close(&mut self) {
         self.finished = true;
         self.stop_sending = true;
         self.send_msg.close();
 
-        if self.data_send {
-            unsafe {
-                picoquic_add_to_stream(self.cnx.as_ptr(), self.id, ptr::null(), 0, 1);
+        if !self.send_finished {
+            if self.data_send {
+                self.send_fin();
+            } else {
+                self.reset();
             }
-        } else {
-            self.reset();
         }
 
         if !is_unidirectional(self.id) || !self.is_unidirectional_send_allowed() {
             unsafe {
-                picoquic_stop_sending(self.cnx.as_ptr(), self.id, 0);
+                picoquic_stop_sending(self.cnx.get().as_ptr(), self.id, 0);
             }
         }
     }
 
     /// Returns if this Stream is the sending side of an unidirectional Stream.
     fn is_unidirectional_send_allowed(&self) -> bool {
-        if self.is_client_initiated() {
-            self.is_client_con
-        } else {
-            !self.is_client_con
-        }
+        is_client_initiated(self.id) == self.is_client_con
     }
+}
 
-    /// Is the Stream initiated by the client?
-    fn is_client_initiated(&self) -> bool {
-        self.id & 1 == 0
+/// Tells picoquic that there is no data to send in the `prepare_to_send` callback with the given
+/// `context` and that the `Stream` is not active anymore.
+pub(crate) fn provide_no_data(context: *mut c_void) {
+    unsafe {
+        picoquic_provide_stream_data_buffer(context, 0, 0, 0);
     }
 }
 
@@ -285,17 +804,41 @@ fn is_unidirectional(id: Id) -> bool {
     id & 2 != 0
 }
 
+/// Is the Stream initiated by the client?
+pub(crate) fn is_client_initiated(id: Id) -> bool {
+    id & 1 == 0
+}
+
 impl Future for Context {
     type Item = ();
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.send_reader_data();
+        self.resume_data_provider();
+
+        if self.close_after_send && self.reader.is_none() && self.data_provider.is_none() {
+            self.close();
+            return Ok(Ready(()));
+        }
+
         loop {
             match try_ready!(self.send_msg.poll()) {
                 Some(Message::Reset) => {
+                    if let Some(reader) = self.reader.take() {
+                        let _ = reader
+                            .done
+                            .send(Err(ErrorKind::SendError(BytesMut::new()).into()));
+                    }
+
                     self.reset();
                     return Ok(Ready(()));
                 }
+                // The `Stream` is closed after all data of the reader or the provider is sent.
+                Some(Message::Close) if self.reader.is_some() || self.data_provider.is_some() => {
+                    self.close_after_send = true;
+                    return Ok(NotReady);
+                }
                 Some(Message::Close) => {
                     self.close();
                     return Ok(Ready(()));
@@ -303,6 +846,7 @@ impl Future for Context {
                 Some(Message::Data(data)) => {
                     self.send_data(&data);
                 }
+                Some(Message::Finish) => self.finish(),
                 Some(Message::Error(_)) => {}
                 None => {
                     if self.finished && self.stop_sending {
@@ -315,3 +859,108 @@ impl Future for Context {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+
+    /// Returns a `SharedConnection` that must never be used.
+    fn dummy_connection() -> ffi::SharedConnection {
+        let cnx = unsafe { ffi::Connection::from_raw(ptr::null_mut()) };
+        ffi::SharedConnection::new(Rc::new(ffi::QuicCtx::dummy()), cnx)
+    }
+
+    #[test]
+    fn type_is_encoded_in_stream_id() {
+        assert_eq!(Type::Bidirectional, Type::from_id(0));
+        assert_eq!(Type::Bidirectional, Type::from_id(5));
+        assert_eq!(Type::Unidirectional, Type::from_id(2));
+        assert_eq!(Type::Unidirectional, Type::from_id(7));
+    }
+
+    #[test]
+    fn unidirectional_stream_direction_depends_on_initiator() {
+        assert_eq!(Direction::Bidirectional, Direction::from_id(0, true));
+        assert_eq!(Direction::Bidirectional, Direction::from_id(1, false));
+        assert_eq!(Direction::SendOnly, Direction::from_id(2, true));
+        assert_eq!(Direction::ReceiveOnly, Direction::from_id(2, false));
+        assert_eq!(Direction::ReceiveOnly, Direction::from_id(3, true));
+        assert_eq!(Direction::SendOnly, Direction::from_id(3, false));
+    }
+
+    #[test]
+    fn paused_stream_buffers_messages_until_resumed() {
+        future::lazy(|| {
+            let (recv_msg, recv_send) = unbounded();
+            let (_send_msg, send_recv) = unbounded();
+            let buffered_bytes = Arc::new(AtomicUsize::new(0));
+
+            let mut ctx = Context::new(
+                recv_msg,
+                send_recv,
+                4,
+                dummy_connection(),
+                true,
+                buffered_bytes.clone(),
+                Rc::new(Cell::new(0)),
+            );
+
+            ctx.pause();
+            ctx.forward_msg(Message::Data(BytesMut::from(&b"hello"[..])));
+            assert_eq!(0, buffered_bytes.load(Ordering::SeqCst));
+
+            ctx.resume();
+            assert_eq!(5, buffered_bytes.load(Ordering::SeqCst));
+
+            drop(ctx);
+            let msgs = recv_send.wait().collect::<Vec<_>>();
+            assert_eq!(1, msgs.len());
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn all_received_after_fin_and_buffered_data_was_read() {
+        future::lazy(|| {
+            let (recv_msg, _recv_send) = unbounded();
+            let (_send_msg, send_recv) = unbounded();
+            let buffered_bytes = Arc::new(AtomicUsize::new(0));
+
+            let mut ctx = Context::new(
+                recv_msg,
+                send_recv,
+                4,
+                dummy_connection(),
+                true,
+                buffered_bytes.clone(),
+                Rc::new(Cell::new(0)),
+            );
+
+            ctx.recv_data(
+                b"hello",
+                picoquic::picoquic_call_back_event_t_picoquic_callback_no_event,
+            );
+            assert!(!ctx.is_fin_received());
+
+            ctx.recv_data(
+                &[],
+                picoquic::picoquic_call_back_event_t_picoquic_callback_stream_fin,
+            );
+            assert!(ctx.is_fin_received());
+            assert!(!ctx.is_all_received());
+
+            // The `Stream` reads the buffered data.
+            buffered_bytes.store(0, Ordering::SeqCst);
+            assert!(ctx.is_all_received());
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
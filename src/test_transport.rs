@@ -0,0 +1,441 @@
+//! An in-memory transport for testing.
+//!
+//! Instead of sending the packets of a `Context` over an `UdpSocket`, the packets are sent over
+//! a `Network` that lives in memory. This makes it possible to run client and server without
+//! any UDP sockets and to simulate the properties of a real link with a `LinkModel`.
+//!
+//! A `Context` that uses the in-memory transport is created with
+//! `Context::new_with_test_transport`.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_core::reactor::{Handle, Timeout};
+
+use futures::{task, Async, Future};
+
+/// A packet that is in transit to an `Endpoint`.
+struct Packet {
+    deliver_at: Instant,
+    from: SocketAddr,
+    data: Vec<u8>,
+    /// The ECN codepoint the packet is delivered with.
+    ecn: u8,
+}
+
+/// An `Endpoint` is the receiving side of a `Socket` in the `Network`.
+#[derive(Default)]
+struct Endpoint {
+    /// The packets in transit, ordered by their delivery time.
+    packets: VecDeque<Packet>,
+    /// The task that waits for new packets.
+    task: Option<task::Task>,
+    /// The point in time, when the link of this `Endpoint` finished sending all packets.
+    /// Only used, if the `LinkModel` limits the bandwidth.
+    send_free_at: Option<Instant>,
+    /// Is the `Socket` of this `Endpoint` blocked from sending, see `Network::block_sending`?
+    send_blocked: bool,
+    /// The task that waits for the `Socket` of this `Endpoint` to become writable.
+    write_task: Option<task::Task>,
+}
+
+/// The properties of the link between all `Context`s of a `Network`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkModel {
+    /// The rate of packets that are dropped. Needs to be between `0.0` (no packet is dropped)
+    /// and `1.0` (all packets are dropped).
+    pub loss_rate: f64,
+    /// The round trip time of the link. Each packet is delayed by the half of it.
+    pub rtt: Duration,
+    /// The maximum additional random delay of a packet. As each packet gets a different delay,
+    /// packets can be reordered.
+    pub jitter: Duration,
+    /// The bandwidth in bytes per second that each `Context` can send with.
+    /// `None` means unlimited bandwidth.
+    /// Like the send buffer of an `UdpSocket`, the `Socket` of a `Context` is not writable, while
+    /// the sent packets need more than `MAX_SEND_QUEUE_DELAY` to leave the link.
+    pub bandwidth: Option<u64>,
+    /// The rate of packets that are marked as congestion experienced (CE), like a router with a
+    /// full queue does. Needs to be between `0.0` and `1.0`. All other packets are delivered as
+    /// not ECN capable, see `Config::enable_ecn_marks`.
+    pub ce_mark_rate: f64,
+}
+
+impl Default for LinkModel {
+    fn default() -> LinkModel {
+        LinkModel {
+            loss_rate: 0.0,
+            rtt: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            bandwidth: None,
+            ce_mark_rate: 0.0,
+        }
+    }
+}
+
+struct Inner {
+    endpoints: HashMap<SocketAddr, Endpoint>,
+    /// The addresses of `Endpoint`s that are rebound, mapped to the address that is seen by the
+    /// peers.
+    rebindings: HashMap<SocketAddr, SocketAddr>,
+    next_port: u16,
+    link: LinkModel,
+    rng: XorShift,
+}
+
+impl Inner {
+    /// Returns the `Endpoint` for the given address.
+    /// If no `Endpoint` is bound to the exact address, an `Endpoint` that is bound to an
+    /// unspecified address with the same port is returned. An address that an `Endpoint` was
+    /// rebound to, returns this `Endpoint`.
+    fn endpoint_mut(&mut self, addr: &SocketAddr) -> Option<&mut Endpoint> {
+        let addr = match self.rebindings.iter().find(|(_, a)| *a == addr) {
+            Some((local, _)) => *local,
+            None => *addr,
+        };
+
+        let addr = if self.endpoints.contains_key(&addr) {
+            addr
+        } else {
+            match self
+                .endpoints
+                .keys()
+                .find(|a| a.ip().is_unspecified() && a.port() == addr.port())
+            {
+                Some(a) => *a,
+                None => return None,
+            }
+        };
+
+        self.endpoints.get_mut(&addr)
+    }
+
+    /// Returns the point in time, when a packet with the given length that is sent now by the
+    /// `Endpoint` with the given address, arrives at its destination.
+    fn delivery_time(&mut self, from: &SocketAddr, len: usize) -> Instant {
+        let now = Instant::now();
+        let mut deliver_at = now + self.link.rtt / 2;
+
+        if self.link.jitter > Duration::from_millis(0) {
+            deliver_at += duration_from_secs_f64(
+                duration_as_secs_f64(self.link.jitter) * self.rng.next_f64(),
+            );
+        }
+
+        if let Some(bandwidth) = self.link.bandwidth {
+            let send_time = duration_from_secs_f64(len as f64 / bandwidth.max(1) as f64);
+
+            if let Some(endpoint) = self.endpoints.get_mut(from) {
+                let send_finished = endpoint.send_free_at.map_or(now, |t| t.max(now)) + send_time;
+                endpoint.send_free_at = Some(send_finished);
+                deliver_at += send_finished - now;
+            }
+        }
+
+        deliver_at
+    }
+}
+
+/// An in-memory network that connects `Context`s without using UDP sockets.
+/// All `Context`s that should communicate with each other, need to use the same `Network`.
+#[derive(Clone)]
+pub struct Network {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Network {
+    /// Creates a new `Network` with a perfect link (no delay and no loss).
+    pub fn new() -> Network {
+        Network::with_link_model(LinkModel::default())
+    }
+
+    /// Creates a new `Network` that simulates the given `LinkModel`.
+    pub fn with_link_model(link: LinkModel) -> Network {
+        check_link_model(&link);
+
+        Network {
+            inner: Arc::new(Mutex::new(Inner {
+                endpoints: HashMap::new(),
+                rebindings: HashMap::new(),
+                next_port: 40000,
+                link,
+                rng: XorShift::new(DEFAULT_SEED),
+            })),
+        }
+    }
+
+    /// Sets the `LinkModel` that is simulated by this `Network`.
+    /// The new `LinkModel` is applied to all packets that are sent after calling this function.
+    pub fn set_link_model(&self, link: LinkModel) {
+        check_link_model(&link);
+        self.inner.lock().unwrap().link = link;
+    }
+
+    /// Sets the seed of the pseudo random generator that decides which packets are dropped and
+    /// how much jitter is added. With the same seed, the same packets are dropped in each run.
+    pub fn set_seed(&self, seed: u64) {
+        self.inner.lock().unwrap().rng = XorShift::new(seed);
+    }
+
+    /// Rebinds the `Socket` with the given address to the new address, like a NAT that assigns a
+    /// new public address. The packets of the `Socket` are sent from the new address and packets
+    /// to the new address are received by the `Socket`. The peers see this as a migration of
+    /// the `Connection`s of the `Socket`'s `Context`.
+    pub fn rebind(&self, addr: SocketAddr, new_addr: SocketAddr) {
+        self.inner.lock().unwrap().rebindings.insert(addr, new_addr);
+    }
+
+    /// Blocks the `Socket` with the given address from sending, as if its send buffer was full.
+    /// The `Context` of the `Socket` keeps the datagrams for this `Socket` queued, until it is
+    /// unblocked again by calling this function with `blocked` set to `false`.
+    pub fn block_sending(&self, addr: SocketAddr, blocked: bool) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(endpoint) = inner.endpoints.get_mut(&addr) {
+            endpoint.send_blocked = blocked;
+
+            if !blocked {
+                if let Some(task) = endpoint.write_task.take() {
+                    task.notify();
+                }
+            }
+        }
+    }
+
+    /// Binds a new `Socket` to the given address.
+    /// If the port of the address is `0`, a free port is chosen.
+    pub(crate) fn bind(&self, addr: &SocketAddr, handle: &Handle) -> io::Result<Socket> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut addr = *addr;
+
+        if addr.port() == 0 {
+            while inner.endpoints.keys().any(|a| a.port() == inner.next_port) {
+                inner.next_port = inner.next_port.wrapping_add(1).max(1);
+            }
+
+            addr.set_port(inner.next_port);
+            inner.next_port = inner.next_port.wrapping_add(1).max(1);
+        }
+
+        if inner.endpoints.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+
+        inner.endpoints.insert(addr, Endpoint::default());
+
+        Ok(Socket {
+            inner: self.inner.clone(),
+            local_addr: addr,
+            timer: Timeout::new(Duration::from_secs(0), handle)?,
+            write_timer: RefCell::new(Timeout::new(Duration::from_secs(0), handle)?),
+        })
+    }
+}
+
+impl Default for Network {
+    fn default() -> Network {
+        Network::new()
+    }
+}
+
+/// A `Socket` that sends and receives packets over a `Network`.
+/// The interface mirrors the parts of `tokio_core::net::UdpSocket` that are used by the
+/// `Context`.
+pub(crate) struct Socket {
+    inner: Arc<Mutex<Inner>>,
+    local_addr: SocketAddr,
+    /// Wakes up the current task, when the next packet should be delivered.
+    timer: Timeout,
+    /// Wakes up the current task, when the link has room for new packets.
+    write_timer: RefCell<Timeout>,
+}
+
+impl Socket {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    pub fn poll_write(&self) -> Async<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let endpoint = inner
+            .endpoints
+            .get_mut(&self.local_addr)
+            .expect("endpoint exists while the socket is alive");
+
+        if endpoint.send_blocked {
+            endpoint.write_task = Some(task::current());
+            return Async::NotReady;
+        }
+
+        match endpoint.send_free_at {
+            Some(free_at) if free_at > Instant::now() + MAX_SEND_QUEUE_DELAY => {
+                // Poll the timer once, to register the current task to be woken up when the link
+                // has room again.
+                let mut timer = self.write_timer.borrow_mut();
+                timer.reset(free_at - MAX_SEND_QUEUE_DELAY);
+                let _ = timer.poll();
+
+                Async::NotReady
+            }
+            _ => Async::Ready(()),
+        }
+    }
+
+    pub fn send_to(&self, buf: &[u8], target: &SocketAddr) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(true) = inner
+            .endpoints
+            .get(&self.local_addr)
+            .map(|e| e.send_blocked)
+        {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let loss_rate = inner.link.loss_rate;
+        if loss_rate > 0.0 && inner.rng.next_f64() < loss_rate {
+            return Ok(buf.len());
+        }
+
+        let ce_mark_rate = inner.link.ce_mark_rate;
+        let ecn = if ce_mark_rate > 0.0 && inner.rng.next_f64() < ce_mark_rate {
+            ECN_CE
+        } else {
+            ECN_NOT_ECT
+        };
+
+        let deliver_at = inner.delivery_time(&self.local_addr, buf.len());
+        let from = inner
+            .rebindings
+            .get(&self.local_addr)
+            .cloned()
+            .unwrap_or(self.local_addr);
+
+        if let Some(endpoint) = inner.endpoint_mut(target) {
+            let pos = endpoint
+                .packets
+                .iter()
+                .position(|p| p.deliver_at > deliver_at)
+                .unwrap_or(endpoint.packets.len());
+
+            endpoint.packets.insert(
+                pos,
+                Packet {
+                    deliver_at,
+                    from,
+                    data: buf.to_vec(),
+                    ecn,
+                },
+            );
+
+            if let Some(task) = endpoint.task.take() {
+                task.notify();
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Receives a packet and returns its length, the address of the sender and the ECN codepoint
+    /// of the packet.
+    pub(crate) fn recv_from_with_ecn(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, u8)> {
+        let deliver_at = {
+            let mut inner = self.inner.lock().unwrap();
+            let endpoint = inner
+                .endpoints
+                .get_mut(&self.local_addr)
+                .expect("endpoint exists while the socket is alive");
+
+            endpoint.task = Some(task::current());
+
+            let deliver_at = match endpoint.packets.front() {
+                Some(packet) => packet.deliver_at,
+                None => return Err(io::ErrorKind::WouldBlock.into()),
+            };
+
+            if deliver_at <= Instant::now() {
+                let packet = endpoint.packets.pop_front().unwrap();
+                let len = packet.data.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet.data[..len]);
+                return Ok((len, packet.from, packet.ecn));
+            }
+
+            deliver_at
+        };
+
+        // Poll the timer once, to register the current task to be woken up when the next
+        // packet should be delivered.
+        self.timer.reset(deliver_at);
+        let _ = self.timer.poll();
+
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.endpoints.remove(&self.local_addr);
+            inner.rebindings.remove(&self.local_addr);
+        }
+    }
+}
+
+/// The maximum time the packets of a `Socket` wait for a bandwidth limited link, before the
+/// `Socket` is not writable anymore.
+const MAX_SEND_QUEUE_DELAY: Duration = Duration::from_millis(10);
+
+const DEFAULT_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+/// The ECN codepoint of a packet that is not ECN capable.
+const ECN_NOT_ECT: u8 = 0x00;
+/// The ECN codepoint of a packet that was marked as congestion experienced.
+const ECN_CE: u8 = 0x03;
+
+fn check_link_model(link: &LinkModel) {
+    assert!(
+        (0.0..=1.0).contains(&link.loss_rate),
+        "loss rate needs to be between 0.0 and 1.0!"
+    );
+    assert!(
+        (0.0..=1.0).contains(&link.ce_mark_rate),
+        "CE mark rate needs to be between 0.0 and 1.0!"
+    );
+}
+
+fn duration_as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000f64
+}
+
+fn duration_from_secs_f64(secs: f64) -> Duration {
+    let nanos = (secs * 1_000_000_000f64) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// A simple pseudo random generator, to get reproducible results.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a value in the range `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
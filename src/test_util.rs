@@ -0,0 +1,138 @@
+//! Helpers for tests that need established `Connection`s or that replay captured datagrams.
+//!
+//! The helpers use the in-memory transport or no transport at all, so no UDP sockets are
+//! required. The `Connection`s are driven by the given `Core`, so the tests need to run the
+//! `Core` to make progress.
+
+use config::Config;
+use connection::Connection;
+use context::Context;
+use context_inner::ContextInner;
+use error::*;
+use test_transport::Network;
+
+use futures::Async::Ready;
+use futures::{future, Stream};
+
+use tokio_core::reactor::Core;
+
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// The address of the context that the datagrams of a replay are sent to.
+pub const REPLAY_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 4433);
+
+/// A client and a server `Connection` that are connected over an in-memory `Network`.
+/// The `Context`s and the `Network` need to outlive the `Connection`s.
+pub struct Pair {
+    pub client: Connection,
+    pub server: Connection,
+    pub client_context: Context,
+    pub server_context: Context,
+    pub network: Network,
+}
+
+/// Creates a client and a server `Context` on a new in-memory `Network` and runs the handshake
+/// of a `Connection` between both to completion.
+///
+/// server_name - The name of the server that is used by TLS to verify the certificate of the
+///               server.
+///
+/// # Returns
+/// The ready client and server `Connection`.
+pub fn establish_pair(
+    evt_loop: &mut Core,
+    client_config: Config,
+    server_config: Config,
+    server_name: &str,
+) -> Result<Pair, Error> {
+    let network = Network::new();
+    let handle = evt_loop.handle();
+
+    let server_context = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        server_config,
+    )?;
+    let mut client_context = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        client_config,
+    )?;
+
+    let client =
+        evt_loop.run(client_context.new_connection(server_context.local_addr(), server_name))?;
+
+    let (server, server_context) = evt_loop
+        .run(server_context.into_future())
+        .map_err(|(e, _)| e)?;
+
+    let server = match server {
+        Some(server) => server,
+        None => bail!("Server `Context` closed before returning the `Connection`"),
+    };
+
+    Ok(Pair {
+        client,
+        server,
+        client_context,
+        server_context,
+        network,
+    })
+}
+
+/// The state after a replay of datagrams, see `replay`.
+/// The `ReplayResult` needs to outlive the `Connection`s.
+pub struct ReplayResult {
+    /// The incoming `Connection`s that were created by the datagrams.
+    pub connections: Vec<Connection>,
+    /// The datagrams that were sent in response, with the address they were sent to.
+    pub responses: Vec<(Vec<u8>, SocketAddr)>,
+    _context: ContextInner,
+}
+
+/// Replays captured datagrams (e.g. from a pcap of a reported bug) into a new server context,
+/// to reproduce the behavior of the context deterministically in a regression test.
+///
+/// The datagrams are processed synchronously in the given order, as if they were sent from the
+/// given addresses to `REPLAY_ADDR`. No sockets are used and all datagrams are processed at
+/// the same time, so the result does not depend on the timing of the test. Afterwards, all
+/// datagrams the context wants to send in response are collected.
+///
+/// # Returns
+/// The `Connection`s that were created by the datagrams and the datagrams that were sent in
+/// response.
+pub fn replay(
+    evt_loop: &mut Core,
+    datagrams: &[(Vec<u8>, SocketAddr)],
+    config: Config,
+) -> Result<ReplayResult, Error> {
+    let (mut context, mut recv_con, _) = ContextInner::new(
+        Vec::new(),
+        &evt_loop.handle(),
+        config,
+        Arc::new(AtomicBool::new(false)),
+    )?;
+
+    // The connection contexts and the channel of the new `Connection`s need to be polled from
+    // within a task.
+    let result = evt_loop.run(future::lazy(move || {
+        let responses = context.replay(datagrams, REPLAY_ADDR.into());
+
+        let mut connections = Vec::new();
+        while let Ok(Ready(Some(con))) = recv_con.poll() {
+            connections.push(con);
+        }
+
+        Ok::<_, Error>(ReplayResult {
+            connections,
+            responses,
+            _context: context,
+        })
+    }))?;
+
+    Ok(result)
+}
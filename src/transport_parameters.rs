@@ -0,0 +1,177 @@
+use error::*;
+
+/// The ids of the transport parameters that are defined by QUIC or by the extensions that are
+/// supported by picoquic. Custom transport parameters must not use these ids.
+const DEFINED_IDS: &[u64] = &[
+    // max_datagram_frame_size (RFC 9221)
+    0x20,
+    // grease_quic_bit (RFC 9287)
+    0x2ab2,
+    // min_ack_delay (ack frequency extension)
+    0xff04_de1a,
+    0xff04_de1b,
+    // enable_time_stamp
+    0x7158,
+    // version_information (draft of RFC 9368)
+    0x00ff_73db,
+];
+
+/// The maximum value of a variable-length integer.
+const MAX_VARINT: u64 = (1 << 62) - 1;
+
+/// Checks that the given id can be used for a custom transport parameter.
+/// The ids below `0x40` are reserved for the transport parameters of RFC 9000 and future
+/// standards, the ids of the form `31 * N + 27` are reserved for greasing.
+pub fn validate_id(id: u64) -> Result<(), Error> {
+    if id > MAX_VARINT {
+        bail!(
+            "Transport parameter id {:#x} is not a valid variable-length integer",
+            id
+        );
+    }
+
+    if id < 0x40 || DEFINED_IDS.contains(&id) {
+        bail!("Transport parameter id {:#x} is defined by QUIC", id);
+    }
+
+    if id % 31 == 27 {
+        bail!("Transport parameter id {:#x} is reserved for greasing", id);
+    }
+
+    Ok(())
+}
+
+/// Encodes the given transport parameters in the wire format (id, length and value).
+/// All ids are validated and must be unique.
+pub fn encode(params: &[(u64, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+
+    for (i, &(id, ref value)) in params.iter().enumerate() {
+        validate_id(id)?;
+
+        if params[..i].iter().any(|&(other, _)| other == id) {
+            bail!("Transport parameter id {:#x} is used multiple times", id);
+        }
+
+        write_varint(&mut data, id);
+        write_varint(&mut data, value.len() as u64);
+        data.extend_from_slice(value);
+    }
+
+    Ok(data)
+}
+
+/// Parses transport parameters in the wire format.
+pub fn parse(mut data: &[u8]) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+    let mut params = Vec::new();
+
+    while !data.is_empty() {
+        let id = read_varint(&mut data)?;
+        let len = read_varint(&mut data)? as usize;
+
+        if data.len() < len {
+            bail!("Transport parameter {:#x} is truncated", id);
+        }
+
+        let (value, rest) = data.split_at(len);
+        params.push((id, value.to_vec()));
+        data = rest;
+    }
+
+    Ok(params)
+}
+
+/// Writes a variable-length integer, the value must not be greater than `MAX_VARINT`.
+fn write_varint(data: &mut Vec<u8>, value: u64) {
+    let (len, prefix) = if value < 1 << 6 {
+        (1, 0x00)
+    } else if value < 1 << 14 {
+        (2, 0x40)
+    } else if value < 1 << 30 {
+        (4, 0x80)
+    } else {
+        (8, 0xc0)
+    };
+
+    for i in (0..len).rev() {
+        let mut byte = (value >> (8 * i)) as u8;
+
+        if i == len - 1 {
+            byte |= prefix;
+        }
+
+        data.push(byte);
+    }
+}
+
+/// Reads a variable-length integer and advances `data` behind it.
+fn read_varint(data: &mut &[u8]) -> Result<u64, Error> {
+    let len = match data.first() {
+        Some(first) => 1 << (first >> 6),
+        None => bail!("Transport parameters are truncated"),
+    };
+
+    if data.len() < len {
+        bail!("Transport parameters are truncated");
+    }
+
+    let value = data[1..len]
+        .iter()
+        .fold(u64::from(data[0] & 0x3f), |v, b| (v << 8) | u64::from(*b));
+    *data = &data[len..];
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_parse() {
+        let params = vec![
+            (0x40, vec![1, 2, 3]),
+            (0x4000_0000, Vec::new()),
+            (0x1234, vec![0; 100]),
+        ];
+
+        assert_eq!(params, parse(&encode(&params).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn varints_are_encoded_with_the_shortest_length() {
+        let mut data = Vec::new();
+        write_varint(&mut data, 37);
+        write_varint(&mut data, 15_293);
+        write_varint(&mut data, 494_878_333);
+        write_varint(&mut data, 151_288_809_941_952_652);
+
+        assert_eq!(
+            vec![
+                0x25, 0x7b, 0xbd, 0x9d, 0x7f, 0x3e, 0x7d, 0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8,
+                0x8c
+            ],
+            data
+        );
+    }
+
+    #[test]
+    fn defined_and_duplicated_ids_are_rejected() {
+        assert!(validate_id(0x00).is_err());
+        assert!(validate_id(0x3f).is_err());
+        assert!(validate_id(0x2ab2).is_err());
+        assert!(validate_id(31 * 5 + 27).is_err());
+        assert!(validate_id(MAX_VARINT + 1).is_err());
+        assert!(validate_id(0x40).is_ok());
+
+        assert!(encode(&[(0x40, vec![1]), (0x40, vec![2])]).is_err());
+    }
+
+    #[test]
+    fn truncated_parameters_are_rejected() {
+        let data = encode(&[(0x40, vec![1, 2, 3])]).unwrap();
+
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(&[0x40]).is_err());
+    }
+}
@@ -33,3 +33,20 @@ pub fn default_verify_certificate(
     let mut context = X509StoreContext::new()?;
     context.init(store, cert, chain, |c| c.verify_cert())
 }
+
+/// A `VerifyCertificate` handler that accepts all certificates without verifying them.
+#[cfg(feature = "insecure")]
+pub(crate) struct SkipVerification;
+
+#[cfg(feature = "insecure")]
+impl VerifyCertificate for SkipVerification {
+    fn verify(
+        &mut self,
+        _: ConnectionId,
+        _: ConnectionType,
+        _: &X509Ref,
+        _: &StackRef<X509>,
+    ) -> Result<bool, ErrorStack> {
+        Ok(true)
+    }
+}
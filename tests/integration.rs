@@ -6,16 +6,20 @@ extern crate timebomb;
 extern crate tokio_core;
 
 use picoquic::{
-    default_verify_certificate, Config, Connection, ConnectionId, ConnectionType, Context,
-    FileFormat, NewStreamFuture, NewStreamHandle, SType, Stream, VerifyCertificate,
+    default_verify_certificate, AcceptDecision, CipherSuite, Config, Connection, ConnectionId,
+    ConnectionState, ConnectionType, Context, DisconnectReason, EarlyDataPolicy, ErrorKind, Event,
+    FileFormat, IncomingInfo, NewStreamFuture, NewStreamHandle, OverflowPolicy, SType, Stream,
+    VerifyCertificate,
 };
 
 use std::fmt;
-use std::net::SocketAddr;
+use std::io::Cursor;
+use std::net::{SocketAddr, UdpSocket};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use futures::sync::mpsc::unbounded;
 use futures::{Future, Sink, Stream as FStream};
@@ -137,9 +141,12 @@ fn client_connects_creates_stream_and_sends_data<F, T, C>(
 
     assert_eq!(
         stream.local_addr(),
-        ([0, 0, 0, 0], context.local_addr().port()).into()
+        Some(([0, 0, 0, 0], context.local_addr().port()).into())
+    );
+    assert_eq!(
+        stream.peer_addr(),
+        Some(([127, 0, 0, 1], addr.port()).into())
     );
-    assert_eq!(stream.peer_addr(), ([127, 0, 0, 1], addr.port()).into());
     assert_ne!(stream.peer_addr(), stream.local_addr());
 
     evt_loop
@@ -302,9 +309,9 @@ where
                 c.for_each(move |s| {
                     // Peer addr and local addr can not be the same
                     assert_ne!(s.peer_addr(), s.local_addr());
-                    // The local address should be unspecified, as we do not set a specific address in
-                    // the tests.
-                    assert!(s.local_addr().ip().is_unspecified());
+                    // The server is bound to an unspecified address, so the local address is the
+                    // address the client contacted.
+                    assert!(s.local_addr().expect("local addr").ip().is_loopback());
 
                     let (send, recv) = s.split();
 
@@ -554,3 +561,3382 @@ fn set_certificate_and_key_from_memory() {
         config
     });
 }
+
+#[test]
+fn alpn_select_handler_selects_offered_protocol() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_select_handler(|protocols: &[&str]| {
+                protocols.iter().position(|p| *p == "picoquic-test")
+            });
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let mut client_config = get_test_config();
+    client_config.set_alpn("picoquic-test");
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(client_config);
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(Some("picoquic-test".into()), con.negotiated_alpn());
+}
+
+#[test]
+fn connection_reports_negotiated_cipher_suite() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(con.cipher_suite().is_some());
+}
+
+#[test]
+fn connection_uses_configured_cipher_suite() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let mut config = get_test_config();
+    config.set_cipher_suites(&[CipherSuite::Aes256GcmSha384]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(Some(CipherSuite::Aes256GcmSha384), con.cipher_suite());
+}
+
+#[test]
+fn handshake_without_common_cipher_suite_fails() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_cipher_suites(&[CipherSuite::Chacha20Poly1305Sha256]);
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let mut config = get_test_config();
+    config.set_cipher_suites(&[CipherSuite::Aes128GcmSha256]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let err = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .err()
+        .expect("handshake fails");
+
+    assert!(match err.kind() {
+        ErrorKind::NoCommonTLSParameters => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn connection_id_generator_sets_prefix_of_server_connection_ids() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_connection_id_generator(|id: &mut [u8]| {
+                id[0] = 0xAB;
+                id[1] = 0xCD;
+            });
+            config
+        },
+        move |c, _| {
+            c.for_each(move |c| {
+                let _ = send.send(c.id());
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let id = recv.recv().expect("receives server connection id");
+    assert_eq!(0xABCD, u64::from(id) >> 48);
+}
+
+#[test]
+fn server_bound_to_unspecified_address_replies_from_contacted_address() {
+    let (send, recv) = channel();
+
+    thread::spawn(move || {
+        let mut evt_loop = Core::new().expect("creates event loop");
+
+        let context = Context::new(
+            &([0, 0, 0, 0], 0).into(),
+            &evt_loop.handle(),
+            get_test_config(),
+        )
+        .expect("creates quic context");
+
+        send.send(context.local_addr().port())
+            .expect("sends server socket port");
+
+        evt_loop
+            .run(context.for_each(|_| Ok(())))
+            .expect("event loop spins on server context");
+    });
+
+    let port = recv.recv().expect("receives server socket port");
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    for ip in &[[127, 0, 0, 1], [127, 0, 0, 2]] {
+        let addr = (*ip, port).into();
+        let con = evt_loop
+            .run(context.new_connection(addr, TEST_SERVER_NAME))
+            .expect("creates connection");
+
+        assert_eq!(Some(addr), con.peer_addr());
+    }
+}
+
+#[test]
+fn server_reports_handshake_confirmed_event() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+
+            h.spawn(
+                c.events()
+                    .into_future()
+                    .map(move |(event, _)| {
+                        let _ = send.send(event);
+                        drop(c);
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let _con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(
+        Some(Event::HandshakeConfirmed),
+        recv.recv().expect("receives server event")
+    );
+}
+
+#[test]
+fn heartbeat_is_reported_to_peer() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.enable_heartbeat();
+            config
+        },
+        move |c, h| {
+            c.for_each(move |c| {
+                c.send_heartbeat();
+                h.spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+                Ok(())
+            })
+        },
+    );
+
+    let mut config = get_test_config();
+    config.enable_heartbeat();
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let events = con.events();
+
+    // The server gets the `Connection`, when the first data is received.
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let _stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+
+    let (event, _) = evt_loop
+        .run(
+            events
+                .filter(|e| *e == Event::Heartbeat)
+                .into_future()
+                .map_err(|(e, _)| e),
+        )
+        .unwrap();
+
+    assert_eq!(Some(Event::Heartbeat), event);
+}
+
+#[test]
+fn client_sends_stream_from_reader() {
+    let data = vec![7u8; 1024 * 1024];
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread(get_test_config, move |c, _| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            c.for_each(move |s| {
+                let send = send.clone();
+                s.fold(0, |len, m| Ok::<_, picoquic::Error>(len + m.len()))
+                    .map(move |len| {
+                        let _ = send.unbounded_send(len);
+                    })
+            })
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    evt_loop
+        .run(con.send_stream_from_reader(stream.id(), Cursor::new(data.clone())))
+        .expect("sends all data");
+
+    // The last data is still queued, so we need to run the event loop until the server received it.
+    assert_eq!(
+        Some(data.len()),
+        evt_loop.run(recv.into_future()).unwrap().0
+    );
+}
+
+#[test]
+fn client_sends_stream_from_data_provider() {
+    let total = 512 * 1024;
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread(get_test_config, move |c, _| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            c.for_each(move |s| {
+                let send = send.clone();
+                s.fold(0, |len, m| Ok::<_, picoquic::Error>(len + m.len()))
+                    .map(move |len| {
+                        let _ = send.unbounded_send(len);
+                    })
+            })
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let mut remaining = total;
+    con.set_stream_data_provider(stream.id(), move |buf| {
+        let len = buf.len().min(remaining);
+        buf[..len].iter_mut().for_each(|b| *b = 7);
+        remaining -= len;
+        (len, remaining == 0)
+    });
+
+    assert_eq!(Some(total), evt_loop.run(recv.into_future()).unwrap().0);
+}
+
+#[test]
+fn client_reports_remote_close_as_disconnect_reason() {
+    let addr = start_server_thread_with_default_config(|c, _| {
+        c.for_each(|c| {
+            c.close_immediately();
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(None, con.disconnect_reason());
+
+    let (_, con) = evt_loop.run(con.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(Some(DisconnectReason::RemoteClose), con.disconnect_reason());
+}
+
+#[test]
+fn client_reports_close_info_of_remote_close() {
+    let addr = start_server_thread_with_default_config(|c, _| {
+        c.for_each(|c| {
+            c.close_with_error(0x2a, "shutting down");
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(None, con.close_info());
+
+    let (_, con) = evt_loop.run(con.into_future()).map_err(|_| ()).unwrap();
+    let info = con
+        .close_info()
+        .expect("close info of the closed connection");
+    assert_eq!(0, info.local_error);
+    assert_eq!(0, info.remote_error);
+    assert_eq!(0x2a, info.remote_application_error);
+    assert_eq!(Some("shutting down".to_owned()), info.remote_reason);
+}
+
+#[test]
+fn server_closes_connection_on_recv_buffer_overflow() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Close);
+            config
+        },
+        move |c, _| {
+            c.for_each(move |mut c| {
+                let send = send.clone();
+                // The `Stream`s are never read, so the received data stays buffered.
+                let mut streams = Vec::new();
+                futures::future::poll_fn(move || loop {
+                    let overflow = match c.poll() {
+                        Ok(futures::Async::Ready(Some(stream))) => {
+                            streams.push(stream);
+                            continue;
+                        }
+                        Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                        Err(ref e) => match e.kind() {
+                            ErrorKind::RecvBufferOverflow => true,
+                            _ => false,
+                        },
+                        Ok(futures::Async::Ready(None)) => false,
+                    };
+
+                    let _ = send.send((overflow, c.close_info()));
+                    return Ok::<_, picoquic::Error>(futures::Async::Ready(()));
+                })
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let mut remaining = 256 * 1024;
+    con.set_stream_data_provider(stream.id(), move |buf| {
+        let len = buf.len().min(remaining);
+        remaining -= len;
+        (len, remaining == 0)
+    });
+
+    let _ = evt_loop.run(futures::future::poll_fn(|| loop {
+        match con.poll() {
+            Ok(futures::Async::Ready(Some(_))) => {}
+            Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+            _ => return Ok::<_, ()>(futures::Async::Ready(())),
+        }
+    }));
+
+    let (overflow, server_close_info) = recv.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(overflow);
+    // The buffered data was received in `STREAM` frames.
+    assert_eq!(
+        Some(0x08),
+        server_close_info
+            .expect("close info of the server")
+            .offending_frame_type
+    );
+    // `FLOW_CONTROL_ERROR`
+    assert_eq!(3, con.close_info().expect("close info").remote_error);
+}
+
+#[test]
+fn server_throttles_peer_with_backpressure_until_data_is_read() {
+    const DATA_SIZE: usize = 512 * 1024;
+
+    let (send, recv) = unbounded();
+    let provided = Arc::new(AtomicUsize::new(0));
+    let provided_server = provided.clone();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Backpressure);
+            config
+        },
+        move |c, h| {
+            c.for_each(move |c| {
+                use tokio_core::reactor::Timeout;
+
+                let send = send.clone();
+                let provided = provided_server.clone();
+                let timeout = Timeout::new(Duration::from_secs(1), &h).unwrap();
+                let h2 = h.clone();
+
+                h.spawn(
+                    c.into_future()
+                        .map_err(|_| ())
+                        .and_then(move |(stream, c)| {
+                            h2.spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+
+                            // The `Stream` is not read for one second.
+                            timeout.map_err(|_| ()).map(move |_| (stream, send))
+                        })
+                        .and_then(move |(stream, send)| {
+                            let _ = send.unbounded_send(provided.load(Ordering::SeqCst));
+                            stream
+                                .expect("stream")
+                                .fold(0, |len, data| Ok::<_, picoquic::Error>(len + data.len()))
+                                .map(move |len| {
+                                    let _ = send.unbounded_send(len);
+                                })
+                                .map_err(|_| ())
+                        }),
+                );
+
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let mut remaining = DATA_SIZE;
+    con.set_stream_data_provider(stream.id(), move |buf| {
+        let len = buf.len().min(remaining);
+        remaining -= len;
+        provided.fetch_add(len, Ordering::SeqCst);
+        (len, remaining == 0)
+    });
+
+    evt_loop.handle().spawn(
+        con.for_each(move |_| {
+            let _ = &stream;
+            Ok(())
+        })
+        .map_err(|_| ()),
+    );
+
+    let lens = evt_loop.run(recv.take(2).collect()).unwrap();
+    assert!(
+        lens[0] < DATA_SIZE / 2,
+        "{} bytes sent while throttled",
+        lens[0]
+    );
+    assert_eq!(DATA_SIZE, lens[1]);
+}
+
+#[test]
+fn data_blocked_by_flow_control_is_counted_in_send_queue() {
+    use tokio_core::reactor::Timeout;
+
+    const DATA_SIZE: usize = 4 * 1024 * 1024;
+
+    // The server holds the `Stream`s, but never reads them.
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Backpressure);
+            config
+        },
+        move |c, h| {
+            c.for_each(move |c| {
+                h.spawn(
+                    c.fold(Vec::new(), |mut streams, s| {
+                        streams.push(s);
+                        Ok::<_, picoquic::Error>(streams)
+                    })
+                    .map(|_| ())
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(vec![0; DATA_SIZE])))
+        .unwrap();
+
+    let handle = evt_loop.handle();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(500), &handle).unwrap())
+        .unwrap();
+
+    let queued = con.total_send_queue_bytes();
+    assert!(queued > 0, "nothing queued");
+    assert!(queued < DATA_SIZE as u64, "{} bytes queued", queued);
+    drop(stream);
+}
+
+#[test]
+fn client_reports_idle_timeout_as_disconnect_reason() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection_with_idle_timeout(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            Duration::from_secs(1),
+        ))
+        .expect("creates connection");
+
+    let (_, con) = evt_loop.run(con.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(Some(DisconnectReason::IdleTimeout), con.disconnect_reason());
+}
+
+#[test]
+fn server_reports_recv_buffer_overflow_as_local_error() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Close);
+            config
+        },
+        move |c, h| {
+            c.for_each(move |mut c| {
+                let send = send.clone();
+                let mut streams = Vec::new();
+
+                // The `Stream`s are never read, so the received data stays buffered.
+                h.spawn(futures::future::poll_fn(move || loop {
+                    match c.poll() {
+                        Ok(futures::Async::Ready(Some(stream))) => streams.push(stream),
+                        Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                        _ => {
+                            let _ = send.send(c.disconnect_reason());
+                            return Ok(futures::Async::Ready(()));
+                        }
+                    }
+                }));
+
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let mut remaining = 256 * 1024;
+    con.set_stream_data_provider(stream.id(), move |buf| {
+        let len = buf.len().min(remaining);
+        remaining -= len;
+        (len, remaining == 0)
+    });
+
+    let _ = evt_loop.run(con.for_each(|_| Ok(())));
+
+    assert_eq!(
+        Some(DisconnectReason::LocalError),
+        recv.recv_timeout(Duration::from_secs(10)).unwrap()
+    );
+}
+
+#[test]
+fn data_provider_without_data_is_not_asked_again_immediately() {
+    use tokio_core::reactor::Timeout;
+
+    let total = 64 * 1024;
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread(get_test_config, move |c, _| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            c.for_each(move |s| {
+                let send = send.clone();
+                s.fold(0, |len, m| Ok::<_, picoquic::Error>(len + m.len()))
+                    .map(move |len| {
+                        let _ = send.unbounded_send(len);
+                    })
+            })
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+    con.set_stream_data_provider(stream.id(), move |_| {
+        calls2.fetch_add(1, Ordering::SeqCst);
+        (0, false)
+    });
+
+    let timeout = Timeout::new(Duration::from_millis(200), &evt_loop.handle()).unwrap();
+    evt_loop.run(timeout).unwrap();
+
+    // Without waiting, picoquic would call the provider for each packet it prepares.
+    let calls = calls.load(Ordering::SeqCst);
+    assert!(calls > 0, "the provider was not asked for data");
+    assert!(calls < 20, "the provider was asked {} times", calls);
+
+    let mut remaining = total;
+    con.set_stream_data_provider(stream.id(), move |buf| {
+        let len = buf.len().min(remaining);
+        remaining -= len;
+        (len, remaining == 0)
+    });
+
+    assert_eq!(Some(total), evt_loop.run(recv.into_future()).unwrap().0);
+}
+
+#[test]
+fn connection_has_no_unacked_data_after_peer_acknowledged() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(send_data)))
+        .unwrap();
+
+    let (data, _stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(send_data.as_bytes(), &data.unwrap()[..]);
+
+    // The acknowledgement of the echoed data may arrive after the data itself.
+    for _ in 0..100 {
+        if !con.has_unacked_data() {
+            break;
+        }
+
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert!(!con.has_unacked_data());
+    assert_eq!(0, con.total_send_queue_bytes());
+}
+
+#[test]
+fn context_reports_capacity_stats_of_hinted_connections() {
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let mut config = get_test_config();
+    config.set_capacity_hints(4, 8);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+    evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    let stats = context.capacity_stats();
+    assert_eq!(4, stats.connection_capacity);
+    assert_eq!(8, stats.stream_capacity);
+    assert_eq!(1, stats.connections_high_water);
+    assert!(stats.streams_high_water >= 1);
+    assert_eq!(0, stats.exhausted);
+}
+
+#[test]
+fn new_connection_has_not_migrated() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(0, con.migration_count());
+}
+
+#[test]
+fn client_reads_custom_transport_parameters_of_server() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.add_custom_transport_parameter(0x7a7a7a, b"hello client".to_vec());
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(con
+        .peer_custom_transport_parameters()
+        .contains(&(0x7a7a7a, b"hello client".to_vec())));
+}
+
+#[test]
+fn custom_transport_parameter_with_quic_defined_id_is_rejected() {
+    let mut config = get_test_config();
+    config.add_custom_transport_parameter(0x01, vec![1]);
+
+    let evt_loop = Core::new().expect("creates event loop");
+    assert!(Context::new(&([0, 0, 0, 0], 0).into(), &evt_loop.handle(), config).is_err());
+}
+
+#[test]
+fn send_ack_now_keeps_connection_working() {
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    for i in 0..3 {
+        let send_data = format!("hello server{}", i);
+        stream = evt_loop
+            .run(stream.send(BytesMut::from(send_data.as_bytes())))
+            .unwrap();
+
+        let (data, s) = evt_loop
+            .run(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        assert_eq!(send_data.as_bytes(), &data.unwrap()[..]);
+        stream = s;
+
+        con.send_ack_now();
+    }
+}
+
+#[test]
+fn handshake_with_small_handshake_packets_is_counted_in_flights() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_handshake_packet_size(1200);
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let mut config = get_test_config();
+    config.set_max_handshake_packet_size(1200);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    // The client sends its hello and, after receiving the reply of the server, its finished
+    // message in a second flight.
+    assert!(
+        con.handshake_flight_count() >= 2,
+        "{} handshake flights",
+        con.handshake_flight_count()
+    );
+}
+
+#[test]
+fn too_small_max_handshake_packet_size_is_rejected() {
+    let mut config = get_test_config();
+    config.set_max_handshake_packet_size(1000);
+
+    let evt_loop = Core::new().expect("creates event loop");
+    assert!(Context::new(&([0, 0, 0, 0], 0).into(), &evt_loop.handle(), config).is_err());
+
+    // The minimum size of an `Initial` is accepted.
+    let mut config = get_test_config();
+    config.set_max_handshake_packet_size(1200);
+    assert!(Context::new(&([0, 0, 0, 0], 0).into(), &evt_loop.handle(), config).is_ok());
+}
+
+#[test]
+fn binding_to_unknown_device_is_rejected() {
+    let mut config = get_test_config();
+    config.set_bind_device("unknown-dev0");
+
+    let evt_loop = Core::new().expect("creates event loop");
+    assert!(Context::new(&([0, 0, 0, 0], 0).into(), &evt_loop.handle(), config).is_err());
+}
+
+#[test]
+fn switching_congestion_algorithm_keeps_connection_working() {
+    use picoquic::CongestionAlgorithm;
+
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(
+        Some(CongestionAlgorithm::NewReno),
+        con.congestion_algorithm()
+    );
+
+    let mut stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    for algorithm in &[
+        CongestionAlgorithm::Cubic,
+        CongestionAlgorithm::Bbr,
+        CongestionAlgorithm::NewReno,
+    ] {
+        con.set_congestion_algorithm(*algorithm);
+
+        let send_data = format!("hello server {:?}", algorithm);
+        stream = evt_loop
+            .run(stream.send(BytesMut::from(send_data.as_bytes())))
+            .unwrap();
+
+        let (data, s) = evt_loop
+            .run(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        assert_eq!(send_data.as_bytes(), &data.unwrap()[..]);
+        assert_eq!(Some(*algorithm), con.congestion_algorithm());
+        stream = s;
+    }
+}
+
+#[test]
+fn congestion_debug_reports_the_state_of_the_current_algorithm() {
+    use picoquic::{CongestionAlgorithm, CongestionDebug};
+
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    con.set_congestion_algorithm(CongestionAlgorithm::Cubic);
+
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+    let _ = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    match con.congestion_debug() {
+        Some(CongestionDebug::Cubic { .. }) => {}
+        debug => panic!("unexpected congestion debug state: {:?}", debug),
+    }
+}
+
+#[test]
+fn connection_pool_hands_out_ready_connections_and_refills() {
+    use picoquic::{ConnectionPool, ConnectionState};
+
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let mut config = get_test_config();
+    config.enable_keep_alive(Duration::from_secs(1));
+    let (context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut pool = ConnectionPool::new(
+        context.get_new_connection_handle(),
+        ([127, 0, 0, 1], addr.port()).into(),
+        TEST_SERVER_NAME,
+        2,
+        &evt_loop.handle(),
+    )
+    .expect("creates pool");
+    assert_eq!(2, pool.connecting_count());
+
+    let mut con = evt_loop.run(pool.get()).expect("gets connection");
+    assert_eq!(ConnectionState::Ready, con.state());
+    assert_eq!(2, pool.idle_count() + pool.connecting_count());
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+    let (data, _stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(&b"hello server"[..], &data.unwrap()[..]);
+
+    // The returned `Connection` takes the slot of the `Connection` that replaced it.
+    let id = con.id();
+    pool.put(con);
+    assert_eq!(2, pool.idle_count() + pool.connecting_count());
+
+    let second = evt_loop.run(pool.get()).expect("gets connection");
+    assert_eq!(ConnectionState::Ready, second.state());
+    assert_eq!(id, second.id());
+}
+
+#[test]
+fn connection_pool_backs_off_while_server_is_down() {
+    use picoquic::ConnectionPool;
+
+    // Nothing listens on the port of the dropped socket.
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+        .and_then(|s| s.local_addr())
+        .unwrap()
+        .port();
+
+    let mut config = get_test_config();
+    config.set_handshake_timeout(Duration::from_millis(200));
+    let (context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut pool = ConnectionPool::new(
+        context.get_new_connection_handle(),
+        ([127, 0, 0, 1], port).into(),
+        TEST_SERVER_NAME,
+        1,
+        &evt_loop.handle(),
+    )
+    .expect("creates pool");
+
+    assert!(evt_loop.run(pool.get()).is_err());
+
+    // No new `Connection` is started, before the backoff expired.
+    evt_loop
+        .run(futures::future::poll_fn(|| {
+            assert!(pool.poll_connection().unwrap().is_not_ready());
+            Ok::<_, ()>(futures::Async::Ready(()))
+        }))
+        .unwrap();
+    assert_eq!(0, pool.connecting_count());
+
+    // After the backoff, the pool connects again.
+    assert!(evt_loop.run(pool.get()).is_err());
+}
+
+#[test]
+fn connection_pool_without_connections_is_rejected() {
+    use picoquic::ConnectionPool;
+
+    let (context, evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    assert!(ConnectionPool::new(
+        context.get_new_connection_handle(),
+        ([127, 0, 0, 1], 1).into(),
+        TEST_SERVER_NAME,
+        0,
+        &evt_loop.handle(),
+    )
+    .is_err());
+}
+
+#[test]
+fn idle_connection_is_app_limited() {
+    use picoquic::SendLimit;
+
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    evt_loop.turn(Some(Duration::from_millis(100)));
+
+    assert_eq!(SendLimit::AppLimited, con.send_limitation());
+}
+
+#[test]
+fn connection_label_is_set() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(None, con.label());
+    con.set_label("user-42");
+
+    for _ in 0..100 {
+        if con.label().is_some() {
+            break;
+        }
+
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert_eq!(Some("user-42".to_owned()), con.label());
+}
+
+#[test]
+fn server_accepts_request_and_responds() {
+    use picoquic::Request;
+
+    let addr = start_server_thread_with_default_config(|mut c, _| {
+        futures::future::poll_fn(move || c.accept_request().poll()).and_then(
+            |Request {
+                 connection,
+                 stream,
+                 data,
+             }| {
+                stream.send(data).and_then(move |stream| {
+                    // Keep the `Connection` and the `Stream` alive, until the client closes.
+                    connection.for_each(|_| Ok(())).map(move |_| {
+                        let _ = &stream;
+                    })
+                })
+            },
+        )
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(BytesMut::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    let (data, _stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(&b"hello server"[..], &data.unwrap()[..]);
+}
+
+#[test]
+fn connections_exceeding_the_new_connection_rate_limit_are_delayed() {
+    use picoquic::RateLimitPolicy;
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_new_connection_rate_limit(1, RateLimitPolicy::Drop);
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+    let addr: SocketAddr = ([127, 0, 0, 1], addr.port()).into();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+    let mut second_context = Context::new(
+        &([0, 0, 0, 0], 0).into(),
+        &evt_loop.handle(),
+        get_test_config(),
+    )
+    .expect("creates quic context");
+
+    evt_loop
+        .run(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates first connection");
+
+    // The `Initial` of the second connection is dropped, until the rate limit has the next
+    // token. So, the client needs to retransmit it.
+    let second = evt_loop
+        .run(second_context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates second connection");
+    assert!(second.handshake_retransmit_count() > 0);
+}
+
+#[test]
+fn connections_exceeding_the_new_connection_rate_limit_are_accepted_after_a_retry() {
+    use picoquic::RateLimitPolicy;
+
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        move || {
+            let mut config = get_test_config();
+            config.set_new_connection_rate_limit(1, RateLimitPolicy::Retry);
+            config.set_accept_filter(move |info: &IncomingInfo| {
+                let _ = send.send(info.address_validated);
+                AcceptDecision::Accept
+            });
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+    let addr: SocketAddr = ([127, 0, 0, 1], addr.port()).into();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+    let mut second_context = Context::new(
+        &([0, 0, 0, 0], 0).into(),
+        &evt_loop.handle(),
+        get_test_config(),
+    )
+    .expect("creates quic context");
+
+    evt_loop
+        .run(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates first connection");
+    assert!(!recv
+        .recv()
+        .expect("filter is called for the first connection"));
+
+    // The second connection exceeds the rate limit, so it is only accepted after it came back
+    // with the token of the retry.
+    evt_loop
+        .run(second_context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates second connection after the retry");
+    assert!(recv
+        .recv()
+        .expect("filter is called for the second connection"));
+    assert!(recv.try_recv().is_err());
+}
+
+#[test]
+fn bulk_transfer_is_not_app_limited() {
+    use picoquic::SendLimit;
+
+    const DATA_SIZE: usize = 16 * 1024 * 1024;
+
+    // The server reads all data, so only the network limits the transfer.
+    let addr = start_server_thread_with_default_config(|c, h| {
+        c.for_each(move |c| {
+            let h = h.clone();
+
+            h.clone().spawn(
+                c.for_each(move |s| {
+                    h.spawn(s.for_each(|_| Ok(())).map_err(|_| ()));
+                    Ok(())
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(vec![0; DATA_SIZE])))
+        .unwrap();
+
+    // Samples the limit, until all data left the send queue.
+    let mut limits = Vec::new();
+    loop {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+        limits.push(con.send_limitation());
+
+        if con.total_send_queue_bytes() == 0 || limits.len() >= 1000 {
+            break;
+        }
+    }
+
+    assert!(
+        limits.iter().any(|l| *l != SendLimit::AppLimited),
+        "{:?}",
+        limits
+    );
+    drop(stream);
+}
+
+#[test]
+fn connection_blocked_by_small_peer_max_data_is_flow_control_limited() {
+    use picoquic::SendLimit;
+    use tokio_core::reactor::Timeout;
+
+    // The server grants only 16 KB to the client and never reads its `Stream`s.
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Backpressure);
+            config
+        },
+        move |c, h| {
+            c.for_each(move |c| {
+                h.spawn(
+                    c.fold(Vec::new(), |mut streams, s| {
+                        streams.push(s);
+                        Ok::<_, picoquic::Error>(streams)
+                    })
+                    .map(|_| ())
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(vec![0; 1024 * 1024])))
+        .unwrap();
+
+    let handle = evt_loop.handle();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(500), &handle).unwrap())
+        .unwrap();
+
+    assert_eq!(SendLimit::FlowControlLimited, con.send_limitation());
+    drop(stream);
+}
+
+#[test]
+fn stream_finished_without_data_is_received_as_empty_stream() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, _| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            c.into_future()
+                .map_err(|(e, _)| e)
+                .and_then(|(s, _)| {
+                    s.expect("receives stream")
+                        .into_future()
+                        .map_err(|(e, _)| e)
+                })
+                .map(move |(data, s)| send.send((data, s.is_reset())).unwrap())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    stream.finish().expect("finishes stream");
+
+    for _ in 0..100 {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    // The server observes the end of the `Stream`, without any data and without a reset.
+    let (data, reset) = recv.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(None, data);
+    assert!(!reset);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn weighted_connections_send_all_data() {
+    use picoquic::test_transport::{LinkModel, Network};
+
+    let total = 512 * 1024;
+    let network = Network::with_link_model(LinkModel {
+        rtt: Duration::from_millis(10),
+        bandwidth: Some(2 * 1024 * 1024),
+        ..Default::default()
+    });
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let server_addr = server.local_addr();
+
+    let (send, recv) = unbounded();
+    let h = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |c| {
+                let h = h.clone();
+                let send = send.clone();
+
+                h.clone().spawn(
+                    c.for_each(move |s| {
+                        let send = send.clone();
+                        h.spawn(
+                            s.fold(0, |len, m| Ok::<_, picoquic::Error>(len + m.len()))
+                                .map(move |len| {
+                                    let _ = send.unbounded_send(len);
+                                })
+                                .map_err(|_| ()),
+                        );
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+                );
+
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+
+    // The bytes that were provided to each connection.
+    let provided = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+    // The bytes of the light connection, when the heavy connection provided all its data.
+    let light_when_heavy_finished = Arc::new(AtomicUsize::new(0));
+
+    let mut cons = Vec::new();
+    for weight in &[4, 1] {
+        let mut con = evt_loop
+            .run(client.new_connection(server_addr, TEST_SERVER_NAME))
+            .expect("creates connection");
+        con.set_weight(*weight);
+
+        let stream = evt_loop
+            .run(con.new_bidirectional_stream())
+            .expect("creates stream");
+
+        cons.push((con, stream));
+    }
+
+    for (index, (con, stream)) in cons.iter().enumerate() {
+        let provided = provided.clone();
+        let light_when_heavy_finished = light_when_heavy_finished.clone();
+        let mut remaining = total;
+
+        con.set_stream_data_provider(stream.id(), move |buf| {
+            let len = buf.len().min(remaining);
+            remaining -= len;
+            provided[index].fetch_add(len, Ordering::SeqCst);
+
+            if index == 0 && remaining == 0 {
+                light_when_heavy_finished
+                    .store(provided[1].load(Ordering::SeqCst), Ordering::SeqCst);
+            }
+
+            (len, remaining == 0)
+        });
+    }
+
+    assert_eq!(
+        vec![total, total],
+        evt_loop.run(recv.take(2).collect()).unwrap()
+    );
+
+    // The link is shared in the ratio of the weights (4:1), while both connections are
+    // backlogged.
+    let light = light_when_heavy_finished.load(Ordering::SeqCst);
+    assert!(light > 0, "the light connection was starved");
+    assert!(
+        light < total / 2,
+        "the light connection sent {} bytes, while the heavy connection sent {}",
+        light,
+        total
+    );
+}
+
+#[test]
+fn server_resets_all_streams() {
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            h.spawn(c.into_future().map_err(|_| ()).and_then(|(stream, c)| {
+                c.reset_all_streams(7);
+                // Keep the `Stream` alive, so it is not closed by being dropped.
+                c.for_each(move |_| {
+                    let _ = &stream;
+                    Ok(())
+                })
+                .map_err(|_| ())
+            }));
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(BytesMut::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    let (result, stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    assert_eq!(result, None);
+    assert!(stream.is_reset());
+}
+
+#[test]
+fn client_counts_streams_reset_by_server() {
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            h.spawn(c.into_future().map_err(|_| ()).and_then(|(stream, c)| {
+                c.reset_all_streams(7);
+                // Keep the `Stream` alive, so it is not closed by being dropped.
+                c.for_each(move |_| {
+                    let _ = &stream;
+                    Ok(())
+                })
+                .map_err(|_| ())
+            }));
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(0, con.peer_reset_count());
+
+    let stream = evt_loop
+        .run(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(BytesMut::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    let (result, _stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(result, None);
+
+    // The `STOP_SENDING` frame may arrive after the `RESET_STREAM` frame.
+    for _ in 0..100 {
+        if con.peer_stop_sending_count() > 0 {
+            break;
+        }
+
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert_eq!(1, con.peer_reset_count());
+    assert_eq!(1, con.peer_stop_sending_count());
+}
+
+#[test]
+fn split_connection_opens_and_accepts_streams() {
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |mut c| {
+            h.spawn(
+                c.new_bidirectional_stream()
+                    .and_then(|s| s.send(BytesMut::from("hello client")))
+                    .and_then(move |s| {
+                        // Keep the `Connection` and `Stream` alive.
+                        c.for_each(move |_| {
+                            let _ = &s;
+                            Ok(())
+                        })
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let (read, mut write) = con.split();
+
+    // The server gets the `Connection`, when the first data is received.
+    let _stream = evt_loop
+        .run(
+            write
+                .new_bidirectional_stream()
+                .and_then(|s| s.send(BytesMut::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    let (stream, _read) = evt_loop
+        .run(read.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    let (data, _) = evt_loop
+        .run(stream.unwrap().into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    assert_eq!(&b"hello client"[..], &data.unwrap()[..]);
+    assert_eq!(ConnectionType::Outgoing, write.get_type());
+}
+
+#[test]
+fn handshake_with_unresponsive_server_times_out() {
+    // The socket never answers, but it prevents ICMP port unreachable messages.
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut config = get_test_config();
+    config.set_handshake_timeout(Duration::from_millis(500));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let err = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .err()
+        .expect("handshake times out");
+
+    assert!(match err.kind() {
+        ErrorKind::HandshakeTimeout => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn accept_filter_rejects_connection() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        move || {
+            let mut config = get_test_config();
+            config.set_accept_filter(move |info: &IncomingInfo| {
+                let _ = send.send(info.clone());
+                AcceptDecision::Reject(40)
+            });
+            config
+        },
+        |c, _| c.for_each(|_| -> Result<(), _> { panic!("connection is rejected") }),
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    assert!(evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_err());
+
+    let info = recv.recv().expect("filter is called");
+    assert_eq!(Some(TEST_SERVER_NAME.to_owned()), info.server_name);
+    assert_eq!(context.local_addr().port(), info.peer_addr.port());
+}
+
+fn early_data_callback_decides_for_resumed_connection(accept: bool) {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        move || {
+            let mut config = get_test_config();
+            config.set_early_data_policy(EarlyDataPolicy::callback(move |info: &IncomingInfo| {
+                let _ = send.send(info.clone());
+                accept
+            }));
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(!con.early_data_allowed());
+
+    let info = recv.recv().expect("callback is called");
+    assert_eq!(Some(TEST_SERVER_NAME.to_owned()), info.server_name);
+
+    // Receive the session ticket of the server.
+    for _ in 0..20 {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    // The ticket only allows 0-RTT data, if the server accepted the early data of the first
+    // connection.
+    let resumed_con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(resumed_con.resumed());
+    assert_eq!(accept, resumed_con.early_data_allowed());
+}
+
+#[test]
+fn early_data_policy_callback_accepts_early_data() {
+    early_data_callback_decides_for_resumed_connection(true);
+}
+
+#[test]
+fn early_data_policy_callback_rejects_early_data() {
+    early_data_callback_decides_for_resumed_connection(false);
+}
+
+#[test]
+fn busy_server_refusal_is_reported_as_connection_refused() {
+    let addr = start_server_thread_with_default_config(|c, _| {
+        c.enter_drain_mode();
+        c.for_each(|_| Ok(()))
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    // Each attempt is refused, the client does not retry on its own.
+    for _ in 0..2 {
+        let err = evt_loop
+            .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+            .err()
+            .expect("connection is refused");
+
+        assert!(match err.kind() {
+            ErrorKind::ConnectionRefused => true,
+            _ => false,
+        });
+        assert_eq!(
+            "The connection was refused by the server.",
+            err.kind().to_string()
+        );
+    }
+}
+
+#[test]
+fn draining_server_refuses_new_connections() {
+    let addr = start_server_thread_with_default_config(|c, _| {
+        c.enter_drain_mode();
+        c.for_each(|_| -> Result<(), _> { panic!("connection is refused") })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let err = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .err()
+        .expect("connection is refused");
+
+    assert!(match err.kind() {
+        ErrorKind::ConnectionRefused => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn accept_filter_asks_client_to_retry() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        move || {
+            let mut config = get_test_config();
+            config.set_accept_filter(move |info: &IncomingInfo| {
+                let _ = send.send(info.clone());
+                AcceptDecision::Retry
+            });
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(get_test_config());
+
+    evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection after the retry");
+
+    let first = recv.recv().expect("filter is called");
+    assert!(!first.address_validated);
+    let second = recv.recv().expect("filter is called again after the retry");
+    assert!(second.address_validated);
+    assert_eq!(first.peer_addr, second.peer_addr);
+    assert!(recv.try_recv().is_err());
+}
+
+#[test]
+fn connect_with_socket_buffer_sizes() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_socket_buffer_sizes(1024 * 1024, 1024 * 1024);
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let mut config = get_test_config();
+    config.set_socket_buffer_sizes(1024 * 1024, 1024 * 1024);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn client_counts_ecn_codepoints_of_received_udp_packets() {
+    use tokio_core::reactor::Timeout;
+
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let mut config = get_test_config();
+    config.enable_ecn_marks();
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let handle = evt_loop.handle();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(100), &handle).unwrap())
+        .unwrap();
+
+    // picoquic does not mark its packets as ECN capable and the loopback interface does not
+    // mark packets as congestion experienced.
+    let counts = con.received_ecn_counts();
+    assert!(counts.not_ect > 0, "{:?}", counts);
+    assert_eq!(0, counts.ce);
+}
+
+#[test]
+fn path_mtu_grows_with_dont_fragment() {
+    use tokio_core::reactor::Timeout;
+
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.enable_dont_fragment();
+        config
+    });
+
+    let mut config = get_test_config();
+    config.enable_dont_fragment();
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let initial_mtu = con.path_mtu();
+    assert!(initial_mtu > 0);
+
+    // The path MTU discovery probes larger packets, while data is sent.
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(vec![0; 64 * 1024])))
+        .unwrap();
+
+    let handle = evt_loop.handle();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(500), &handle).unwrap())
+        .unwrap();
+
+    // The loopback interface has a much larger MTU than the initial MTU of picoquic.
+    assert!(
+        con.path_mtu() > initial_mtu,
+        "path MTU stayed at {}",
+        initial_mtu
+    );
+    drop(stream);
+}
+
+#[test]
+fn server_gets_client_certificates_with_client_authentication() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_verify_certificate_handler(VerifyCertificateImpl::new());
+            config.enable_client_authentication();
+            config
+        },
+        move |c, _| {
+            c.for_each(move |c| {
+                let _ = send.send(c.peer_certificates());
+                Ok(())
+            })
+        },
+    );
+
+    let mut client_config = get_test_config();
+    client_config.set_verify_certificate_handler(VerifyCertificateImpl::new());
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(client_config);
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let server_certs = con
+        .peer_certificates()
+        .expect("client has server certificates");
+    let client_certs = recv
+        .recv()
+        .expect("receives client certificates")
+        .expect("server has client certificates");
+
+    let device_cert = X509::from_pem(include_bytes!("certs/device.test.crt")).unwrap();
+    assert_eq!(
+        device_cert.to_der().unwrap(),
+        client_certs[0].to_der().unwrap()
+    );
+    assert_eq!(
+        device_cert.to_der().unwrap(),
+        server_certs[0].to_der().unwrap()
+    );
+}
+
+#[test]
+fn server_with_multiple_addresses_replies_from_contacted_address() {
+    let (send, recv) = channel();
+
+    thread::spawn(move || {
+        let mut evt_loop = Core::new().expect("creates event loop");
+
+        let context = Context::new_with_addresses(
+            &[([127, 0, 0, 1], 0).into(), ([127, 0, 0, 2], 0).into()],
+            &evt_loop.handle(),
+            get_test_config(),
+        )
+        .expect("creates quic context");
+
+        send.send(context.local_addrs().to_vec())
+            .expect("sends server socket addrs");
+
+        evt_loop
+            .run(context.for_each(|_| Ok(())))
+            .expect("event loop spins on server context");
+    });
+
+    let addrs = recv.recv().expect("receives server socket addrs");
+    assert_eq!(2, addrs.len());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    for addr in addrs {
+        let con = evt_loop
+            .run(context.new_connection(addr, TEST_SERVER_NAME))
+            .expect("creates connection");
+
+        assert_eq!(Some(addr), con.peer_addr());
+    }
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn unwritable_socket_does_not_block_other_sockets_of_server() {
+    use picoquic::test_transport::Network;
+
+    let network = Network::new();
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport_addresses(
+        &[([127, 0, 0, 1], 0).into(), ([127, 0, 0, 1], 0).into()],
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let addrs = server.local_addrs().to_vec();
+    assert_eq!(2, addrs.len());
+
+    handle.spawn(server.for_each(|_| Ok(())).map_err(|_| ()));
+
+    let mut blocked_client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 3], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+
+    network.block_sending(addrs[0], true);
+
+    let mut blocked_con = blocked_client.new_connection(addrs[0], TEST_SERVER_NAME);
+
+    let con = evt_loop
+        .run(client.new_connection(addrs[1], TEST_SERVER_NAME))
+        .expect("creates connection over the writable socket");
+    assert_eq!(Some(addrs[1]), con.peer_addr());
+
+    let not_ready = evt_loop
+        .run(futures::future::lazy(|| blocked_con.poll()))
+        .expect("polls connection")
+        .is_not_ready();
+    assert!(
+        not_ready,
+        "the server can not reply over the blocked socket"
+    );
+
+    network.block_sending(addrs[0], false);
+
+    let con = evt_loop
+        .run(blocked_con)
+        .expect("creates connection after the socket is writable again");
+    assert_eq!(Some(addrs[0]), con.peer_addr());
+}
+
+#[cfg(all(feature = "test-transport", feature = "loss-injection"))]
+#[test]
+fn packets_marked_as_lost_are_retransmitted() {
+    use picoquic::test_transport::{LinkModel, Network};
+
+    let network = Network::with_link_model(LinkModel {
+        rtt: Duration::from_millis(200),
+        ..Default::default()
+    });
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let server_addr = server.local_addr();
+
+    let h = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |c| {
+                let h = h.clone();
+
+                h.clone().spawn(
+                    c.for_each(move |s| {
+                        let (send, recv) = s.split();
+
+                        h.spawn(send.send_all(recv).map(|_| ()).map_err(|_| ()));
+                        Ok(())
+                    }).map_err(|_| ()),
+                );
+
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+
+    let mut con = evt_loop
+        .run(client.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(0.0, con.loss_rate());
+
+    let send_data = vec![0x2a; 8 * 1024];
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .run(stream.send(BytesMut::from(&send_data[..])))
+        .unwrap();
+
+    // Let the data leave the client, the packets are in flight for the half of the rtt.
+    evt_loop.turn(Some(Duration::from_millis(20)));
+    for packet_number in 0..64 {
+        con.mark_packet_lost(packet_number);
+    }
+
+    let mut received = Vec::new();
+    while received.len() < send_data.len() {
+        let (data, s) = evt_loop
+            .run(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        received.extend_from_slice(&data.expect("receives data"));
+        stream = s;
+    }
+
+    assert_eq!(send_data, received);
+    assert!(con.loss_rate() > 0.0);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn small_initial_rtt_retransmits_lost_handshake_packets_earlier() {
+    use picoquic::test_transport::{LinkModel, Network};
+    use tokio_core::reactor::Timeout;
+
+    // All packets are lost in the first 300ms.
+    let network = Network::with_link_model(LinkModel {
+        loss_rate: 1.0,
+        ..Default::default()
+    });
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let server_addr = server.local_addr();
+    handle.spawn(server.for_each(|_| Ok(())).map_err(|_| ()));
+
+    let network2 = network.clone();
+    handle.spawn(
+        Timeout::new(Duration::from_millis(300), &handle)
+            .expect("creates timeout")
+            .map(move |_| network2.set_link_model(LinkModel::default()))
+            .map_err(|_| ()),
+    );
+
+    let mut config = get_test_config();
+    config.set_initial_rtt(Duration::from_millis(20));
+    let mut fast_client =
+        Context::new_with_test_transport(&([127, 0, 0, 2], 0).into(), &network, &handle, config)
+            .expect("creates client context");
+    assert_eq!(Duration::from_millis(20), fast_client.initial_rtt());
+
+    let mut default_client = Context::new_with_test_transport(
+        &([127, 0, 0, 3], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+
+    let (fast, default) = evt_loop
+        .run(
+            fast_client
+                .new_connection(server_addr, TEST_SERVER_NAME)
+                .join(default_client.new_connection(server_addr, TEST_SERVER_NAME)),
+        )
+        .expect("creates connections");
+
+    assert!(fast.handshake_retransmit_count() > default.handshake_retransmit_count());
+}
+
+/// Rebinds the client to a new address after exchanging data with an echo server, that calls a
+/// peer address change handler which allows or vetoes the migration.
+/// Returns the data that is echoed by the server after the migration.
+#[cfg(feature = "test-transport")]
+fn client_migrates_over_test_transport(allow: bool) -> Option<Vec<u8>> {
+    use picoquic::test_transport::Network;
+    use std::sync::Mutex;
+
+    let network = Network::new();
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let changes2 = changes.clone();
+    let mut config = get_test_config();
+    config.set_peer_address_change_handler(move |_, old, new| -> Result<(), picoquic::Error> {
+        changes2.lock().unwrap().push((old, new));
+
+        if allow {
+            Ok(())
+        } else {
+            Err(ErrorKind::Unknown.into())
+        }
+    });
+
+    let server =
+        Context::new_with_test_transport(&([127, 0, 0, 1], 0).into(), &network, &handle, config)
+            .expect("creates server context");
+    let server_addr = server.local_addr();
+
+    let h = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |c| {
+                let h = h.clone();
+
+                h.clone().spawn(
+                    c.for_each(move |s| {
+                        let (send, recv) = s.split();
+
+                        h.spawn(send.send_all(recv).map(|_| ()).map_err(|_| ()));
+                        Ok(())
+                    }).map_err(|_| ()),
+                );
+
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+    let client_addr = client.local_addr();
+
+    let mut con = evt_loop
+        .run(client.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop.run(stream.send(BytesMut::from("before"))).unwrap();
+    let (data, stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(&b"before"[..], &data.expect("receives data")[..]);
+    assert!(changes.lock().unwrap().is_empty());
+
+    let new_addr = ([127, 0, 0, 3], client_addr.port()).into();
+    network.rebind(client_addr, new_addr);
+
+    let after = evt_loop
+        .run(
+            stream
+                .send(BytesMut::from("after"))
+                .and_then(|s| s.into_future().map_err(|(e, _)| e)),
+        )
+        .ok()
+        .and_then(|(data, _)| data)
+        .map(|data| data.to_vec());
+
+    assert_eq!(vec![(client_addr, new_addr)], *changes.lock().unwrap());
+
+    after
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn allowed_peer_address_change_keeps_connection_alive() {
+    assert_eq!(
+        Some(b"after".to_vec()),
+        client_migrates_over_test_transport(true)
+    );
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn vetoed_peer_address_change_closes_connection() {
+    assert_eq!(None, client_migrates_over_test_transport(false));
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn bbr_paces_packets_on_limited_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::CongestionAlgorithm;
+
+    let bandwidth = 1024 * 1024;
+    let link = LinkModel {
+        rtt: Duration::from_millis(20),
+        bandwidth: Some(bandwidth),
+        ..Default::default()
+    };
+
+    let con = client_and_server_exchange_data_over_test_transport(
+        link,
+        (0..256 * 1024).map(|i| i as u8).collect(),
+        |con| con.set_congestion_algorithm(CongestionAlgorithm::Bbr),
+    );
+
+    let rate = con.pacing_rate();
+    assert!(rate > 0, "pacing is not active");
+    // BBR paces the packets at a multiple of its bandwidth estimate.
+    assert!(rate < 10 * bandwidth, "pacing rate {} is too large", rate);
+}
+
+/// Sends the given data to an echo server over the test transport and checks that the same data
+/// is received back.
+/// Returns the `Connection` of the client.
+#[cfg(feature = "test-transport")]
+fn client_and_server_exchange_data_over_test_transport<F: FnOnce(&Connection)>(
+    link: picoquic::test_transport::LinkModel,
+    send_data: Vec<u8>,
+    setup_client: F,
+) -> Connection {
+    client_with_config_and_server_exchange_data_over_test_transport(
+        link,
+        get_test_config(),
+        send_data,
+        setup_client,
+    )
+}
+
+#[cfg(feature = "test-transport")]
+fn client_with_config_and_server_exchange_data_over_test_transport<F: FnOnce(&Connection)>(
+    link: picoquic::test_transport::LinkModel,
+    client_config: Config,
+    send_data: Vec<u8>,
+    setup_client: F,
+) -> Connection {
+    use picoquic::test_transport::Network;
+
+    let network = Network::with_link_model(link);
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let server_addr = server.local_addr();
+
+    let h = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |c| {
+                let h = h.clone();
+
+                h.clone().spawn(
+                    c.for_each(move |s| {
+                        let (send, recv) = s.split();
+
+                        h.spawn(send.send_all(recv).map(|_| ()).map_err(|_| ()));
+                        Ok(())
+                    }).map_err(|_| ()),
+                );
+
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        client_config,
+    )
+    .expect("creates client context");
+
+    let mut con = evt_loop
+        .run(client.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(con.peer_addr(), Some(server_addr));
+    setup_client(&con);
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .run(stream.send(BytesMut::from(&send_data[..])))
+        .unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < send_data.len() {
+        let (data, s) = evt_loop
+            .run(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        received.extend_from_slice(&data.expect("receives data"));
+        stream = s;
+    }
+
+    assert_eq!(send_data, received);
+
+    con
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn establish_pair_returns_connected_connections() {
+    let mut evt_loop = Core::new().expect("creates event loop");
+
+    let mut pair = picoquic::test_util::establish_pair(
+        &mut evt_loop,
+        get_test_config(),
+        get_test_config(),
+        TEST_SERVER_NAME,
+    )
+    .expect("establishes connections");
+
+    assert_eq!(
+        Some(pair.server_context.local_addr()),
+        pair.client.peer_addr()
+    );
+    assert_eq!(
+        Some(pair.client_context.local_addr()),
+        pair.server.peer_addr()
+    );
+
+    let stream = evt_loop
+        .run(pair.client.new_bidirectional_stream())
+        .expect("creates stream");
+    evt_loop
+        .run(stream.send(BytesMut::from(&b"hello server"[..])))
+        .expect("sends data");
+
+    let (stream, _) = evt_loop
+        .run(pair.server.into_future())
+        .map_err(|(e, _)| e)
+        .unwrap();
+    let (data, _) = evt_loop
+        .run(stream.expect("receives stream").into_future())
+        .map_err(|(e, _)| e)
+        .unwrap();
+    assert_eq!(&b"hello server"[..], &data.expect("receives data")[..]);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn replayed_initial_of_unknown_version_is_answered_with_version_negotiation() {
+    use picoquic::{Packet, PacketType};
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+
+    // A long header packet of an unknown version, padded to the minimum size of an `Initial`.
+    let mut datagram = vec![0xc0, 0x1a, 0x2a, 0x3a, 0x4a, 8];
+    datagram.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    datagram.push(8);
+    datagram.extend_from_slice(&[8, 7, 6, 5, 4, 3, 2, 1]);
+    datagram.resize(1200, 0);
+
+    let peer_addr: SocketAddr = ([127, 0, 0, 2], 4433).into();
+    let result =
+        picoquic::test_util::replay(&mut evt_loop, &[(datagram, peer_addr)], get_test_config())
+            .expect("replays datagrams");
+
+    assert!(result.connections.is_empty());
+    assert_eq!(1, result.responses.len());
+
+    let (ref response, addr) = result.responses[0];
+    assert_eq!(peer_addr, addr);
+    assert_eq!(
+        PacketType::VersionNegotiation,
+        Packet::parse_header(response).unwrap().packet_type
+    );
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn client_and_server_communicate_over_test_transport() {
+    client_and_server_exchange_data_over_test_transport(
+        Default::default(),
+        b"hello server".to_vec(),
+        |_| {},
+    );
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn client_counts_ce_marks_of_received_packets() {
+    use picoquic::test_transport::LinkModel;
+
+    let link = LinkModel {
+        ce_mark_rate: 1.0,
+        ..Default::default()
+    };
+
+    let mut config = get_test_config();
+    config.enable_ecn_marks();
+
+    let con = client_with_config_and_server_exchange_data_over_test_transport(
+        link,
+        config,
+        vec![1; 1024],
+        |_| {},
+    );
+
+    let counts = con.received_ecn_counts();
+    assert!(counts.ce > 0, "{:?}", counts);
+    assert_eq!(0, counts.not_ect);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn transfer_completes_over_lossy_and_delayed_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use std::time::Duration;
+
+    let link = LinkModel {
+        loss_rate: 0.1,
+        rtt: Duration::from_millis(20),
+        jitter: Duration::from_millis(5),
+        bandwidth: Some(10 * 1024 * 1024),
+        ..Default::default()
+    };
+
+    client_and_server_exchange_data_over_test_transport(
+        link,
+        (0..64 * 1024).map(|i| i as u8).collect(),
+        |_| {},
+    );
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn congestion_event_callback_is_called_on_lossy_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::CongestionCause;
+    use std::time::Duration;
+
+    let link = LinkModel {
+        loss_rate: 0.1,
+        rtt: Duration::from_millis(20),
+        jitter: Duration::from_millis(5),
+        bandwidth: Some(10 * 1024 * 1024),
+        ..Default::default()
+    };
+
+    let events = Arc::new(AtomicUsize::new(0));
+    let events2 = events.clone();
+
+    client_and_server_exchange_data_over_test_transport(
+        link,
+        (0..256 * 1024).map(|i| i as u8).collect(),
+        move |con| {
+            con.set_congestion_event_callback(move |event| {
+                assert_eq!(CongestionCause::Loss, event.cause);
+                events2.fetch_add(1, Ordering::SeqCst);
+            })
+        },
+    );
+
+    assert!(events.load(Ordering::SeqCst) > 0);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn congestion_event_callback_reports_ecn_on_ce_marking_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::CongestionCause;
+    use std::time::Duration;
+
+    // The congestion window is only reduced by the loss, but all packets are marked with CE.
+    let link = LinkModel {
+        loss_rate: 0.1,
+        ce_mark_rate: 1.0,
+        rtt: Duration::from_millis(20),
+        jitter: Duration::from_millis(5),
+        bandwidth: Some(10 * 1024 * 1024),
+    };
+
+    let mut config = get_test_config();
+    config.enable_ecn_marks();
+
+    let events = Arc::new(AtomicUsize::new(0));
+    let events2 = events.clone();
+
+    client_with_config_and_server_exchange_data_over_test_transport(
+        link,
+        config,
+        (0..256 * 1024).map(|i| i as u8).collect(),
+        move |con| {
+            con.set_congestion_event_callback(move |event| {
+                assert_eq!(CongestionCause::Ecn, event.cause);
+                events2.fetch_add(1, Ordering::SeqCst);
+            })
+        },
+    );
+
+    assert!(events.load(Ordering::SeqCst) > 0);
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn rtt_change_callback_is_called_when_queues_build_up_on_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use std::time::Duration;
+
+    let link = LinkModel {
+        loss_rate: 0.0,
+        rtt: Duration::from_millis(20),
+        jitter: Duration::from_millis(0),
+        bandwidth: Some(1024 * 1024),
+        ..Default::default()
+    };
+
+    let changes = Arc::new(AtomicUsize::new(0));
+    let changes2 = changes.clone();
+
+    client_and_server_exchange_data_over_test_transport(
+        link,
+        (0..256 * 1024).map(|i| i as u8).collect(),
+        move |con| {
+            con.set_rtt_change_callback(Duration::from_millis(5), move |_| {
+                changes2.fetch_add(1, Ordering::SeqCst);
+            })
+        },
+    );
+
+    assert!(changes.load(Ordering::SeqCst) > 0);
+}
+
+#[test]
+fn custom_random_source_is_used_for_the_handshake() {
+    static RANDOM_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    picoquic::set_random_source(|buf| {
+        RANDOM_CALLS.fetch_add(1, Ordering::SeqCst);
+        openssl::rand::rand_bytes(buf).expect("fills buffer with random bytes");
+    });
+
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let calls_before = RANDOM_CALLS.load(Ordering::SeqCst);
+
+    evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(RANDOM_CALLS.load(Ordering::SeqCst) > calls_before);
+}
+
+#[test]
+fn paused_stream_blocks_peer_until_resumed() {
+    let (send, recv) = unbounded();
+    let data_len = 512 * 1024;
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            let events = c.events();
+
+            h.spawn(
+                c.into_future()
+                    .map_err(|(e, _)| e)
+                    .and_then(move |(stream, c)| {
+                        let stream = stream.expect("receives stream");
+                        let id = stream.id();
+
+                        c.pause_stream(id)
+                            .and_then(move |_| {
+                                // The peer only gets blocked, if no new credit is granted.
+                                events
+                                    .filter(move |e| *e == Event::PeerStreamDataBlocked(id))
+                                    .into_future()
+                                    .map_err(|(e, _)| e)
+                            })
+                            .and_then(move |_| c.resume_stream(id).map(move |_| c))
+                            .and_then(move |c| {
+                                stream
+                                    .fold(0, |len, data| Ok::<_, picoquic::Error>(len + data.len()))
+                                    .map(move |len| (len, c))
+                            })
+                    })
+                    .map(move |(len, _)| {
+                        let _ = send.unbounded_send(len);
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let err = evt_loop
+        .run(con.pause_stream(1000))
+        .expect_err("pausing an unknown stream fails");
+    assert!(match err.kind() {
+        ErrorKind::UnknownStream(1000) => true,
+        _ => false,
+    });
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from(vec![0; data_len])))
+        .unwrap();
+
+    // Finishes the `Stream` with a `FIN`, after all data was sent.
+    drop(stream);
+
+    let (len, _) = evt_loop.run(recv.into_future()).unwrap();
+    assert_eq!(Some(data_len), len);
+}
+
+#[test]
+fn second_connection_resumes_the_tls_session() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+
+            // The session is known to be resumed, when the handshake finished.
+            h.spawn(
+                c.events()
+                    .filter(|e| *e == Event::HandshakeConfirmed)
+                    .into_future()
+                    .map(move |_| {
+                        let _ = send.send(c.resumed());
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(!con.resumed());
+
+    // Receive the session ticket of the server.
+    for _ in 0..20 {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    let resumed_con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(resumed_con.resumed());
+
+    assert_eq!(Ok(false), recv.recv());
+    assert_eq!(Ok(true), recv.recv());
+}
+
+#[test]
+fn amplification_limit_is_lifted_after_the_handshake() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+
+            h.spawn(
+                c.events()
+                    .filter(|e| *e == Event::HandshakeConfirmed)
+                    .into_future()
+                    .map(move |_| {
+                        let _ = send.send((c.amplification_budget(), c.is_amplification_limited()));
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    // The amplification limit only applies to servers.
+    assert_eq!(None, con.amplification_budget());
+    assert!(!con.is_amplification_limited());
+
+    // The finished handshake validated the address of the client.
+    assert_eq!(Ok((None, false)), recv.recv());
+}
+
+#[test]
+fn client_sees_grease_quic_bit_of_server() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.enable_grease_quic_bit();
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(con.peer_extensions().grease_quic_bit);
+
+    // The greased QUIC bit must not break the connection.
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .expect("sends data");
+    let (answer, _) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .expect("receives answer");
+    assert_eq!(Some(BytesMut::from("hello server")), answer);
+}
+
+#[test]
+fn peer_blocked_by_connection_flow_control_is_reported() {
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_max_recv_buffer(16 * 1024, OverflowPolicy::Backpressure);
+            config
+        },
+        move |c, h| {
+            c.for_each(move |mut c| {
+                let send = send.clone();
+                let mut streams = Vec::new();
+
+                h.spawn(
+                    c.events()
+                        .filter(|e| *e == Event::PeerDataBlocked)
+                        .into_future()
+                        .map(move |_| {
+                            let _ = send.unbounded_send(());
+                        })
+                        .map_err(|_| ()),
+                );
+
+                // The `Stream`s are never read, so the peer gets no new credit.
+                h.spawn(futures::future::poll_fn(move || loop {
+                    match c.poll() {
+                        Ok(futures::Async::Ready(Some(stream))) => streams.push(stream),
+                        Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                        _ => return Ok(futures::Async::Ready(())),
+                    }
+                }));
+
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    // The data is spread over multiple `Stream`s, to exceed the connection level flow control
+    // before the flow control of a single `Stream`.
+    let mut streams = Vec::new();
+    for _ in 0..8 {
+        let stream = evt_loop
+            .run(con.new_bidirectional_stream())
+            .expect("creates stream");
+
+        let mut remaining = 64 * 1024;
+        con.set_stream_data_provider(stream.id(), move |buf| {
+            let len = buf.len().min(remaining);
+            remaining -= len;
+            (len, remaining == 0)
+        });
+        streams.push(stream);
+    }
+
+    evt_loop.handle().spawn(
+        con.for_each(move |_| {
+            let _ = &streams;
+            Ok(())
+        })
+        .map_err(|_| ()),
+    );
+
+    let (blocked, _) = evt_loop.run(recv.into_future()).unwrap();
+    assert_eq!(Some(()), blocked);
+}
+
+#[test]
+fn server_reports_new_stream_event_for_stream_without_data() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            let events = c.events();
+
+            h.spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+
+            events
+                .filter(|e| match e {
+                    Event::NewStream(_, _) => true,
+                    _ => false,
+                })
+                .into_future()
+                .map_err(|(e, _)| e)
+                .map(move |(e, _)| send.send(e).unwrap())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let id = stream.id();
+    stream.finish().expect("finishes stream");
+
+    for _ in 0..100 {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert_eq!(
+        Some(Event::NewStream(id, SType::Bidirectional)),
+        recv.recv_timeout(Duration::from_secs(1)).unwrap()
+    );
+}
+
+#[test]
+fn server_connection_is_draining_after_client_closed_it() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, _| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            let (read, write) = c.split();
+
+            read.for_each(|_| Ok(())).then(move |_| {
+                send.send(write.state()).unwrap();
+                Ok(())
+            })
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(ConnectionState::Ready, con.state());
+
+    con.close_immediately();
+
+    for _ in 0..50 {
+        evt_loop.turn(Some(Duration::from_millis(10)));
+    }
+
+    assert_eq!(
+        ConnectionState::Draining,
+        recv.recv_timeout(Duration::from_secs(1)).unwrap()
+    );
+}
+
+#[test]
+fn client_knows_max_udp_payload_size_advertised_by_server() {
+    let addr = start_server_thread_with_default_config(|c, _| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    // picoquic advertises a limit that is smaller than the default of the transport parameter.
+    let size = con.peer_max_udp_payload_size();
+    assert!(size >= 1200, "{} is below the minimum of QUIC", size);
+    assert!(
+        size < 65527,
+        "{} is the default of the transport parameter",
+        size
+    );
+}
+
+#[test]
+fn server_sees_idle_timeout_of_client_connection() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+
+            h.spawn(
+                c.events()
+                    .filter(|e| *e == Event::HandshakeConfirmed)
+                    .into_future()
+                    .map(move |_| {
+                        let _ = send.send(c.peer_idle_timeout());
+                    })
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let _con = evt_loop
+        .run(context.new_connection_with_idle_timeout(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            Duration::from_secs(7),
+        ))
+        .expect("creates connection");
+
+    assert_eq!(
+        Some(Duration::from_secs(7)),
+        recv.recv_timeout(Duration::from_secs(5))
+            .expect("receives idle timeout")
+    );
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn bbr_estimates_bandwidth_of_limited_test_transport() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::CongestionAlgorithm;
+
+    let bandwidth = 1024 * 1024;
+    let link = LinkModel {
+        rtt: Duration::from_millis(20),
+        bandwidth: Some(bandwidth),
+        ..Default::default()
+    };
+
+    let con = client_and_server_exchange_data_over_test_transport(
+        link,
+        (0..256 * 1024).map(|i| i as u8).collect(),
+        |con| con.set_congestion_algorithm(CongestionAlgorithm::Bbr),
+    );
+
+    let estimate = con.bandwidth_estimate();
+    assert!(estimate > 0);
+    // The estimate is in the order of the bandwidth of the link.
+    assert!(
+        estimate < 10 * bandwidth,
+        "estimate {} is too large",
+        estimate
+    );
+}
+
+#[test]
+fn server_knows_when_all_data_of_a_stream_was_received() {
+    use tokio_core::reactor::Timeout;
+
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread_with_default_config(move |c, h| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            let h2 = h.clone();
+
+            h.spawn(
+                c.into_future()
+                    .map_err(|_| ())
+                    .and_then(move |(stream, c)| {
+                        let stream = stream.expect("receives stream");
+                        let id = stream.id();
+
+                        // Wait for the `FIN` of the client, without reading the data.
+                        Timeout::new(Duration::from_millis(200), &h2)
+                            .unwrap()
+                            .map_err(|_| ())
+                            .and_then(move |_| {
+                                let _ = send.unbounded_send((
+                                    c.stream_recv_finished(id),
+                                    c.stream_all_received(id),
+                                ));
+
+                                stream
+                                    .fold(0, |len, data| Ok::<_, picoquic::Error>(len + data.len()))
+                                    .map_err(|_| ())
+                                    .map(move |_| (c, send, id, h2))
+                            })
+                    })
+                    .and_then(|(c, send, id, h2)| {
+                        // Subscribing to the events wakes up the `Connection`, so it notices
+                        // that the data was read.
+                        let _events = c.events();
+
+                        Timeout::new(Duration::from_millis(100), &h2)
+                            .unwrap()
+                            .map_err(|_| ())
+                            .map(move |_| {
+                                let _ = send.unbounded_send((
+                                    c.stream_recv_finished(id),
+                                    c.stream_all_received(id),
+                                ));
+                            })
+                    }),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+    stream.finish().expect("finishes stream");
+
+    // The `Stream` is kept open for receiving, so the server keeps tracking it.
+    let states = evt_loop.run(recv.take(2).collect()).unwrap();
+    assert_eq!(vec![(true, false), (true, true)], states);
+    drop(stream);
+}
+
+#[test]
+fn client_knows_extensions_advertised_by_server() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.disable_migration();
+            config.enable_grease_quic_bit();
+            config.enable_ack_frequency(Duration::from_millis(1));
+            // The largest valid exponent.
+            config.set_ack_delay_exponent(20);
+            config.set_max_ack_ranges(4);
+            config
+        },
+        |c, _| c.for_each(|_| Ok(())),
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let extensions = con.peer_extensions();
+    assert!(extensions.migration_disabled);
+    assert!(extensions.grease_quic_bit);
+    assert_eq!(Some(Duration::from_millis(1)), extensions.min_ack_delay);
+    assert!(!extensions.multipath);
+    assert_eq!(20, con.peer_ack_delay_exponent());
+}
+
+#[test]
+fn client_knows_initiator_of_streams_and_can_open_streams() {
+    let addr = start_server_thread_with_default_config(|c, h| {
+        c.for_each(move |mut c| {
+            h.spawn(
+                c.new_bidirectional_stream()
+                    .and_then(|s| s.send(BytesMut::from("hello client")))
+                    .and_then(move |s| c.for_each(|_| Ok(())).map(move |_| drop(s)))
+                    .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(con.can_open_stream(SType::Bidirectional));
+    assert!(con.can_open_stream(SType::Unidirectional));
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    assert!(!con.is_peer_initiated(stream.id()));
+
+    let (peer_stream, con) = evt_loop
+        .run(con.into_future().map_err(|(e, _)| e))
+        .expect("receives stream");
+    assert!(con.is_peer_initiated(peer_stream.expect("stream from server").id()));
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn connection_label_is_reported_in_quic_events() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::QuicEvent;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let labels = Rc::new(RefCell::new(Vec::new()));
+    let labels2 = labels.clone();
+    let mut config = get_test_config();
+    config.set_event_sink(move |event| {
+        if let QuicEvent::PacketSent { label, .. } = event {
+            labels2.borrow_mut().push(label);
+        }
+    });
+
+    client_with_config_and_server_exchange_data_over_test_transport(
+        LinkModel {
+            rtt: Duration::from_millis(10),
+            ..Default::default()
+        },
+        config,
+        (0..16 * 1024).map(|i| i as u8).collect(),
+        |con| con.set_label("user-42"),
+    );
+
+    assert!(labels.borrow().contains(&Some("user-42".to_owned())));
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn only_lost_handshake_packets_are_counted_as_handshake_retransmissions() {
+    use picoquic::test_transport::LinkModel;
+    use picoquic::{PacketType, QuicEvent};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let link = LinkModel {
+        loss_rate: 0.5,
+        rtt: Duration::from_millis(10),
+        ..Default::default()
+    };
+
+    let losses = Rc::new(RefCell::new(Vec::new()));
+    let losses2 = losses.clone();
+    let mut config = get_test_config();
+    config.set_event_sink(move |event| {
+        if let QuicEvent::PacketLost { packet_type, .. } = event {
+            losses2.borrow_mut().push(packet_type);
+        }
+    });
+
+    let con = client_with_config_and_server_exchange_data_over_test_transport(
+        link,
+        config,
+        (0..16 * 1024).map(|i| i as u8).collect(),
+        |_| {},
+    );
+
+    let losses = losses.borrow();
+    let handshake_losses = losses
+        .iter()
+        .filter(|t| **t == PacketType::Initial || **t == PacketType::Handshake)
+        .count();
+
+    // Half of the packets are lost, so the handshake and the data need retransmissions.
+    assert!(handshake_losses > 0);
+    assert!(losses.contains(&PacketType::OneRtt));
+    // The data is sent in 1-RTT packets, their losses are not counted.
+    assert_eq!(handshake_losses as u32, con.handshake_retransmit_count());
+}
+
+#[cfg(feature = "test-transport")]
+#[test]
+fn sent_packets_are_in_flight_before_they_are_acknowledged() {
+    use picoquic::test_transport::{LinkModel, Network};
+    use tokio_core::reactor::Timeout;
+
+    let network = Network::with_link_model(LinkModel {
+        rtt: Duration::from_millis(400),
+        ..Default::default()
+    });
+
+    let mut evt_loop = Core::new().expect("creates event loop");
+    let handle = evt_loop.handle();
+
+    let server = Context::new_with_test_transport(
+        &([127, 0, 0, 1], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates server context");
+    let server_addr = server.local_addr();
+
+    let h = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |c| {
+                let h = h.clone();
+
+                h.clone().spawn(
+                    c.for_each(move |s| {
+                        h.spawn(s.for_each(|_| Ok(())).map_err(|_| ()));
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+                );
+
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let mut client = Context::new_with_test_transport(
+        &([127, 0, 0, 2], 0).into(),
+        &network,
+        &handle,
+        get_test_config(),
+    )
+    .expect("creates client context");
+
+    let mut con = evt_loop
+        .run(client.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let _stream = evt_loop
+        .run(stream.send(BytesMut::from(&vec![1; 32 * 1024][..])))
+        .unwrap();
+
+    // The acknowledgements of the sent packets arrive after one round trip time.
+    let timeout = Timeout::new(Duration::from_millis(100), &handle).unwrap();
+    evt_loop.run(timeout).unwrap();
+    assert!(con.packets_in_flight() > 0);
+}
+
+#[test]
+fn send_ack_now_sends_an_ack_frame() {
+    use tokio_core::reactor::Timeout;
+
+    // The frame type of an `ACK` frame.
+    const ACK: u64 = 0x02;
+
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let mut config = get_test_config();
+    config.enable_frame_stats();
+    config.set_max_ack_delay(Duration::from_secs(1));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .run(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .run(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .run(stream.send(BytesMut::from("hello server")))
+        .unwrap();
+    let (_, stream) = evt_loop
+        .run(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    // Nothing is sent on the idle connection, without a request for an acknowledgement.
+    let handle = evt_loop.handle();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(200), &handle).unwrap())
+        .unwrap();
+    let acks = con.frame_stats().sent(ACK);
+
+    con.send_ack_now();
+    evt_loop
+        .run(Timeout::new(Duration::from_millis(100), &handle).unwrap())
+        .unwrap();
+
+    assert!(con.frame_stats().sent(ACK) > acks);
+    drop(stream);
+}